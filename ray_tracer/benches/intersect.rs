@@ -0,0 +1,152 @@
+// Compares the scalar HitList against the SIMD SphereSoA and the Bvh on the
+// random scene, with coherent camera rays and incoherent random rays, and
+// measures the rounding error of hit points on the ground sphere. Then times
+// refitting and rebuilding the top level hierarchy of many moving instances.
+// Run with `cargo bench --bench intersect [--features f64]`.
+use ray_tracer::core::cameras::PerspectiveCamera;
+use ray_tracer::core::{
+    random_spheres, Bvh, Camera, HitList, HitRecord, Hitable, Instance, SphereObject, SphereSoA,
+    Tlas,
+};
+use ray_tracer::geometry::{
+    normalize, random, random_in_unit_sphere, seed_random, Float, Ray, Transform, Vec3,
+};
+use std::sync::Arc;
+use std::time::Instant;
+
+const N_RAYS: usize = 200_000;
+
+fn camera_rays() -> Vec<Ray> {
+    let camera = PerspectiveCamera::new(
+        Vec3::new(12.0, 1.2, 4.0),
+        Vec3::new(2.0, 1.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        30.0,
+        2.0,
+        0.0,
+        10.0,
+    );
+    (0..N_RAYS)
+        .filter_map(|_| camera.ray(random(), random()))
+        .collect()
+}
+
+fn random_rays() -> Vec<Ray> {
+    (0..N_RAYS)
+        .map(|_| {
+            let o = Vec3::new(
+                24.0 * random() - 12.0,
+                3.0 * random(),
+                24.0 * random() - 12.0,
+            );
+            Ray::new(o, random_in_unit_sphere())
+        })
+        .collect()
+}
+
+// Returns million rays per second and the number of hits.
+fn bench(world: &dyn Hitable, rays: &[Ray]) -> (f64, usize) {
+    let start = Instant::now();
+    let mut hits = 0;
+    for r in rays {
+        let mut rec = HitRecord::new();
+        if world.hit(r, 0.001, Float::MAX, &mut rec) {
+            hits += 1;
+        }
+    }
+    (
+        rays.len() as f64 / start.elapsed().as_secs_f64() / 1e6,
+        hits,
+    )
+}
+
+// Largest error bound of hit points on a sphere, and the number of rays
+// bounced off it that hit it again.
+fn hit_point_error(world: &dyn Hitable, rays: &[Ray]) -> (Float, usize) {
+    let (mut max, mut again) = (0.0, 0);
+    for r in rays {
+        let mut rec = HitRecord::new();
+        if world.hit(r, 0.0, Float::MAX, &mut rec) {
+            max = rec.p_error.length().max(max);
+            let d = rec.normal + 0.5 * normalize(random_in_unit_sphere());
+            if world.hit(&rec.spawn_ray(d), 0.0, Float::MAX, &mut HitRecord::new()) {
+                again += 1;
+            }
+        }
+    }
+    (max, again)
+}
+
+fn main() {
+    seed_random(7);
+    let spheres = random_spheres();
+    let mut list = HitList::new();
+    for s in spheres.iter() {
+        list.list.push(Box::new(s.clone()));
+    }
+    let soa = SphereSoA::new(&spheres);
+    let bvh = Bvh::new(spheres.clone());
+    println!(
+        "{} spheres, {} bit floats",
+        spheres.len(),
+        8 * std::mem::size_of::<Float>()
+    );
+    for (name, rays) in [("camera", camera_rays()), ("random", random_rays())] {
+        let (scalar, scalar_hits) = bench(&list, &rays);
+        let (simd, simd_hits) = bench(&soa, &rays);
+        let (tree, tree_hits) = bench(&bvh, &rays);
+        assert_eq!(scalar_hits, simd_hits);
+        assert_eq!(scalar_hits, tree_hits);
+        println!(
+            "{:>6} rays: scalar {:8.3} Mrays/s, simd {:8.3} Mrays/s ({:.2}x), bvh {:8.3} Mrays/s ({:.2}x)",
+            name,
+            scalar,
+            simd,
+            simd / scalar,
+            tree,
+            tree / scalar
+        );
+    }
+    let (error, again) = hit_point_error(&spheres[0], &camera_rays());
+    println!(
+        "ground sphere hit point error: {:e}, self intersections: {}",
+        error, again
+    );
+    instances(&spheres);
+}
+
+// A grid of instances of the small spheres, moved as an animation would.
+fn instances(spheres: &[SphereObject]) {
+    const N: usize = 100;
+    let blas: Arc<dyn Hitable> = Arc::new(Bvh::new(spheres[1..].to_vec()));
+    let place = |i: usize, time: Float| {
+        let (x, z) = ((i % N) as Float, (i / N) as Float);
+        Transform::translate(Vec3::new(30.0 * x, (x + z + time).sin(), 30.0 * z))
+            * Transform::rotate(10.0 * time, Vec3::new(0.0, 1.0, 0.0))
+    };
+    let mut tlas = Tlas::new(
+        (0..N * N)
+            .map(|i| Instance::new(blas.clone(), place(i, 0.0)))
+            .collect(),
+    );
+    let ms = |start: Instant| start.elapsed().as_secs_f64() * 1e3;
+    let (mut refit, mut rebuild) = (0.0, 0.0);
+    for frame in 1..=10 {
+        for i in 0..tlas.len() {
+            tlas.set_transform(i, place(i, frame as Float));
+        }
+        let start = Instant::now();
+        tlas.refit();
+        refit += ms(start) / 10.0;
+        let start = Instant::now();
+        tlas.rebuild();
+        rebuild += ms(start) / 10.0;
+    }
+    println!(
+        "{} instances of {} spheres: refit {:.3} ms, rebuild {:.3} ms",
+        tlas.len(),
+        spheres.len() - 1,
+        refit,
+        rebuild
+    );
+}
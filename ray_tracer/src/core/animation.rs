@@ -0,0 +1,125 @@
+use crate::geometry::Float;
+
+// How a parameter moves from one keyframe to the next: at constant speed,
+// easing in and out of both keys, or on a Catmull-Rom spline through all
+// the keys, which keeps the speed continuous across them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    SmoothStep,
+    CatmullRom,
+}
+
+// Values of one parameter, a number or a vector, at keyframes. Before the
+// first key and after the last the parameter holds still.
+#[derive(Debug, Clone, Default)]
+pub struct Track {
+    // frame, value and the interpolation to the next key, sorted by frame
+    keys: Vec<(Float, Vec<Float>, Interpolation)>,
+}
+impl Track {
+    pub fn new() -> Self {
+        Self { keys: vec![] }
+    }
+    pub fn insert(
+        &mut self,
+        frame: Float,
+        value: Vec<Float>,
+        interpolation: Interpolation,
+    ) -> Result<(), String> {
+        if self.keys.iter().any(|k| k.1.len() != value.len()) {
+            return Err("keyframe values differ in length".to_string());
+        }
+        let i = self.keys.partition_point(|k| k.0 < frame);
+        match self.keys.get_mut(i) {
+            Some(k) if k.0 == frame => *k = (frame, value, interpolation),
+            _ => self.keys.insert(i, (frame, value, interpolation)),
+        }
+        Ok(())
+    }
+    pub fn at(&self, frame: Float) -> Vec<Float> {
+        let n = self.keys.len();
+        let i = self.keys.partition_point(|k| k.0 <= frame);
+        if i == 0 {
+            return self.keys.first().map_or(vec![], |k| k.1.clone());
+        }
+        if i == n {
+            return self.keys[n - 1].1.clone();
+        }
+        let ((f1, p1, interpolation), (f2, p2, _)) = (&self.keys[i - 1], &self.keys[i]);
+        let t = (frame - f1) / (f2 - f1);
+        match interpolation {
+            Interpolation::Linear => lerp(p1, p2, t),
+            Interpolation::SmoothStep => lerp(p1, p2, t * t * (3.0 - 2.0 * t)),
+            Interpolation::CatmullRom => {
+                // tangents per frame from the neighbouring keys, one sided at
+                // the ends, scaled to the segment
+                let tangent = |j: usize| {
+                    let (a, b) = (j.saturating_sub(1), (j + 1).min(n - 1));
+                    let (fa, pa) = (self.keys[a].0, &self.keys[a].1);
+                    let (fb, pb) = (self.keys[b].0, &self.keys[b].1);
+                    pa.iter()
+                        .zip(pb)
+                        .map(|(a, b)| (b - a) / (fb - fa) * (f2 - f1))
+                        .collect::<Vec<_>>()
+                };
+                let (m1, m2) = (tangent(i - 1), tangent(i));
+                let (t2, t3) = (t * t, t * t * t);
+                let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+                let h10 = t3 - 2.0 * t2 + t;
+                let h01 = -2.0 * t3 + 3.0 * t2;
+                let h11 = t3 - t2;
+                (0..p1.len())
+                    .map(|c| h00 * p1[c] + h10 * m1[c] + h01 * p2[c] + h11 * m2[c])
+                    .collect()
+            }
+        }
+    }
+}
+
+fn lerp(a: &[Float], b: &[Float], t: Float) -> Vec<Float> {
+    a.iter().zip(b).map(|(a, b)| a + t * (b - a)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{Interpolation, Track};
+    use crate::geometry::Float;
+    fn track(interpolation: Interpolation) -> Track {
+        let mut track = Track::new();
+        for &(frame, x) in &[(10.0, 2.0), (0.0, 0.0), (20.0, 4.0), (40.0, 0.0)] {
+            track.insert(frame, vec![x, 1.0], interpolation).unwrap();
+        }
+        track
+    }
+    fn close(a: &[Float], b: &[Float]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-4)
+    }
+    #[test]
+    fn interpolates_between_keyframes() {
+        let linear = track(Interpolation::Linear);
+        assert!(close(&linear.at(-5.0), &[0.0, 1.0]));
+        assert!(close(&linear.at(5.0), &[1.0, 1.0]));
+        assert!(close(&linear.at(30.0), &[2.0, 1.0]));
+        assert!(close(&linear.at(50.0), &[0.0, 1.0]));
+        let smooth = track(Interpolation::SmoothStep);
+        assert!(close(&smooth.at(5.0), &[1.0, 1.0]));
+        assert!(smooth.at(1.0)[0] < linear.at(1.0)[0]);
+        assert!(smooth.at(9.0)[0] > linear.at(9.0)[0]);
+        let spline = track(Interpolation::CatmullRom);
+        for &(frame, x) in &[(0.0, 0.0), (10.0, 2.0), (20.0, 4.0), (40.0, 0.0)] {
+            assert!(close(&spline.at(frame), &[x, 1.0]));
+        }
+        // keys on a line are followed at constant speed
+        assert!(close(&spline.at(7.0), &[1.4, 1.0]));
+        // and corners of the linear path are rounded off
+        assert!(spline.at(18.0)[0] > linear.at(18.0)[0]);
+        let mut mismatched = Track::new();
+        mismatched
+            .insert(0.0, vec![1.0], Interpolation::Linear)
+            .unwrap();
+        assert!(mismatched
+            .insert(1.0, vec![1.0, 2.0], Interpolation::Linear)
+            .is_err());
+    }
+}
@@ -0,0 +1,538 @@
+use crate::core::integrator::sky;
+use crate::core::stats;
+use crate::core::{splat, HitRecord, Hitable, Integrator, Scene};
+use crate::geometry::{dot, normalize, random, Float, Ray, Vec3};
+
+// Bidirectional path tracing. A subpath traced from the camera and one
+// traced from a light, chosen by power, are connected in every way that
+// makes a path of at most max_depth bounces, and each connection is weighted
+// by the power heuristic against the other ways of sampling the same path.
+// Connections through the camera alone are splatted to the film. The
+// interior media of dielectrics are ignored.
+pub struct BdptIntegrator {
+    max_depth: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Camera,
+    Light(usize),
+    Surface,
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind: Kind,
+    p: Vec3,
+    // geometric normal, zero for points and for scattering in volumes
+    n: Vec3,
+    rec: HitRecord,
+    // throughput of the subpath up to here over its density
+    beta: Vec3,
+    // scattered specularly
+    delta: bool,
+    // area densities of sampling this vertex from the previous one and,
+    // going the other way, from the next one
+    pdf_fwd: Float,
+    pdf_rev: Float,
+}
+
+impl Vertex {
+    fn new(kind: Kind, p: Vec3, n: Vec3, beta: Vec3) -> Self {
+        Self {
+            kind,
+            p,
+            n,
+            rec: HitRecord::new(),
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+    fn surface(rec: HitRecord, beta: Vec3) -> Self {
+        let volumetric = rec.mat.as_ref().is_some_and(|m| m.is_volumetric());
+        let n = if volumetric {
+            Vec3::new(0.0, 0.0, 0.0)
+        } else {
+            rec.normal
+        };
+        let mut v = Self::new(Kind::Surface, rec.p, n, beta);
+        v.rec = rec;
+        v
+    }
+    fn light(&self) -> Option<usize> {
+        match self.kind {
+            Kind::Light(i) => Some(i),
+            Kind::Surface => self.rec.mat.as_ref().and_then(|m| m.light_index()),
+            Kind::Camera => None,
+        }
+    }
+    // Whether w leaves on the side the path is on.
+    fn consistent(&self, w: &Vec3) -> bool {
+        self.n == Vec3::new(0.0, 0.0, 0.0) || self.rec.is_consistent(w)
+    }
+    // Scattering (or emission) times the cosine towards the unit direction
+    // w, for a path arriving from prev.
+    fn f(&self, scene: &Scene, prev: &Vec3, w: &Vec3) -> Vec3 {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        match self.kind {
+            Kind::Surface => match self.rec.mat {
+                Some(ref mat) => mat
+                    .eval(&Ray::new(*prev, self.p - *prev), &self.rec, w)
+                    .unwrap_or(black),
+                None => black,
+            },
+            Kind::Light(i) if self.n == black => scene.lights[i].le(&self.n, w),
+            Kind::Light(i) => dot(&self.n, w).abs() * scene.lights[i].le(&self.n, w),
+            Kind::Camera => black,
+        }
+    }
+    // Materials shaded with normals other than the geometric one are not
+    // symmetric, so light carried along light subpaths is scaled by
+    // |wo.ns| |wi.ng| / (|wo.ng| |wi.ns|) when it arrives from prev and leaves
+    // in the unit direction wi.
+    fn adjoint(&self, prev: &Vec3, wi: &Vec3) -> Float {
+        let mat = match (self.kind, &self.rec.mat) {
+            (Kind::Surface, Some(mat)) if self.n != Vec3::new(0.0, 0.0, 0.0) => mat,
+            _ => return 1.0,
+        };
+        let wo = normalize(*prev - self.p);
+        let ns = mat.shading_normal(&Ray::new(*prev, self.p - *prev), &self.rec);
+        let denominator = dot(&wo, &self.n).abs() * dot(wi, &ns).abs();
+        if denominator == 0.0 {
+            return 0.0;
+        }
+        dot(&wo, &ns).abs() * dot(wi, &self.n).abs() / denominator
+    }
+    // Converts the solid angle density of sampling next from here into an
+    // area density at next.
+    fn to_area(&self, pdf: Float, next: &Vertex) -> Float {
+        let d = next.p - self.p;
+        let d2 = d.length2();
+        if d2 == 0.0 {
+            return 0.0;
+        }
+        if next.n == Vec3::new(0.0, 0.0, 0.0) {
+            return pdf / d2;
+        }
+        pdf * dot(&next.n, &d).abs() / (d2 * d2.sqrt())
+    }
+    // Area density of sampling next from here, for a path arriving from
+    // prev.
+    fn pdf(&self, scene: &Scene, prev: Option<&Vertex>, next: &Vertex) -> Float {
+        let w = normalize(next.p - self.p);
+        let pdf = match (self.kind, prev, &self.rec.mat) {
+            (Kind::Light(i), _, _) => scene.lights[i].pdf_le(&self.n, &w).1,
+            (Kind::Camera, _, _) => scene.camera.pdf_we(&Ray::new(self.p, w)).1,
+            (Kind::Surface, Some(prev), Some(mat)) => {
+                mat.pdf(&Ray::new(prev.p, self.p - prev.p), &self.rec, &w)
+            }
+            _ => 0.0,
+        };
+        self.to_area(pdf, next)
+    }
+    // Area density of the light here emitting towards next.
+    fn pdf_light(&self, scene: &Scene, next: &Vertex) -> Float {
+        match self.light() {
+            Some(i) => {
+                let w = normalize(next.p - self.p);
+                self.to_area(scene.lights[i].pdf_le(&self.n, &w).1, next)
+            }
+            None => 0.0,
+        }
+    }
+    // Area density of a light path starting here.
+    fn pdf_light_origin(&self, scene: &Scene, next: &Vertex) -> Float {
+        match self.light() {
+            Some(i) => {
+                let w = normalize(next.p - self.p);
+                let pdf_pos = scene.lights[i].pdf_le(&self.n, &w).0;
+                scene.light_sampler.pmf_power(i) * pdf_pos
+            }
+            None => 0.0,
+        }
+    }
+}
+
+impl BdptIntegrator {
+    pub fn new(max_depth: usize) -> Self {
+        Self { max_depth }
+    }
+    // Extends path up to `vertices` vertices, following r that left its last
+    // vertex with the solid angle density pdf. Returns the sky seen by
+    // camera paths that escape.
+    fn walk(
+        &self,
+        scene: &Scene,
+        r: Ray,
+        beta: Vec3,
+        pdf: Float,
+        vertices: usize,
+        path: &mut Vec<Vertex>,
+    ) -> Vec3 {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        let camera = matches!(path[0].kind, Kind::Camera);
+        let world: &dyn Hitable = &scene.world;
+        let (mut r, mut beta, mut pdf) = (r, beta, pdf);
+        while path.len() < vertices {
+            if !camera || path.len() > 1 {
+                stats::count(stats::Counter::SecondaryRays, 1);
+            }
+            let mut rec = HitRecord::new();
+            if !world.hit(&r, 0.0, Float::INFINITY, &mut rec) {
+                return if camera { beta * sky(scene, &r) } else { black };
+            }
+            stats::count(stats::Counter::PathVertices, 1);
+            let mat = match rec.mat.clone() {
+                Some(mat) => mat,
+                None => break,
+            };
+            let n = path.len();
+            let mut v = Vertex::surface(rec, beta);
+            v.pdf_fwd = path[n - 1].to_area(pdf, &v);
+            path.push(v);
+            if path.len() == vertices {
+                break;
+            }
+            let rec = &path[n].rec;
+            let mut scattered = Ray::new(black, black);
+            let mut attenuation = black;
+            if !mat.scatter(&r, rec, &mut attenuation, &mut scattered)
+                || !path[n].consistent(&scattered.d)
+            {
+                break;
+            }
+            let wi = normalize(scattered.d);
+            pdf = mat.pdf(&r, rec, &wi);
+            let rev = if pdf > 0.0 {
+                let back = normalize(path[n - 1].p - rec.p);
+                mat.pdf(&Ray::new(rec.p + wi, -wi), rec, &back)
+            } else {
+                0.0
+            };
+            path[n].delta = pdf == 0.0;
+            path[n - 1].pdf_rev = path[n].to_area(rev, &path[n - 1]);
+            beta = beta * attenuation;
+            if !camera {
+                beta = path[n].adjoint(&path[n - 1].p, &wi) * beta;
+            }
+            r = scattered;
+        }
+        black
+    }
+    // Light of the path made of the first s light and t camera vertices,
+    // splatted instead for t = 1.
+    fn connect(
+        &self,
+        scene: &Scene,
+        light: &[Vertex],
+        camera: &[Vertex],
+        (s, t): (usize, usize),
+        reachable: bool,
+    ) -> Vec3 {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        let world: &dyn Hitable = &scene.world;
+        let pt = &camera[t - 1];
+        if s == 0 {
+            // the camera path found a light by itself
+            let prev = &camera[t - 2];
+            let le = match pt.rec.mat {
+                Some(ref mat) => mat.emitted(&Ray::new(prev.p, pt.p - prev.p), &pt.rec),
+                None => black,
+            };
+            if le == black {
+                return black;
+            }
+            let weight = match pt.light() {
+                Some(_) => mis_weight(scene, light, camera, None, (s, t), reachable),
+                // emitters that are not lights can not be sampled otherwise
+                None => 1.0,
+            };
+            return weight * pt.beta * le;
+        }
+        if t == 1 {
+            let qs = &light[s - 1];
+            if qs.delta {
+                return black;
+            }
+            let c = match scene.camera.sample_wi(&qs.p) {
+                Some(c) if c.pdf > 0.0 && c.importance > 0.0 => c,
+                _ => return black,
+            };
+            if qs.kind == Kind::Surface && !qs.consistent(&c.wi) {
+                return black;
+            }
+            let prev = if s > 1 { light[s - 2].p } else { qs.p };
+            let f = qs.adjoint(&prev, &c.wi) * qs.f(scene, &prev, &c.wi);
+            if f == black {
+                return black;
+            }
+            stats::count(stats::Counter::ShadowRays, 1);
+            let ray = match qs.kind {
+                Kind::Surface => qs.rec.spawn_ray(c.wi),
+                _ => Ray::new(qs.p, c.wi),
+            };
+            let tr = world.transmittance(&ray, 0.0, c.distance);
+            let we = c.importance / c.pdf;
+            let sampled = Vertex::new(Kind::Camera, c.lens, black, Vec3::new(we, we, we));
+            let weight = mis_weight(scene, light, camera, Some(&sampled), (s, t), reachable);
+            splat(c.s, c.t, (weight * tr * we) * qs.beta * f);
+            return black;
+        }
+        if pt.delta {
+            return black;
+        }
+        let prev = &camera[t - 2];
+        if s == 1 {
+            let (i, pmf) = match scene.light_sampler.sample_power(random()) {
+                Some(choice) => choice,
+                None => return black,
+            };
+            let l = &scene.lights[i];
+            let ls = match l.sample(&pt.p) {
+                Some(ls) if ls.pdf > 0.0 && pt.consistent(&ls.wi) => ls,
+                _ => return black,
+            };
+            let f = pt.f(scene, &prev.p, &ls.wi);
+            if f == black {
+                return black;
+            }
+            stats::count(stats::Counter::ShadowRays, 1);
+            // stopping short of the surface of area lights
+            let ray = pt.rec.spawn_ray(ls.wi);
+            let tr = world.transmittance(&ray, 0.0, (1.0 - 1e-3) * ls.distance);
+            let contribution = (tr / (pmf * ls.pdf)) * pt.beta * f * ls.radiance;
+            if l.bounds().is_none() {
+                // lights at infinity can only be sampled this way
+                return contribution;
+            }
+            let p = pt.p + ls.distance * ls.wi;
+            let mut sampled = Vertex::new(Kind::Light(i), p, ls.normal, black);
+            sampled.pdf_fwd = scene.light_sampler.pmf_power(i) * l.pdf_le(&ls.normal, &-ls.wi).0;
+            let weight = mis_weight(scene, light, camera, Some(&sampled), (s, t), reachable);
+            return weight * contribution;
+        }
+        let qs = &light[s - 1];
+        if qs.delta {
+            return black;
+        }
+        let d = qs.p - pt.p;
+        let distance = d.length();
+        let w = d / distance;
+        if !pt.consistent(&w) || !qs.consistent(&-w) {
+            return black;
+        }
+        let from = light[s - 2].p;
+        let f = pt.f(scene, &prev.p, &w) * (qs.adjoint(&from, &-w) * qs.f(scene, &from, &-w));
+        if f == black {
+            return black;
+        }
+        stats::count(stats::Counter::ShadowRays, 1);
+        let ray = pt.rec.spawn_ray(w);
+        let tr = world.transmittance(&ray, 0.0, (1.0 - 1e-3) * distance);
+        let weight = mis_weight(scene, light, camera, None, (s, t), reachable);
+        (weight * tr / (distance * distance)) * qs.beta * f * pt.beta
+    }
+}
+
+// Power heuristic weight of the (s, t) strategy, from the ratios of the
+// densities of the other strategies to its own, walking the path from the
+// connection towards either end. `sampled` replaces the light vertex for
+// s = 1 and the camera vertex for t = 1. Cameras without importance can not
+// be reached by light paths.
+fn mis_weight(
+    scene: &Scene,
+    light: &[Vertex],
+    camera: &[Vertex],
+    sampled: Option<&Vertex>,
+    (s, t): (usize, usize),
+    reachable: bool,
+) -> Float {
+    if s + t == 2 {
+        return 1.0;
+    }
+    let lv = |i: usize| match sampled {
+        Some(v) if s == 1 && i == 0 => v,
+        _ => &light[i],
+    };
+    let cv = |i: usize| match sampled {
+        Some(v) if t == 1 && i == 0 => v,
+        _ => &camera[i],
+    };
+    // (pdf_fwd, pdf_rev, delta) along both subpaths
+    let mut cam: Vec<_> = (0..t)
+        .map(|i| (cv(i).pdf_fwd, cv(i).pdf_rev, cv(i).delta))
+        .collect();
+    let mut lig: Vec<_> = (0..s)
+        .map(|i| (lv(i).pdf_fwd, lv(i).pdf_rev, lv(i).delta))
+        .collect();
+    let pt = cv(t - 1);
+    let pt_minus = if t > 1 { Some(cv(t - 2)) } else { None };
+    let qs = if s > 0 { Some(lv(s - 1)) } else { None };
+    let qs_minus = if s > 1 { Some(lv(s - 2)) } else { None };
+    // the connected vertices are never specular
+    cam[t - 1].2 = false;
+    cam[t - 1].1 = match qs {
+        Some(qs) => qs.pdf(scene, qs_minus, pt),
+        None => pt_minus.map_or(0.0, |m| pt.pdf_light_origin(scene, m)),
+    };
+    if let Some(pt_minus) = pt_minus {
+        cam[t - 2].1 = match qs {
+            Some(qs) => pt.pdf(scene, Some(qs), pt_minus),
+            None => pt.pdf_light(scene, pt_minus),
+        };
+    }
+    if let Some(qs) = qs {
+        lig[s - 1].2 = false;
+        lig[s - 1].1 = pt.pdf(scene, pt_minus, qs);
+        if let Some(qs_minus) = qs_minus {
+            lig[s - 2].1 = qs.pdf(scene, Some(pt), qs_minus);
+        }
+    }
+    // deltas have no density, and only cancel out
+    let remap = |x: Float| if x == 0.0 { 1.0 } else { x };
+    let mut sum = 0.0;
+    let mut ri = 1.0;
+    for i in (1..t).rev() {
+        ri *= remap(cam[i].1) / remap(cam[i].0);
+        if !cam[i].2 && !cam[i - 1].2 && (i > 1 || reachable) {
+            sum += ri * ri;
+        }
+    }
+    let mut ri = 1.0;
+    for i in (0..s).rev() {
+        ri *= remap(lig[i].1) / remap(lig[i].0);
+        let delta_light = match i {
+            0 => lv(0).light().is_some_and(|l| scene.lights[l].is_delta()),
+            _ => lig[i - 1].2,
+        };
+        if !lig[i].2 && !delta_light {
+            sum += ri * ri;
+        }
+    }
+    1.0 / (1.0 + sum)
+}
+
+impl Integrator for BdptIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene) -> Vec3 {
+        let one = Vec3::new(1.0, 1.0, 1.0);
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        let mut camera = vec![Vertex::new(Kind::Camera, r.o, black, one)];
+        let pdf_dir = scene.camera.pdf_we(r).1;
+        let reachable = pdf_dir > 0.0;
+        let vertices = self.max_depth + 2;
+        let mut l = self.walk(scene, *r, one, pdf_dir, vertices, &mut camera);
+        let mut light = vec![];
+        if let Some((i, pmf)) = scene.light_sampler.sample_power(random()) {
+            match scene.lights[i].sample_le() {
+                Some(e) if e.pdf_pos > 0.0 && e.pdf_dir > 0.0 => {
+                    let pdf_pos = pmf * e.pdf_pos;
+                    let mut v = Vertex::new(Kind::Light(i), e.ray.o, e.normal, one / pdf_pos);
+                    v.pdf_fwd = pdf_pos;
+                    let beta = v.f(scene, &e.ray.o, &normalize(e.ray.d)) / (pdf_pos * e.pdf_dir);
+                    light.push(v);
+                    self.walk(scene, e.ray, beta, e.pdf_dir, vertices - 1, &mut light);
+                }
+                _ => {}
+            }
+        }
+        for t in 1..=camera.len() {
+            for s in 0..=light.len() {
+                let depth = s + t;
+                if (s == 1 && t == 1) || depth < 2 || depth - 2 > self.max_depth {
+                    continue;
+                }
+                l += self.connect(scene, &light, &camera, (s, t), reachable);
+            }
+        }
+        l
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::bdpt::Vertex;
+    use crate::core::materials::{Lambertian, NormalMap, NormalMapped};
+    use crate::core::{BdptIntegrator, Integrator, PathIntegrator, Renderer, Scene};
+    use crate::core::{HitRecord, Hitable, SphereObject, Texture};
+    use crate::geometry::{dot, normalize, Float, Ray, Vec3};
+    use std::sync::Arc;
+    fn mean(scene: &Scene, integrator: &dyn Integrator, spp: u32) -> Vec3 {
+        let mut renderer = Renderer::new(scene.width, scene.height, 1);
+        renderer.threads = 2;
+        renderer.render_pass(&*scene.camera, spp, &|r| integrator.li(r, scene));
+        let image = renderer.film.image();
+        let n = image.pixels.len() as Float;
+        image
+            .pixels
+            .iter()
+            .fold(Vec3::new(0.0, 0.0, 0.0), |a, p| a + *p / n)
+    }
+    #[test]
+    fn matches_path_tracing() {
+        // a diffuse sphere, a mirror and a ground lit from out of frame by a
+        // sphere light and a point light
+        let scene = Scene::parse(
+            "image width=16 height=12\n\
+             camera look_from=0,1,6 look_at=0,0.5,0 fov=30 aperture=0.1\n\
+             sky intensity=0\n\
+             material lamp type=light emission=8,8,8\n\
+             material red albedo=0.7,0.2,0.2\n\
+             material grey albedo=0.5,0.5,0.5\n\
+             material metal type=metal albedo=0.8,0.8,0.8 fuzz=0\n\
+             sphere center=0,-1000,0 radius=1000 material=grey\n\
+             sphere center=-0.6,0.5,0 radius=0.5 material=red\n\
+             sphere center=0.6,0.5,0 radius=0.5 material=metal\n\
+             sphere center=0,3,1 radius=0.5 material=lamp\n\
+             light point position=-2,2,2 intensity=2,2,2\n",
+        )
+        .unwrap();
+        let path = mean(&scene, &PathIntegrator::new(5), 512);
+        let bdpt = mean(&scene, &BdptIntegrator::new(5), 512);
+        for c in 0..3 {
+            assert!(
+                (path[c] - bdpt[c]).abs() < 0.03 * path[c],
+                "{:?} {:?}",
+                path,
+                bdpt
+            );
+        }
+    }
+    #[test]
+    fn light_and_camera_subpaths_carry_the_same_light() {
+        // a diffuse sphere shaded with tilted normals
+        let albedo = Vec3::new(0.5, 0.5, 0.5);
+        let tilt = Texture::Constant(Vec3::new(0.9, 0.5, 0.7));
+        let mat = NormalMapped::new(Arc::new(Lambertian::new(albedo)), NormalMap::Tangent(tilt));
+        let sphere = SphereObject {
+            center: Vec3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            mat: Some(Arc::new(mat)),
+        };
+        let (a, b) = (Vec3::new(3.0, 2.0, 1.0), Vec3::new(2.0, 3.0, -1.0));
+        let mut rec = HitRecord::new();
+        assert!(sphere.hit(&Ray::new(a, -a), 0.0, Float::INFINITY, &mut rec));
+        let v = Vertex::surface(rec, Vec3::new(1.0, 1.0, 1.0));
+        let ns = v
+            .rec
+            .mat
+            .as_ref()
+            .unwrap()
+            .shading_normal(&Ray::new(a, -a), &v.rec);
+        assert!(dot(&ns, &v.n) < 0.99);
+        // light going from a to b scatters at v as much along the light
+        // subpath from a as along the camera subpath from b, up to the
+        // cosines at v of the geometry terms, which use the geometric normal
+        let (wa, wb) = (normalize(a - v.p), normalize(b - v.p));
+        let scene = Scene::parse("").unwrap();
+        let camera = v.f(&scene, &b, &wa) / dot(&wa, &v.n).abs();
+        let light = v.adjoint(&a, &wb) * v.f(&scene, &a, &wb) / dot(&wb, &v.n).abs();
+        assert!(
+            (camera - light).length() < 1e-5 * camera.length(),
+            "{:?} {:?}",
+            camera,
+            light
+        );
+    }
+}
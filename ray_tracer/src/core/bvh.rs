@@ -0,0 +1,518 @@
+use crate::core::stats::{count, Counter};
+use crate::core::{HitRecord, Hitable, Span};
+use crate::geometry::{normalize, Aabb, Float, Ray, Transform};
+use std::sync::Arc;
+
+const MAX_LEAF: usize = 4;
+const BINS: usize = 12;
+// cost of visiting a node, relative to testing a primitive
+const TRAVERSAL_COST: Float = 0.125;
+
+struct Node {
+    bounds: Aabb,
+    // first primitive of leaves in the order, or the second child of
+    // interior nodes, the first one following the node
+    index: usize,
+    // primitives of leaves, 0 for interior nodes
+    count: usize,
+    // axis interior nodes are split on, the second child above the first
+    axis: usize,
+}
+
+// Bounding volume hierarchy over primitives given by their bounds, split by
+// the surface area heuristic over binned centroids and stored depth first.
+// Refitting recomputes the bounds of the nodes and keeps the tree.
+pub(crate) struct Tree {
+    nodes: Vec<Node>,
+    order: Vec<usize>,
+}
+impl Tree {
+    pub fn new(bounds: &[Aabb]) -> Self {
+        let mut tree = Self {
+            nodes: vec![],
+            order: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            tree.build(bounds, 0, bounds.len());
+        }
+        tree
+    }
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|n| n.bounds)
+    }
+    fn build(&mut self, bounds: &[Aabb], start: usize, end: usize) {
+        let node = self.nodes.len();
+        let items = &mut self.order[start..end];
+        let n = items.len();
+        let total = items[1..]
+            .iter()
+            .fold(bounds[items[0]], |b, &i| b.union(&bounds[i]));
+        self.nodes.push(Node {
+            bounds: total,
+            index: start,
+            count: n,
+            axis: 0,
+        });
+        if n == 1 {
+            return;
+        }
+        let c = bounds[items[0]].centroid();
+        let centroids = items[1..].iter().fold(Aabb::new(c, c), |b, &i| {
+            let c = bounds[i].centroid();
+            b.union(&Aabb::new(c, c))
+        });
+        let extent = centroids.max - centroids.min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        let split = if extent[axis] > 0.0 {
+            let bin = |i: usize| {
+                let t = (bounds[i].centroid()[axis] - centroids.min[axis]) / extent[axis];
+                ((t * BINS as Float) as usize).min(BINS - 1)
+            };
+            let mut bins: [(Option<Aabb>, usize); BINS] = [(None, 0); BINS];
+            for &i in items.iter() {
+                let b = &mut bins[bin(i)];
+                b.0 = Some(b.0.map_or(bounds[i], |a| a.union(&bounds[i])));
+                b.1 += 1;
+            }
+            let merge = |bins: &[(Option<Aabb>, usize)]| {
+                bins.iter().fold((None::<Aabb>, 0), |(a, n), (b, m)| {
+                    let union = match (a, b) {
+                        (Some(a), Some(b)) => Some(a.union(b)),
+                        (a, b) => a.or(*b),
+                    };
+                    (union, n + m)
+                })
+            };
+            let area = total.surface_area().max(Float::MIN_POSITIVE);
+            let mut best = (Float::INFINITY, 0);
+            for k in 1..BINS {
+                let ((below, n_below), (above, n_above)) = (merge(&bins[..k]), merge(&bins[k..]));
+                if let (Some(below), Some(above)) = (below, above) {
+                    let cost = TRAVERSAL_COST
+                        + (n_below as Float * below.surface_area()
+                            + n_above as Float * above.surface_area())
+                            / area;
+                    if cost < best.0 {
+                        best = (cost, k);
+                    }
+                }
+            }
+            if n <= MAX_LEAF && best.0 >= n as Float {
+                return;
+            }
+            let mut below = 0;
+            for j in 0..n {
+                if bin(items[j]) < best.1 {
+                    items.swap(below, j);
+                    below += 1;
+                }
+            }
+            below
+        } else if n <= MAX_LEAF {
+            return;
+        } else {
+            // all in the same place, split anywhere
+            n / 2
+        };
+        self.nodes[node].count = 0;
+        self.nodes[node].axis = axis;
+        self.build(bounds, start, start + split);
+        self.nodes[node].index = self.nodes.len();
+        self.build(bounds, start + split, end);
+    }
+    // Updates the bounds of the nodes to the primitives, which must be as
+    // many as it was built with.
+    pub fn refit(&mut self, bounds: &[Aabb]) {
+        // children follow their parents
+        for i in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[i];
+            let b = if node.count > 0 {
+                let leaf = &self.order[node.index..node.index + node.count];
+                leaf[1..]
+                    .iter()
+                    .fold(bounds[leaf[0]], |b, &p| b.union(&bounds[p]))
+            } else {
+                self.nodes[i + 1]
+                    .bounds
+                    .union(&self.nodes[node.index].bounds)
+            };
+            self.nodes[i].bounds = b;
+        }
+    }
+    // Calls hit with the primitives of the leaves the ray reaches before the
+    // closest hit so far, nearer children first. hit returns the distance of
+    // a hit closer than the one it is given.
+    pub fn closest<F>(&self, ray: &Ray, t_min: Float, t_max: Float, mut hit: F) -> bool
+    where
+        F: FnMut(usize, Float) -> Option<Float>,
+    {
+        let mut closest = t_max;
+        let mut found = false;
+        self.visit(ray, t_min, t_max, &mut |primitives| {
+            for &p in primitives {
+                if let Some(t) = hit(p, closest) {
+                    closest = t;
+                    found = true;
+                }
+            }
+            Some(closest)
+        });
+        found
+    }
+    // Calls f with the primitives of the leaves the ray overlaps between
+    // t_min and t_max, until it returns false.
+    pub fn overlapping<F>(&self, ray: &Ray, t_min: Float, t_max: Float, mut f: F)
+    where
+        F: FnMut(usize) -> bool,
+    {
+        self.visit(ray, t_min, t_max, &mut |primitives| {
+            if primitives.iter().all(|&p| f(p)) {
+                Some(t_max)
+            } else {
+                None
+            }
+        });
+    }
+    // Leaves in front to back order, up to the distance the last one returns,
+    // or until it returns None.
+    fn visit(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        mut t_max: Float,
+        leaf: &mut dyn FnMut(&[usize]) -> Option<Float>,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+        while let Some(i) = stack.pop() {
+            count(Counter::BvhNodeVisits, 1);
+            let node = &self.nodes[i];
+            if !node.bounds.hit(ray, t_min, t_max) {
+                continue;
+            }
+            if node.count > 0 {
+                match leaf(&self.order[node.index..node.index + node.count]) {
+                    Some(t) => t_max = t,
+                    None => return,
+                }
+            } else if ray.d[node.axis] < 0.0 {
+                stack.push(i + 1);
+                stack.push(node.index);
+            } else {
+                stack.push(node.index);
+                stack.push(i + 1);
+            }
+        }
+    }
+}
+
+// Objects under a bounding volume hierarchy, hit as one object. Objects
+// without bounds are tested on their own. After the objects change, refit
+// updates the bounds of the hierarchy, which is fast and enough while they
+// move little, and rebuild also regroups them.
+pub struct Bvh<H: Hitable> {
+    objects: Vec<H>,
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>,
+    tree: Tree,
+}
+impl<H: Hitable> Bvh<H> {
+    pub fn new(objects: Vec<H>) -> Self {
+        let mut bvh = Self {
+            objects,
+            bounded: vec![],
+            unbounded: vec![],
+            tree: Tree::new(&[]),
+        };
+        bvh.rebuild();
+        bvh
+    }
+    pub fn objects(&self) -> &[H] {
+        &self.objects
+    }
+    // The objects to change, after which the hierarchy must be refit or
+    // rebuilt.
+    pub fn objects_mut(&mut self) -> &mut [H] {
+        &mut self.objects
+    }
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+    pub fn rebuild(&mut self) {
+        let (mut bounded, mut unbounded, mut bounds) = (vec![], vec![], vec![]);
+        for (i, object) in self.objects.iter().enumerate() {
+            match object.bounding_box() {
+                Some(b) => {
+                    bounded.push(i);
+                    bounds.push(b);
+                }
+                None => unbounded.push(i),
+            }
+        }
+        self.tree = Tree::new(&bounds);
+        self.bounded = bounded;
+        self.unbounded = unbounded;
+    }
+    // Rebuilds instead when objects gained or lost their bounds.
+    pub fn refit(&mut self) {
+        let bounds: Option<Vec<Aabb>> = self
+            .bounded
+            .iter()
+            .map(|&i| self.objects[i].bounding_box())
+            .collect();
+        match bounds {
+            Some(bounds)
+                if self
+                    .unbounded
+                    .iter()
+                    .all(|&i| self.objects[i].bounding_box().is_none()) =>
+            {
+                self.tree.refit(&bounds)
+            }
+            _ => self.rebuild(),
+        }
+    }
+}
+impl<H: Hitable> Hitable for Bvh<H> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        let mut temp_rec = rec.clone();
+        let mut found = self.tree.closest(ray, t_min, t_max, |p, closest| {
+            if self.objects[self.bounded[p]].hit(ray, t_min, closest, &mut temp_rec) {
+                *rec = temp_rec.clone();
+                Some(temp_rec.t)
+            } else {
+                None
+            }
+        });
+        for &i in &self.unbounded {
+            let closest = if found { rec.t } else { t_max };
+            if self.objects[i].hit(ray, t_min, closest, &mut temp_rec) {
+                *rec = temp_rec.clone();
+                found = true;
+            }
+        }
+        found
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.tree.bounds()
+    }
+    fn transmittance(&self, ray: &Ray, t_min: Float, t_max: Float) -> Float {
+        let mut tr: Float = self
+            .unbounded
+            .iter()
+            .map(|&i| self.objects[i].transmittance(ray, t_min, t_max))
+            .product();
+        if tr > 0.0 {
+            self.tree.overlapping(ray, t_min, t_max, |p| {
+                tr *= self.objects[self.bounded[p]].transmittance(ray, t_min, t_max);
+                tr > 0.0
+            });
+        }
+        tr
+    }
+}
+
+// An object placed with a transform, which other instances can share.
+#[derive(Clone)]
+pub struct Instance {
+    pub object: Arc<dyn Hitable>,
+    pub transform: Transform,
+}
+impl Instance {
+    pub fn new(object: Arc<dyn Hitable>, transform: Transform) -> Self {
+        Self { object, transform }
+    }
+    // The ray in the space of the object, where distances along it are the
+    // same.
+    fn object_ray(&self, ray: &Ray) -> Ray {
+        self.transform.inverse().ray_with_error(ray).0
+    }
+    fn record(&self, rec: &HitRecord) -> HitRecord {
+        let (p, p_error) = self.transform.point_with_error(&rec.p, &rec.p_error);
+        HitRecord {
+            p,
+            p_error,
+            normal: normalize(self.transform.normal(&rec.normal)),
+            shading_normal: normalize(self.transform.normal(&rec.shading_normal)),
+            dpdu: self.transform.vector(&rec.dpdu),
+            dpdv: self.transform.vector(&rec.dpdv),
+            ..rec.clone()
+        }
+    }
+}
+impl Hitable for Instance {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        if self.object.hit(&self.object_ray(ray), t_min, t_max, rec) {
+            *rec = self.record(rec);
+            return true;
+        }
+        false
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object
+            .bounding_box()
+            .map(|b| self.transform.bounds(&b))
+    }
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span>> {
+        let spans = self.object.spans(&self.object_ray(ray))?;
+        Some(
+            spans
+                .iter()
+                .map(|s| Span {
+                    enter: self.record(&s.enter),
+                    exit: self.record(&s.exit),
+                })
+                .collect(),
+        )
+    }
+    fn transmittance(&self, ray: &Ray, t_min: Float, t_max: Float) -> Float {
+        self.object
+            .transmittance(&self.object_ray(ray), t_min, t_max)
+    }
+}
+
+// Top level hierarchy over instances of bottom level ones, such as meshes
+// or object sets under their own Bvh. Moving instances only needs this one
+// to be refit or rebuilt.
+pub type Tlas = Bvh<Instance>;
+impl Tlas {
+    // Moves instance i, to be followed by a refit or rebuild.
+    pub fn set_transform(&mut self, i: usize, transform: Transform) {
+        self.objects[i].transform = transform;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{Bvh, HitList, HitRecord, Hitable, Instance, SphereObject, Tlas};
+    use crate::geometry::{random, random_unit_vector, seed_random, Float, Ray, Transform, Vec3};
+    use std::sync::Arc;
+    fn spheres(n: usize) -> Vec<SphereObject> {
+        (0..n)
+            .map(|_| SphereObject {
+                center: Vec3::new(
+                    20.0 * random() - 10.0,
+                    4.0 * random(),
+                    20.0 * random() - 10.0,
+                ),
+                radius: 0.05 + 0.5 * random(),
+                mat: None,
+            })
+            .collect()
+    }
+    fn random_ray() -> Ray {
+        let o = Vec3::new(
+            24.0 * random() - 12.0,
+            6.0 * random() - 1.0,
+            24.0 * random() - 12.0,
+        );
+        Ray::new(o, random_unit_vector())
+    }
+    // Distances and normals of the hits of the same rays, None for misses.
+    fn hits(world: &dyn Hitable, rays: &[Ray]) -> Vec<Option<(Float, Vec3)>> {
+        rays.iter()
+            .map(|r| {
+                let mut rec = HitRecord::new();
+                if world.hit(r, 0.001, Float::INFINITY, &mut rec) {
+                    Some((rec.t, rec.normal))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+    fn close(a: &[Option<(Float, Vec3)>], b: &[Option<(Float, Vec3)>]) -> bool {
+        a.iter().zip(b).all(|(a, b)| match (a, b) {
+            (Some(a), Some(b)) => (a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).length() < 1e-3,
+            (None, None) => true,
+            _ => false,
+        })
+    }
+    #[test]
+    fn bvh_hits_like_a_list() {
+        seed_random(1);
+        let spheres = spheres(500);
+        let mut list = HitList::new();
+        let mut objects: Vec<Box<dyn Hitable>> = vec![];
+        for s in &spheres {
+            list.list.push(Box::new(s.clone()));
+            objects.push(Box::new(s.clone()));
+        }
+        // a ground much bigger than the rest, whose bounds hold them all
+        let ground = SphereObject {
+            center: Vec3::new(0.0, -1000.0, 0.0),
+            radius: 1000.0,
+            mat: None,
+        };
+        list.list.push(Box::new(ground.clone()));
+        objects.push(Box::new(ground));
+        let bvh = Bvh::new(objects);
+        let rays: Vec<Ray> = (0..2000).map(|_| random_ray()).collect();
+        let expected = hits(&list, &rays);
+        assert!(expected.iter().filter(|h| h.is_some()).count() > 500);
+        assert_eq!(hits(&bvh, &rays), expected);
+        for r in &rays[..200] {
+            assert_eq!(
+                bvh.transmittance(r, 0.001, 5.0),
+                list.transmittance(r, 0.001, 5.0)
+            );
+        }
+    }
+    #[test]
+    fn tlas_refits_moved_instances() {
+        seed_random(2);
+        let mut blas: Vec<Box<dyn Hitable>> = vec![];
+        for s in spheres(50) {
+            blas.push(Box::new(SphereObject {
+                center: 0.1 * s.center,
+                radius: 0.1 + 0.2 * s.radius,
+                mat: None,
+            }));
+        }
+        let blas: Arc<dyn Hitable> = Arc::new(Bvh::new(blas));
+        let place = |i: usize, time: Float| {
+            let (x, z) = ((i % 10) as Float * 2.0 - 9.0, (i / 10) as Float * 2.0 - 9.0);
+            Transform::translate(Vec3::new(x + time * (i % 3) as Float, time, z))
+                * Transform::rotate(40.0 * time * i as Float, Vec3::new(0.0, 1.0, 0.0))
+                * Transform::scale(Vec3::new(1.0, 1.0 + time, 1.0))
+        };
+        let instances = |time: Float| -> Vec<Instance> {
+            (0..100)
+                .map(|i| Instance::new(blas.clone(), place(i, time)))
+                .collect()
+        };
+        let mut tlas = Tlas::new(instances(0.0));
+        let rays: Vec<Ray> = (0..2000).map(|_| random_ray()).collect();
+        let before = hits(&tlas, &rays);
+        let n = before.iter().filter(|h| h.is_some()).count();
+        assert!(n > 100, "{}", n);
+        for i in 0..tlas.len() {
+            tlas.set_transform(i, place(i, 0.7));
+        }
+        tlas.refit();
+        let mut list = HitList::new();
+        for instance in instances(0.7) {
+            list.list.push(Box::new(instance));
+        }
+        let expected = hits(&list, &rays);
+        assert!(!close(&before, &expected));
+        assert!(close(&hits(&tlas, &rays), &expected));
+        tlas.rebuild();
+        assert!(close(&hits(&tlas, &rays), &expected));
+    }
+}
@@ -0,0 +1,91 @@
+use crate::core::Image;
+use crate::geometry::{consts, random, random_in_unit_disk, Distribution2D, Float, Vec3};
+
+// Shape of the lens opening of a thin lens camera, which is also the shape of
+// out of focus highlights (bokeh).
+#[derive(Debug, Clone)]
+pub enum Aperture {
+    Circular,
+    // regular polygon inscribed in the unit circle, rotation in degrees
+    Polygonal { blades: u32, rotation: Float },
+    // grayscale mask over [-1,1]^2, sampled in proportion to its intensity
+    Mask(Distribution2D, Float),
+}
+
+impl Aperture {
+    pub fn polygonal(blades: u32, rotation: Float) -> Self {
+        Aperture::Polygonal {
+            blades: blades.max(3),
+            rotation,
+        }
+    }
+    pub fn mask(image: &Image) -> Self {
+        Aperture::Mask(
+            Distribution2D::new(&image.luminance(), image.width, image.height),
+            image.width as Float / image.height as Float,
+        )
+    }
+    // Point on the aperture in lens coordinates (z = 0).
+    pub fn sample(&self) -> Vec3 {
+        match *self {
+            Aperture::Circular => random_in_unit_disk(),
+            Aperture::Polygonal { blades, rotation } => {
+                // pick one of the triangles fanning out from the center
+                let step = 2.0 * consts::PI / blades as Float;
+                let k = (random() * blades as Float) as u32 % blades;
+                let a0 = rotation * consts::PI / 180.0 + k as Float * step;
+                let p0 = Vec3::new(a0.cos(), a0.sin(), 0.0);
+                let p1 = Vec3::new((a0 + step).cos(), (a0 + step).sin(), 0.0);
+                let mut b0: Float = random();
+                let mut b1: Float = random();
+                if b0 + b1 > 1.0 {
+                    b0 = 1.0 - b0;
+                    b1 = 1.0 - b1;
+                }
+                b0 * p0 + b1 * p1
+            }
+            Aperture::Mask(ref distribution, aspect) => {
+                let ((u, v), _) = distribution.sample_continuous(random(), random());
+                let (sx, sy) = if aspect > 1.0 {
+                    (1.0, 1.0 / aspect)
+                } else {
+                    (aspect, 1.0)
+                };
+                // image rows go top to bottom
+                Vec3::new((2.0 * u - 1.0) * sx, (1.0 - 2.0 * v) * sy, 0.0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::cameras::Aperture;
+    use crate::core::Image;
+    use crate::geometry::dot;
+    use crate::geometry::{consts, Float, Vec3};
+    #[test]
+    fn polygonal_samples_inside_polygon() {
+        let aperture = Aperture::polygonal(6, 30.0);
+        // apothem of the hexagon, facets normals at 0, 60, ... degrees
+        let apothem = (consts::PI / 6.0).cos();
+        for _ in 0..1000 {
+            let p = aperture.sample();
+            for k in 0..6 {
+                let a = (k as Float * 60.0).to_radians();
+                assert!(dot(&p, &Vec3::new(a.cos(), a.sin(), 0.0)) <= apothem + 1e-5);
+            }
+        }
+    }
+    #[test]
+    fn mask_samples_bright_pixels() {
+        // only the top right pixel is open
+        let mut image = Image::new(2, 2);
+        image.set(1, 0, Vec3::new(1.0, 1.0, 1.0));
+        let aperture = Aperture::mask(&image);
+        for _ in 0..100 {
+            let p = aperture.sample();
+            assert!(p.x >= 0.0 && p.y >= 0.0);
+        }
+    }
+}
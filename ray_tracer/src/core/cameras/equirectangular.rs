@@ -0,0 +1,32 @@
+use crate::core::cameras::{basis, Camera};
+use crate::geometry::{consts, Float, Ray, Vec3};
+
+// 360 x 180 degree panorama: s maps to longitude, t to latitude, with the
+// center of the image looking at `look_at`.
+pub struct EquirectangularCamera {
+    origin: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl EquirectangularCamera {
+    pub fn new(look_from: Vec3, look_at: Vec3, v_up: Vec3) -> Self {
+        let (u, v, w) = basis(look_from, look_at, v_up);
+        Self {
+            origin: look_from,
+            u,
+            v,
+            w,
+        }
+    }
+}
+impl Camera for EquirectangularCamera {
+    fn ray(&self, s: Float, t: Float) -> Option<Ray> {
+        let longitude = (s - 0.5) * 2.0 * consts::PI;
+        let latitude = (t - 0.5) * consts::PI;
+        let d = latitude.cos() * (longitude.sin() * self.u - longitude.cos() * self.w)
+            + latitude.sin() * self.v;
+        Some(Ray::new(self.origin, d))
+    }
+}
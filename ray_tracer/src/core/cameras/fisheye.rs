@@ -0,0 +1,66 @@
+use crate::core::cameras::{basis, Camera};
+use crate::geometry::{consts, Float, Ray, Vec3};
+
+// Relation between the angle from the optical axis and the radius on the film.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FisheyeMapping {
+    // r = f * theta
+    Equidistant,
+    // r = 2 f sin(theta / 2)
+    Equisolid,
+}
+
+// Circular fisheye: the image circle is inscribed in the smaller film side.
+pub struct FisheyeCamera {
+    origin: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    half_fov: Float,
+    aspect: Float,
+    mapping: FisheyeMapping,
+}
+
+impl FisheyeCamera {
+    pub fn new(
+        look_from: Vec3,
+        look_at: Vec3,
+        v_up: Vec3,
+        fov: Float,
+        aspect: Float,
+        mapping: FisheyeMapping,
+    ) -> Self {
+        let (u, v, w) = basis(look_from, look_at, v_up);
+        Self {
+            origin: look_from,
+            u,
+            v,
+            w,
+            half_fov: (fov * consts::PI / 180.0 / 2.0).min(consts::PI),
+            aspect,
+            mapping,
+        }
+    }
+}
+impl Camera for FisheyeCamera {
+    fn ray(&self, s: Float, t: Float) -> Option<Ray> {
+        let mut x = 2.0 * s - 1.0;
+        let mut y = 2.0 * t - 1.0;
+        if self.aspect > 1.0 {
+            x *= self.aspect;
+        } else {
+            y /= self.aspect;
+        }
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.half_fov,
+            FisheyeMapping::Equisolid => 2.0 * (r * (self.half_fov / 2.0).sin()).asin(),
+        };
+        let phi = y.atan2(x);
+        let d = theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w;
+        Some(Ray::new(self.origin, d))
+    }
+}
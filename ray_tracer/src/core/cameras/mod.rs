@@ -0,0 +1,151 @@
+mod aperture;
+mod equirectangular;
+mod fisheye;
+mod orthographic;
+mod perspective;
+
+pub use self::aperture::*;
+pub use self::equirectangular::*;
+pub use self::fisheye::*;
+pub use self::orthographic::*;
+pub use self::perspective::*;
+
+use crate::geometry::{cross, normalize, Float, Ray, Vec3};
+
+pub trait Camera: Send + Sync {
+    // Ray through film coordinates (s, t) in [0,1]^2, (0,0) being the lower
+    // left corner. None if the film point is not covered by the projection.
+    fn ray(&self, s: Float, t: Float) -> Option<Ray>;
+    // Importance of a ray leaving the lens and the film coordinates it is
+    // seen at, None if it misses the film. Cameras that keep the defaults
+    // can not be reached by paths traced from the lights.
+    fn we(&self, _r: &Ray) -> Option<(Float, Float, Float)> {
+        None
+    }
+    // Densities with which ray would choose the origin of r on the lens (by
+    // area) and its direction (by solid angle).
+    fn pdf_we(&self, _r: &Ray) -> (Float, Float) {
+        (0.0, 0.0)
+    }
+    fn sample_wi(&self, _p: &Vec3) -> Option<CameraSample> {
+        None
+    }
+}
+
+// A point on the lens seen from a point p of the scene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraSample {
+    pub lens: Vec3,
+    // unit direction from p to the lens
+    pub wi: Vec3,
+    pub distance: Float,
+    pub importance: Float,
+    // solid angle density of wi at p
+    pub pdf: Float,
+    // film coordinates p is seen at
+    pub s: Float,
+    pub t: Float,
+}
+
+// Orthonormal camera basis: the camera looks along -w with v pointing up.
+fn basis(look_from: Vec3, look_at: Vec3, v_up: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = normalize(look_from - look_at);
+    let u = normalize(cross(&v_up, &w));
+    let v = cross(&w, &u);
+    (u, v, w)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::cameras::*;
+    use crate::geometry::{dot, normalize, seed_random, Vec3};
+    #[test]
+    fn image_center_looks_at_target() {
+        let look_from = Vec3::new(1.0, 2.0, 3.0);
+        let look_at = Vec3::new(-2.0, 0.5, 1.0);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let forward = normalize(look_at - look_from);
+        let cameras: Vec<Box<dyn Camera>> = vec![
+            Box::new(PerspectiveCamera::new(
+                look_from, look_at, up, 40.0, 2.0, 0.0, 1.0,
+            )),
+            Box::new(OrthographicCamera::new(look_from, look_at, up, 2.0, 2.0)),
+            Box::new(FisheyeCamera::new(
+                look_from,
+                look_at,
+                up,
+                180.0,
+                2.0,
+                FisheyeMapping::Equidistant,
+            )),
+            Box::new(FisheyeCamera::new(
+                look_from,
+                look_at,
+                up,
+                180.0,
+                2.0,
+                FisheyeMapping::Equisolid,
+            )),
+            Box::new(EquirectangularCamera::new(look_from, look_at, up)),
+        ];
+        for c in cameras.iter() {
+            let r = c.ray(0.5, 0.5).unwrap();
+            assert!((r.o - look_from).length() < 1e-5);
+            assert!(dot(&normalize(r.d), &forward) > 0.9999);
+        }
+    }
+    #[test]
+    fn perspective_importance() {
+        seed_random(15);
+        let look_from = Vec3::new(0.0, 1.0, 4.0);
+        let look_at = Vec3::new(0.0, 1.0, 0.0);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let p = Vec3::new(0.5, 1.3, -1.0);
+        for &aperture in [0.0, 0.4].iter() {
+            let c = PerspectiveCamera::new(look_from, look_at, up, 40.0, 1.5, aperture, 4.0);
+            let s = c.sample_wi(&p).unwrap();
+            // the film point sees p from the lens point, in focus or not
+            let r = c.ray(s.s, s.t).unwrap();
+            let to_p = normalize(p - r.o);
+            if aperture == 0.0 {
+                assert!(dot(&normalize(r.d), &to_p) > 0.99999);
+            }
+            // sampling rays in proportion to importance
+            let (we, _, _) = c.we(&r).unwrap();
+            let (pdf_pos, pdf_dir) = c.pdf_we(&r);
+            let cos = dot(&normalize(r.d), &normalize(look_at - look_from));
+            assert!((we * cos / (pdf_pos * pdf_dir) - 1.0).abs() < 1e-4);
+        }
+        let tilted = PerspectiveCamera::new(look_from, look_at, up, 40.0, 1.5, 0.1, 4.0)
+            .with_tilt(10.0, 0.0);
+        assert!(tilted.sample_wi(&p).is_none());
+        // behind the camera and outside the field of view
+        let c = PerspectiveCamera::new(look_from, look_at, up, 40.0, 1.5, 0.0, 4.0);
+        assert!(c.sample_wi(&Vec3::new(0.0, 1.0, 8.0)).is_none());
+        assert!(c.sample_wi(&Vec3::new(5.0, 1.0, 0.0)).is_none());
+    }
+    #[test]
+    fn fisheye_edges() {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let o = Vec3::new(0.0, 0.0, 0.0);
+        let at = Vec3::new(0.0, 0.0, -1.0);
+        for mapping in [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid].iter() {
+            let c = FisheyeCamera::new(o, at, up, 180.0, 1.0, *mapping);
+            // image circle border is 90 degrees off axis
+            let r = c.ray(1.0, 0.5).unwrap();
+            assert!(normalize(r.d).z.abs() < 1e-5);
+            assert!(c.ray(1.0, 1.0).is_none());
+        }
+    }
+    #[test]
+    fn equirectangular_covers_sphere() {
+        let c = EquirectangularCamera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        assert!(normalize(c.ray(0.5, 1.0).unwrap().d).y > 0.9999);
+        assert!(normalize(c.ray(0.0, 0.5).unwrap().d).z > 0.9999);
+        assert!(normalize(c.ray(0.75, 0.5).unwrap().d).x > 0.9999);
+    }
+}
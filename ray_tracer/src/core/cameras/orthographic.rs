@@ -0,0 +1,32 @@
+use crate::core::cameras::{basis, Camera};
+use crate::geometry::{Float, Ray, Vec3};
+
+pub struct OrthographicCamera {
+    lower_left_corner: Vec3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    direction: Vec3,
+}
+
+impl OrthographicCamera {
+    // `height` is the extent of the view volume along the up vector.
+    pub fn new(look_from: Vec3, look_at: Vec3, v_up: Vec3, height: Float, aspect: Float) -> Self {
+        let (u, v, w) = basis(look_from, look_at, v_up);
+        let half_height = height / 2.0;
+        let half_width = aspect * half_height;
+        Self {
+            lower_left_corner: look_from - half_width * u - half_height * v,
+            horizontal: 2.0 * half_width * u,
+            vertical: 2.0 * half_height * v,
+            direction: -w,
+        }
+    }
+}
+impl Camera for OrthographicCamera {
+    fn ray(&self, s: Float, t: Float) -> Option<Ray> {
+        Some(Ray::new(
+            self.lower_left_corner + s * self.horizontal + t * self.vertical,
+            self.direction,
+        ))
+    }
+}
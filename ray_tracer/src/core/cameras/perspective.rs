@@ -0,0 +1,168 @@
+use crate::core::cameras::{basis, Aperture, Camera, CameraSample};
+use crate::geometry::{consts, dot, normalize, Float, Ray, Vec3};
+
+pub struct PerspectiveCamera {
+    origin: Vec3,
+    lower_left_corner: Vec3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    lens_radius: Float,
+    aperture: Aperture,
+    focus_point: Vec3,
+    focus_normal: Vec3,
+}
+
+impl PerspectiveCamera {
+    pub fn new(
+        look_from: Vec3,
+        look_at: Vec3,
+        v_up: Vec3,
+        v_fov: Float,
+        aspect: Float,
+        aperture: Float,
+        focus_dist: Float,
+    ) -> Self {
+        let lens_radius = aperture / 2.0;
+        let theta = v_fov * consts::PI / 180.0;
+        let half_height = Float::tan(theta / 2.0);
+        let half_width = aspect * half_height;
+        let origin = look_from;
+        let (u, v, w) = basis(look_from, look_at, v_up);
+        Self {
+            origin,
+            u,
+            v,
+            w,
+            lens_radius,
+            lower_left_corner: origin
+                - half_width * focus_dist * u
+                - half_height * focus_dist * v
+                - focus_dist * w,
+            horizontal: 2.0 * half_width * focus_dist * u,
+            vertical: 2.0 * half_height * focus_dist * v,
+            aperture: Aperture::Circular,
+            focus_point: origin - focus_dist * w,
+            focus_normal: w,
+        }
+    }
+    pub fn with_aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
+    // Tilts the plane of focus about the horizontal (tilt) and vertical
+    // (swing) axes, in degrees. A positive tilt moves the top of the plane
+    // away from the camera, a positive swing its right side.
+    pub fn with_tilt(mut self, tilt: Float, swing: Float) -> Self {
+        let (tilt, swing) = (tilt.to_radians(), swing.to_radians());
+        self.focus_normal = normalize(
+            tilt.cos() * swing.cos() * self.w
+                + tilt.sin() * self.v
+                + tilt.cos() * swing.sin() * self.u,
+        );
+        self
+    }
+    // Shifts the field of view parallel to the film, in fractions of the
+    // image width and height.
+    pub fn with_shift(mut self, x: Float, y: Float) -> Self {
+        self.lower_left_corner = self.lower_left_corner + x * self.horizontal + y * self.vertical;
+        self
+    }
+    // Importance is only known for a plane of focus parallel to the film and
+    // uniformly sampled lenses.
+    fn has_importance(&self) -> bool {
+        let untilted = (self.focus_normal - self.w).length2() < 1e-10;
+        untilted && matches!(self.aperture, Aperture::Circular)
+    }
+    fn lens_area(&self) -> Float {
+        if self.lens_radius == 0.0 {
+            1.0
+        } else {
+            consts::PI * self.lens_radius * self.lens_radius
+        }
+    }
+    // Film coordinates of a ray leaving the lens and the cosine of its angle
+    // to the view direction.
+    fn film_point(&self, r: &Ray) -> Option<(Float, Float, Float)> {
+        let d = normalize(r.d);
+        let cos = -dot(&d, &self.w);
+        if cos <= 0.0 {
+            return None;
+        }
+        let focus_dist = dot(&(self.origin - self.focus_point), &self.w);
+        let q = r.o + (focus_dist / cos) * d - self.lower_left_corner;
+        let s = dot(&q, &self.u) / self.horizontal.length();
+        let t = dot(&q, &self.v) / self.vertical.length();
+        if !(0.0..=1.0).contains(&s) || !(0.0..=1.0).contains(&t) {
+            return None;
+        }
+        Some((s, t, cos))
+    }
+    // Area of the film at unit distance from the lens.
+    fn film_area(&self) -> Float {
+        let focus_dist = dot(&(self.origin - self.focus_point), &self.w);
+        self.horizontal.length() * self.vertical.length() / (focus_dist * focus_dist)
+    }
+}
+impl Camera for PerspectiveCamera {
+    fn ray(&self, s: Float, t: Float) -> Option<Ray> {
+        let rd = self.lens_radius * self.aperture.sample();
+        let offset = self.u * rd.x + self.v * rd.y;
+        // point where the chief ray meets the plane of focus
+        let chief = self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin;
+        let cos = dot(&chief, &self.focus_normal);
+        let k = if cos.abs() > 1e-6 {
+            dot(&(self.focus_point - self.origin), &self.focus_normal) / cos
+        } else {
+            1.0
+        };
+        let focus = if k > 0.0 { k * chief } else { chief };
+        Some(Ray::new(self.origin + offset, focus - offset))
+    }
+    fn we(&self, r: &Ray) -> Option<(Float, Float, Float)> {
+        if !self.has_importance() {
+            return None;
+        }
+        let (s, t, cos) = self.film_point(r)?;
+        let cos2 = cos * cos;
+        Some((
+            1.0 / (self.film_area() * self.lens_area() * cos2 * cos2),
+            s,
+            t,
+        ))
+    }
+    fn pdf_we(&self, r: &Ray) -> (Float, Float) {
+        match self.film_point(r) {
+            Some((_, _, cos)) if self.has_importance() => (
+                1.0 / self.lens_area(),
+                1.0 / (self.film_area() * cos * cos * cos),
+            ),
+            _ => (0.0, 0.0),
+        }
+    }
+    fn sample_wi(&self, p: &Vec3) -> Option<CameraSample> {
+        if !self.has_importance() {
+            return None;
+        }
+        let rd = self.lens_radius * self.aperture.sample();
+        let lens = self.origin + self.u * rd.x + self.v * rd.y;
+        let distance = (lens - *p).length();
+        if distance == 0.0 {
+            return None;
+        }
+        let wi = (lens - *p) / distance;
+        let (importance, s, t) = self.we(&Ray::new(lens, -wi))?;
+        let cos = dot(&wi, &self.w);
+        Some(CameraSample {
+            lens,
+            wi,
+            distance,
+            importance,
+            pdf: distance * distance / (cos.abs() * self.lens_area()),
+            s,
+            t,
+        })
+    }
+}
@@ -0,0 +1,292 @@
+use crate::core::{Image, Renderer, Scene};
+use crate::geometry::Float;
+use std::{env, fs};
+
+// Differences of an image from a reference, over all channels. The relative
+// error is taken against the reference, PSNR against a peak of 1, and SSIM
+// on the luminance clamped to [0, 1].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metrics {
+    pub mse: Float,
+    pub rmse: Float,
+    pub rel_mse: Float,
+    pub psnr: Float,
+    pub ssim: Float,
+}
+impl std::fmt::Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "mse {:.6} rmse {:.6} relmse {:.6} psnr {:.2} dB ssim {:.4}",
+            self.mse, self.rmse, self.rel_mse, self.psnr, self.ssim
+        )
+    }
+}
+
+pub fn compare(image: &Image, reference: &Image) -> Result<Metrics, String> {
+    if (image.width, image.height) != (reference.width, reference.height) {
+        return Err(format!(
+            "image is {}x{}, reference {}x{}",
+            image.width, image.height, reference.width, reference.height
+        ));
+    }
+    let n = 3.0 * image.pixels.len().max(1) as Float;
+    let (mut mse, mut rel_mse) = (0.0, 0.0);
+    for (a, b) in image.pixels.iter().zip(&reference.pixels) {
+        for c in 0..3 {
+            let d2 = (a[c] - b[c]) * (a[c] - b[c]);
+            mse += d2 / n;
+            rel_mse += d2 / (b[c] * b[c] + 1e-2) / n;
+        }
+    }
+    Ok(Metrics {
+        mse,
+        rmse: mse.sqrt(),
+        rel_mse,
+        psnr: if mse > 0.0 {
+            -10.0 * mse.log10()
+        } else {
+            Float::INFINITY
+        },
+        ssim: ssim(image, reference),
+    })
+}
+
+// Mean structural similarity over 11x11 gaussian windows.
+fn ssim(image: &Image, reference: &Image) -> Float {
+    let (c1, c2) = (0.01 * 0.01, 0.03 * 0.03);
+    let x: Vec<Float> = image
+        .luminance()
+        .iter()
+        .map(|v| v.clamp(0.0, 1.0))
+        .collect();
+    let y: Vec<Float> = reference
+        .luminance()
+        .iter()
+        .map(|v| v.clamp(0.0, 1.0))
+        .collect();
+    let blur = |v: Vec<Float>| gaussian_blur(&v, image.width, image.height, 1.5);
+    let xx = x.iter().map(|a| a * a).collect();
+    let yy = y.iter().map(|a| a * a).collect();
+    let xy = x.iter().zip(&y).map(|(a, b)| a * b).collect();
+    let (mx, my) = (blur(x), blur(y));
+    let (sxx, syy, sxy) = (blur(xx), blur(yy), blur(xy));
+    let mut sum = 0.0;
+    for i in 0..mx.len() {
+        let (vx, vy) = (sxx[i] - mx[i] * mx[i], syy[i] - my[i] * my[i]);
+        let cov = sxy[i] - mx[i] * my[i];
+        sum += (2.0 * mx[i] * my[i] + c1) * (2.0 * cov + c2)
+            / ((mx[i] * mx[i] + my[i] * my[i] + c1) * (vx + vy + c2));
+    }
+    sum / mx.len().max(1) as Float
+}
+
+// Separable blur, with the weights renormalized where the window leaves the
+// image.
+fn gaussian_blur(values: &[Float], width: usize, height: usize, sigma: Float) -> Vec<Float> {
+    let radius = (3.0 * sigma).ceil() as isize;
+    let weights: Vec<Float> = (-radius..=radius)
+        .map(|i| (-((i * i) as Float) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let pass = |src: &[Float], step: (isize, isize)| {
+        let mut out = vec![0.0; src.len()];
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                let (mut sum, mut total) = (0.0, 0.0);
+                for (k, w) in weights.iter().enumerate() {
+                    let o = k as isize - radius;
+                    let (sx, sy) = (x + o * step.0, y + o * step.1);
+                    if sx >= 0 && sy >= 0 && sx < width as isize && sy < height as isize {
+                        sum += w * src[sy as usize * width + sx as usize];
+                        total += w;
+                    }
+                }
+                out[y as usize * width + x as usize] = sum / total;
+            }
+        }
+        out
+    };
+    pass(&pass(values, (1, 0)), (0, 1))
+}
+
+// False color image of the absolute luminance difference, scaled to its
+// maximum.
+pub fn difference(image: &Image, reference: &Image) -> Image {
+    let diff: Vec<Float> = image
+        .luminance()
+        .iter()
+        .zip(reference.luminance())
+        .map(|(a, b)| (a - b).abs())
+        .collect();
+    Image::heatmap(&diff, image.width, image.height)
+}
+
+// Renders scene at seed 0 and compares it with the golden image of that
+// name in dir, which must be at least as similar as min_ssim and within
+// max_rmse. Precisions draw different random numbers, so each has its own
+// golden images. They are all written when UPDATE_GOLDEN is set, and missing
+// ones fail otherwise. On failure the render and the difference are left in
+// the temporary directory.
+pub fn check_golden(
+    dir: &str,
+    name: &str,
+    scene: &Scene,
+    spp: u32,
+    max_rmse: Float,
+    min_ssim: Float,
+) -> Result<Metrics, String> {
+    let name = format!("{}_f{}", name, 8 * std::mem::size_of::<Float>());
+    let path = format!("{}/{}.ppm", dir, name);
+    let mut renderer = Renderer::new(scene.width, scene.height, 0);
+    renderer.render_pass(&*scene.camera, spp, &|r| scene.integrator.li(r, scene));
+    // compared as stored, clamped and quantized
+    let image = Image::parse_pnm(renderer.film.image().to_ppm().as_bytes())?;
+    if env::var("UPDATE_GOLDEN").is_ok() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir, e))?;
+        image.write_ppm(&path)?;
+        return compare(&image, &image);
+    }
+    if fs::metadata(&path).is_err() {
+        return Err(format!(
+            "{} is missing, set UPDATE_GOLDEN to write it",
+            path
+        ));
+    }
+    let golden = Image::read_pnm(&path)?;
+    let metrics = compare(&image, &golden).map_err(|e| format!("{}: {}", path, e))?;
+    if metrics.rmse > max_rmse || metrics.ssim < min_ssim {
+        let tmp = env::temp_dir();
+        let (new, diff) = (
+            tmp.join(format!("{}.ppm", name)),
+            tmp.join(format!("{}.diff.ppm", name)),
+        );
+        image.write_ppm(&new.to_string_lossy())?;
+        difference(&image, &golden).write_ppm(&diff.to_string_lossy())?;
+        return Err(format!(
+            "{} differs ({}), see {} and {}",
+            path,
+            metrics,
+            new.display(),
+            diff.display()
+        ));
+    }
+    Ok(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{check_golden, compare, difference, Image, Scene};
+    use crate::geometry::{random, seed_random, Float, Vec3};
+    fn gradient() -> Image {
+        let mut image = Image::new(32, 16);
+        for y in 0..16 {
+            for x in 0..32 {
+                let v = x as Float / 32.0;
+                image.set(x, y, Vec3::new(v, 0.5 * v, 1.0 - v));
+            }
+        }
+        image
+    }
+    #[test]
+    fn metrics() {
+        let a = gradient();
+        let same = compare(&a, &a).unwrap();
+        assert_eq!(
+            (same.mse, same.psnr, same.ssim),
+            (0.0, Float::INFINITY, 1.0)
+        );
+        let mut b = a.clone();
+        for p in b.pixels.iter_mut() {
+            *p += Vec3::new(0.1, 0.1, 0.1);
+        }
+        let offset = compare(&b, &a).unwrap();
+        assert!((offset.mse - 0.01).abs() < 1e-5 && (offset.rmse - 0.1).abs() < 1e-4);
+        assert!((offset.psnr - 20.0).abs() < 1e-3);
+        assert!(offset.rel_mse > offset.mse);
+        // noise of the same strength loses more of the structure
+        seed_random(1);
+        let mut c = a.clone();
+        for p in c.pixels.iter_mut() {
+            *p += Vec3::new(0.1, 0.1, 0.1) * if random() < 0.5 { -1.0 } else { 1.0 };
+        }
+        let noise = compare(&c, &a).unwrap();
+        assert!((noise.mse - offset.mse).abs() < 1e-5);
+        assert!(
+            noise.ssim < offset.ssim && offset.ssim < 1.0,
+            "{} {}",
+            noise,
+            offset
+        );
+        assert!(compare(&Image::new(2, 2), &a).is_err());
+        // a uniform difference is at the top of the color ramp everywhere
+        let diff = difference(&b, &a);
+        assert!((diff.get(7, 3) - Vec3::new(1.0, 1.0, 1.0)).length() < 1e-3);
+    }
+    #[test]
+    fn golden_images() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/golden");
+        let scenes = [
+            (
+                "materials",
+                "image width=32 height=16\n\
+                 camera look_from=0,1,5 look_at=0,0.5,0 fov=40\n\
+                 material ground albedo=0.5,0.5,0.5\n\
+                 material glass type=dielectric ior=1.5\n\
+                 material brushed type=metal albedo=0.8,0.6,0.3 fuzz=0.3\n\
+                 material red albedo=0.7,0.2,0.1\n\
+                 sphere center=0,-1000,0 radius=1000 material=ground\n\
+                 sphere center=-1.1,0.5,0 radius=0.5 material=brushed\n\
+                 sphere center=0,0.5,0 radius=0.5 material=glass\n\
+                 sphere center=1.1,0.5,0 radius=0.5 material=red\n",
+            ),
+            (
+                "lights",
+                "image width=32 height=16\n\
+                 camera look_from=0,1,5 look_at=0,0.5,0 fov=40\n\
+                 sky intensity=0.05\n\
+                 material lamp type=light emission=4,4,4\n\
+                 material white albedo=0.8,0.8,0.8\n\
+                 sphere center=0,-1000,0 radius=1000 material=white\n\
+                 sphere center=0,0.5,0 radius=0.5 material=white\n\
+                 sphere center=1.2,1.2,0.5 radius=0.3 material=lamp\n\
+                 light point position=-1.5,1.5,1 intensity=4,3,2\n\
+                 light spot position=0,3,0 look_at=0,0,0 intensity=10,10,16 cone=20\n",
+            ),
+            (
+                "shapes",
+                "image width=32 height=16\n\
+                 camera look_from=0,1.5,4 look_at=0,0.4,0 fov=40\n\
+                 material ground albedo=0.5,0.5,0.5\n\
+                 material red albedo=0.7,0.2,0.1\n\
+                 material green albedo=0.2,0.6,0.2\n\
+                 material blue albedo=0.2,0.3,0.7\n\
+                 sphere center=0,-1000,0 radius=1000 material=ground\n\
+                 torus major=0.4 minor=0.15 translate=-1.1,0.15,0 rotate=90,0,0 material=red\n\
+                 cylinder radius=0.3 z_min=0 z_max=0.8 rotate=-90,0,0 material=green\n\
+                 sdf cuboid size=0.3,0.3,0.3 center=1.1,0.3,0 material=blue\n",
+            ),
+        ];
+        for (name, text) in scenes.iter() {
+            let scene = Scene::parse(text).unwrap();
+            let metrics = check_golden(dir, name, &scene, 16, 0.005, 0.99);
+            assert!(metrics.is_ok(), "{}", metrics.unwrap_err());
+        }
+        if std::env::var("UPDATE_GOLDEN").is_ok() {
+            return;
+        }
+        // missing golden images fail, and a change to the scene is caught
+        let tmp = std::env::temp_dir().join(format!("golden_changes_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&tmp);
+        let tmp = tmp.to_string_lossy();
+        let scene = Scene::parse(scenes[0].1).unwrap();
+        let missing = check_golden(&tmp, "materials", &scene, 16, 0.005, 0.99);
+        assert!(missing.unwrap_err().contains("missing"));
+        let file = format!("materials_f{}.ppm", 8 * std::mem::size_of::<Float>());
+        std::fs::create_dir_all(&*tmp).unwrap();
+        std::fs::copy(format!("{}/{}", dir, file), format!("{}/{}", tmp, file)).unwrap();
+        assert!(check_golden(&tmp, "materials", &scene, 16, 0.005, 0.99).is_ok());
+        let changed = Scene::parse(&scenes[0].1.replace("ior=1.5", "ior=2.4")).unwrap();
+        assert!(check_golden(&tmp, "materials", &changed, 16, 0.005, 0.99).is_err());
+        std::fs::remove_dir_all(&*tmp).unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+use crate::core::stats::{count, Counter};
+use crate::core::{HitRecord, Hitable, Span};
+use crate::geometry::{Aabb, Float, Ray};
+
+// Boolean combinations of two closed solids, from the spans of the ray
+// inside each of them. The scene parser only combines objects that have spans.
+pub struct Union {
+    a: Box<dyn Hitable>,
+    b: Box<dyn Hitable>,
+}
+pub struct Intersection {
+    a: Box<dyn Hitable>,
+    b: Box<dyn Hitable>,
+}
+// The solid a with b carved out of it.
+pub struct Difference {
+    a: Box<dyn Hitable>,
+    b: Box<dyn Hitable>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Union,
+    Intersection,
+    Difference,
+}
+impl Op {
+    fn inside(self, a: bool, b: bool) -> bool {
+        match self {
+            Op::Union => a || b,
+            Op::Intersection => a && b,
+            Op::Difference => a && !b,
+        }
+    }
+}
+
+// Sweeps the boundaries of both span lists in order, keeping those where
+// the combination changes between inside and outside.
+fn combine(a: Vec<Span>, b: Vec<Span>, op: Op) -> Vec<Span> {
+    let mut events: Vec<(bool, HitRecord)> = vec![];
+    for (from_b, spans) in [(false, a), (true, b)] {
+        for s in spans {
+            events.push((from_b, s.enter));
+            events.push((from_b, s.exit));
+        }
+    }
+    events.sort_by(|x, y| x.1.t.total_cmp(&y.1.t));
+    let (mut in_a, mut in_b) = (false, false);
+    let mut enter = None;
+    let mut spans = vec![];
+    for (from_b, mut rec) in events {
+        let was_inside = op.inside(in_a, in_b);
+        if from_b {
+            in_b = !in_b;
+        } else {
+            in_a = !in_a;
+        }
+        if op.inside(in_a, in_b) == was_inside {
+            continue;
+        }
+        // the surface of the carved out solid faces the other way
+        if from_b && op == Op::Difference {
+            rec.normal = -rec.normal;
+            rec.shading_normal = -rec.shading_normal;
+        }
+        match enter.take() {
+            None => enter = Some(rec),
+            Some(e) => spans.push(Span {
+                enter: e,
+                exit: rec,
+            }),
+        }
+    }
+    spans
+}
+
+fn hit_spans(spans: &[Span], t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+    count(Counter::IntersectionTests, 1);
+    for s in spans {
+        for r in [&s.enter, &s.exit] {
+            if r.t >= t_max {
+                return false;
+            }
+            if r.t > t_min {
+                *rec = r.clone();
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn children_spans(a: &dyn Hitable, b: &dyn Hitable, ray: &Ray, op: Op) -> Vec<Span> {
+    let a = a.spans(ray).unwrap_or_default();
+    // nothing to combine with
+    if a.is_empty() && op != Op::Union {
+        return a;
+    }
+    combine(a, b.spans(ray).unwrap_or_default(), op)
+}
+
+impl Union {
+    pub fn new(a: Box<dyn Hitable>, b: Box<dyn Hitable>) -> Self {
+        Self { a, b }
+    }
+}
+impl Intersection {
+    pub fn new(a: Box<dyn Hitable>, b: Box<dyn Hitable>) -> Self {
+        Self { a, b }
+    }
+}
+impl Difference {
+    pub fn new(a: Box<dyn Hitable>, b: Box<dyn Hitable>) -> Self {
+        Self { a, b }
+    }
+}
+
+impl Hitable for Union {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        hit_spans(&self.spans(ray).unwrap(), t_min, t_max, rec)
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.a.bounding_box()?.union(&self.b.bounding_box()?))
+    }
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span>> {
+        Some(children_spans(&*self.a, &*self.b, ray, Op::Union))
+    }
+}
+impl Hitable for Intersection {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        hit_spans(&self.spans(ray).unwrap(), t_min, t_max, rec)
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.a.bounding_box().or_else(|| self.b.bounding_box())
+    }
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span>> {
+        Some(children_spans(&*self.a, &*self.b, ray, Op::Intersection))
+    }
+}
+impl Hitable for Difference {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        hit_spans(&self.spans(ray).unwrap(), t_min, t_max, rec)
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.a.bounding_box()
+    }
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span>> {
+        Some(children_spans(&*self.a, &*self.b, ray, Op::Difference))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::shapes::{Cylinder, ShapeObject};
+    use crate::core::{assert_no_self_intersection, Difference, HitRecord, Hitable};
+    use crate::core::{Intersection, SphereObject, Union};
+    use crate::geometry::{normalize, random_in_unit_sphere, seed_random};
+    use crate::geometry::{Float, Ray, Transform, Vec3};
+    fn sphere(x: Float, radius: Float) -> Box<SphereObject> {
+        Box::new(SphereObject {
+            center: Vec3::new(x, 0.0, 0.0),
+            radius,
+            mat: None,
+        })
+    }
+    fn first_hit(h: &dyn Hitable, o: Vec3, d: Vec3) -> Option<HitRecord> {
+        let mut rec = HitRecord::new();
+        if h.hit(&Ray::new(o, d), 0.0, Float::INFINITY, &mut rec) {
+            Some(rec)
+        } else {
+            None
+        }
+    }
+    #[test]
+    fn combinations_along_the_x_axis() {
+        let o = Vec3::new(-5.0, 0.0, 0.0);
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        // spheres over [-2, 0] and [-0.5, 1.5]
+        let ts = |h: &dyn Hitable| {
+            h.spans(&Ray::new(o, x))
+                .unwrap()
+                .iter()
+                .map(|s| (s.enter.p.x, s.exit.p.x, s.enter.normal.x, s.exit.normal.x))
+                .collect::<Vec<_>>()
+        };
+        let union = Union::new(sphere(-1.0, 1.0), sphere(0.5, 1.0));
+        assert_eq!(ts(&union), vec![(-2.0, 1.5, -1.0, 1.0)]);
+        let both = Intersection::new(sphere(-1.0, 1.0), sphere(0.5, 1.0));
+        assert_eq!(ts(&both), vec![(-0.5, 0.0, -1.0, 1.0)]);
+        let carved = Difference::new(sphere(-1.0, 1.0), sphere(0.5, 1.0));
+        assert_eq!(ts(&carved), vec![(-2.0, -0.5, -1.0, 1.0)]);
+        // a shell has two spans, and nests
+        let shell = Difference::new(sphere(0.0, 2.0), sphere(0.0, 1.0));
+        assert_eq!(
+            ts(&shell),
+            vec![(-2.0, -1.0, -1.0, 1.0), (1.0, 2.0, -1.0, 1.0)]
+        );
+        let half = Intersection::new(Box::new(shell), sphere(-3.0, 2.5));
+        assert_eq!(ts(&half), vec![(-2.0, -1.0, -1.0, 1.0)]);
+    }
+    #[test]
+    fn sphere_with_a_hole() {
+        seed_random(6);
+        let hole = ShapeObject::new(Cylinder::new(0.4, -2.0, 2.0), None)
+            .with_transform(Transform::rotate(90.0, Vec3::new(1.0, 0.0, 0.0)));
+        let bead = Difference::new(sphere(0.0, 1.0), Box::new(hole));
+        // through the hole along the y axis
+        assert!(first_hit(&bead, Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).is_none());
+        // across it, hitting the wall of the hole from inside the bead
+        let o = Vec3::new(-5.0, 0.0, 0.0);
+        let rec = first_hit(&bead, o, Vec3::new(1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.p.x + 1.0).abs() < 1e-5 && rec.normal.x < -0.999);
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let rec = first_hit(&bead, rec.spawn_ray(x).o, x).unwrap();
+        assert!(
+            (rec.p.x + 0.4).abs() < 1e-5 && rec.normal.x > 0.999,
+            "{:?}",
+            rec.p
+        );
+        // spawned rays do not hit the surface they leave
+        for _ in 0..1000 {
+            let o = 3.0 * normalize(random_in_unit_sphere());
+            let rec = match first_hit(&bead, o, 0.5 * random_in_unit_sphere() - o) {
+                Some(rec) => rec,
+                None => continue,
+            };
+            assert_no_self_intersection(&bead, &rec, 0.9, 1e-3);
+        }
+    }
+}
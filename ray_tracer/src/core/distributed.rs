@@ -0,0 +1,517 @@
+use crate::core::{
+    parse_integrator_spec, Film, Renderer, SampledWavelengths, Scene, Tile, TileBuffer,
+};
+use crate::geometry::{random, seed_random, Float, Ray, Vec3};
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// The protocol is little endian binary over TCP. Workers connect to the
+// coordinator, which sends magic, version and the job, then one tile at a
+// time as 1, x, y, width and height, or 0 when the image is done. Workers
+// answer each tile with its position and size, the radiance sum of its
+// pixels and its splats as a count followed by pixel index and radiance.
+// Radiance is sent as f64 whatever the precision of the build.
+const MAGIC: &[u8; 4] = b"RTDR";
+const VERSION: u32 = 1;
+
+// What the workers render: a frame of a scene description, with the options
+// that change the image. Files the scene refers to must be found by the
+// workers at the same paths.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderJob {
+    pub scene: String,
+    pub frame: u64,
+    pub integrator: Option<String>,
+    pub spectral: bool,
+    pub seed: u64,
+    pub spp: u32,
+}
+impl RenderJob {
+    // The scene with its integrator preprocessed, alike on every worker.
+    pub fn load(&self) -> Result<Scene, String> {
+        let mut scene = Scene::parse_frame(&self.scene, self.frame as Float)?;
+        if let Some(ref spec) = self.integrator {
+            scene.integrator = parse_integrator_spec(spec)?;
+        }
+        seed_random(self.seed);
+        scene.integrator.preprocess(&scene);
+        Ok(scene)
+    }
+    fn encode(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        put_u32(&mut data, VERSION);
+        put_str(&mut data, &self.scene);
+        put_u64(&mut data, self.frame);
+        data.push(self.integrator.is_some() as u8);
+        put_str(&mut data, self.integrator.as_deref().unwrap_or(""));
+        data.push(self.spectral as u8);
+        put_u64(&mut data, self.seed);
+        put_u32(&mut data, self.spp);
+        data
+    }
+    fn decode<R: Read>(input: &mut R) -> Result<Self, String> {
+        let mut magic = [0; 4];
+        read(input, &mut magic)?;
+        if &magic != MAGIC {
+            return Err("not a ray_tracer coordinator".to_string());
+        }
+        if get_u32(input)? != VERSION {
+            return Err("unsupported protocol version".to_string());
+        }
+        let scene = get_str(input)?;
+        let frame = get_u64(input)?;
+        let has_integrator = get_u8(input)? != 0;
+        let integrator = get_str(input)?;
+        Ok(Self {
+            scene,
+            frame,
+            integrator: if has_integrator {
+                Some(integrator)
+            } else {
+                None
+            },
+            spectral: get_u8(input)? != 0,
+            seed: get_u64(input)?,
+            spp: get_u32(input)?,
+        })
+    }
+}
+
+// Radiance along a camera ray, sampling wavelengths in spectral mode.
+pub fn radiance(scene: &Scene, spectral: bool, r: &mut Ray) -> Vec3 {
+    if spectral {
+        let mut wl = SampledWavelengths::sample_uniform(random());
+        scene.integrator.li_spectral(r, scene, &mut wl).to_rgb(&wl)
+    } else {
+        scene.integrator.li(r, scene)
+    }
+}
+
+// What happened to the workers of a distributed render.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Progress {
+    Connected(SocketAddr),
+    Rendered(SocketAddr, Tile),
+    // the tile the worker had is handed out again
+    Failed(SocketAddr, String),
+}
+
+// Hands out the tiles of an image to workers connecting on the listener and
+// merges what they return. Tiles of workers that disconnect, send something
+// else or take longer than the timeout are given to other workers.
+pub struct Coordinator {
+    listener: TcpListener,
+    tile_size: usize,
+    timeout: Duration,
+    workers: usize,
+}
+impl Coordinator {
+    pub fn new(listener: TcpListener) -> Self {
+        Self {
+            listener,
+            tile_size: 32,
+            timeout: Duration::from_secs(600),
+            workers: 1,
+        }
+    }
+    pub fn with_tile_size(mut self, tile_size: usize) -> Self {
+        self.tile_size = tile_size.max(1);
+        self
+    }
+    // How long a worker may take to render a tile, zero for no limit.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    // How many workers must connect before tiles are handed out.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(|e| e.to_string())
+    }
+    // The film of the job rendered in a single pass, once every tile is
+    // done. Waits for as long as no worker connects.
+    pub fn render<F>(
+        &self,
+        job: &RenderJob,
+        width: usize,
+        height: usize,
+        progress: F,
+    ) -> Result<Film, String>
+    where
+        F: Fn(Progress) + Sync,
+    {
+        let tiles = Tile::split(width, height, self.tile_size);
+        let total = tiles.len();
+        let queue = Mutex::new(VecDeque::from(tiles));
+        let done = AtomicBool::new(false);
+        let setup = job.encode();
+        let (results, received) = mpsc::channel();
+        self.listener
+            .set_nonblocking(true)
+            .map_err(|e| e.to_string())?;
+        let mut film = Film::new(width, height);
+        let (queue, done, setup, progress) = (&queue, &done, &setup, &progress);
+        thread::scope(|s| {
+            s.spawn(move || {
+                let (mut waiting, mut wait_for) = (vec![], self.workers);
+                // workers still waiting to be accepted when the image is
+                // done are told so rather than left hanging
+                loop {
+                    let connection = match self.listener.accept() {
+                        Ok(connection) => connection,
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                            if done.load(Ordering::Relaxed) {
+                                break;
+                            }
+                            thread::sleep(Duration::from_millis(10));
+                            continue;
+                        }
+                        Err(_) => continue,
+                    };
+                    progress(Progress::Connected(connection.1));
+                    waiting.push(connection);
+                    if waiting.len() < wait_for {
+                        continue;
+                    }
+                    wait_for = 0;
+                    for (stream, addr) in waiting.drain(..) {
+                        let results = results.clone();
+                        let (size, timeout) = ((width, height), self.timeout);
+                        s.spawn(move || {
+                            let served = serve(stream, setup, queue, done, size, timeout, &|b| {
+                                progress(Progress::Rendered(addr, b.tile));
+                                results.send(b).is_ok()
+                            });
+                            if let Err(e) = served {
+                                progress(Progress::Failed(addr, e));
+                            }
+                        });
+                    }
+                }
+            });
+            for _ in 0..total {
+                film.add_tile(&received.recv().unwrap());
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        Ok(film)
+    }
+}
+
+// Feeds tiles to one worker until all are done, putting back the one it has
+// if it fails.
+fn serve(
+    stream: TcpStream,
+    setup: &[u8],
+    queue: &Mutex<VecDeque<Tile>>,
+    done: &AtomicBool,
+    size: (usize, usize),
+    timeout: Duration,
+    rendered: &dyn Fn(TileBuffer) -> bool,
+) -> Result<(), String> {
+    let io = |e: std::io::Error| e.to_string();
+    stream.set_nonblocking(false).map_err(io)?;
+    // a zero timeout is an error to the socket
+    let timeout = Some(timeout).filter(|t| !t.is_zero());
+    stream.set_read_timeout(timeout).map_err(io)?;
+    stream.set_nodelay(true).map_err(io)?;
+    let mut input = BufReader::new(stream.try_clone().map_err(io)?);
+    let mut output = stream;
+    output.write_all(setup).map_err(io)?;
+    loop {
+        let tile = loop {
+            if done.load(Ordering::Relaxed) {
+                // the worker may already be gone
+                let _ = output.write_all(&[0]);
+                return Ok(());
+            }
+            if let Some(tile) = queue.lock().unwrap().pop_front() {
+                break tile;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let mut request = vec![1];
+        for v in [tile.x, tile.y, tile.width, tile.height] {
+            put_u32(&mut request, v as u32);
+        }
+        let buffer = output
+            .write_all(&request)
+            .map_err(io)
+            .and_then(|_| read_tile(&mut input, size));
+        match buffer {
+            Ok(buffer) if buffer.tile == tile => {
+                if !rendered(buffer) {
+                    return Ok(());
+                }
+            }
+            other => {
+                queue.lock().unwrap().push_back(tile);
+                return Err(match other {
+                    Err(e) => e,
+                    Ok(_) => "returned another tile".to_string(),
+                });
+            }
+        }
+    }
+}
+
+// Connects to a coordinator and renders the tiles it hands out until the
+// image is done.
+pub fn work(addr: &str, threads: Option<usize>) -> Result<(), String> {
+    let io = |e: std::io::Error| format!("{}: {}", addr, e);
+    let stream = TcpStream::connect(addr).map_err(io)?;
+    stream.set_nodelay(true).map_err(io)?;
+    let mut input = BufReader::new(stream.try_clone().map_err(io)?);
+    let mut output = stream;
+    let job = RenderJob::decode(&mut input)?;
+    let scene = job.load()?;
+    let mut renderer = Renderer::new(scene.width, scene.height, job.seed);
+    if let Some(threads) = threads {
+        renderer.threads = threads;
+    }
+    loop {
+        match get_u8(&mut input)? {
+            0 => return Ok(()),
+            1 => {
+                let tile = get_tile(&mut input)?;
+                if tile.x + tile.width > scene.width || tile.y + tile.height > scene.height {
+                    return Err("tile outside of the image".to_string());
+                }
+                let buffer = renderer.render_tile(&*scene.camera, tile, job.spp, &|r| {
+                    radiance(&scene, job.spectral, r)
+                });
+                output.write_all(&encode_tile(&buffer)).map_err(io)?;
+            }
+            _ => return Err("unexpected message".to_string()),
+        }
+    }
+}
+
+fn encode_tile(buffer: &TileBuffer) -> Vec<u8> {
+    let tile = buffer.tile;
+    let mut data = vec![];
+    for v in [tile.x, tile.y, tile.width, tile.height] {
+        put_u32(&mut data, v as u32);
+    }
+    put_u32(&mut data, buffer.spp);
+    for v in &buffer.sum {
+        put_vec3(&mut data, v);
+    }
+    put_u32(&mut data, buffer.splats.len() as u32);
+    for (i, v) in &buffer.splats {
+        put_u32(&mut data, *i as u32);
+        put_vec3(&mut data, v);
+    }
+    data
+}
+
+// A tile from a worker, checked to fit an image of the given size.
+fn read_tile<R: Read>(
+    input: &mut R,
+    (width, height): (usize, usize),
+) -> Result<TileBuffer, String> {
+    let tile = get_tile(input)?;
+    if tile.x + tile.width > width || tile.y + tile.height > height {
+        return Err("tile outside of the image".to_string());
+    }
+    let spp = get_u32(input)?;
+    let sum = (0..tile.width * tile.height)
+        .map(|_| get_vec3(input))
+        .collect::<Result<_, _>>()?;
+    let count = get_u32(input)? as usize;
+    let mut splats = Vec::with_capacity(count.min(width * height));
+    for _ in 0..count {
+        let i = get_u32(input)? as usize;
+        if i >= width * height {
+            return Err("splat outside of the image".to_string());
+        }
+        splats.push((i, get_vec3(input)?));
+    }
+    Ok(TileBuffer {
+        tile,
+        spp,
+        sum,
+        splats,
+    })
+}
+
+fn get_tile<R: Read>(input: &mut R) -> Result<Tile, String> {
+    Ok(Tile {
+        x: get_u32(input)? as usize,
+        y: get_u32(input)? as usize,
+        width: get_u32(input)? as usize,
+        height: get_u32(input)? as usize,
+    })
+}
+
+fn put_u32(data: &mut Vec<u8>, v: u32) {
+    data.extend_from_slice(&v.to_le_bytes());
+}
+fn put_u64(data: &mut Vec<u8>, v: u64) {
+    data.extend_from_slice(&v.to_le_bytes());
+}
+fn put_str(data: &mut Vec<u8>, s: &str) {
+    put_u32(data, s.len() as u32);
+    data.extend_from_slice(s.as_bytes());
+}
+fn put_vec3(data: &mut Vec<u8>, v: &Vec3) {
+    for c in 0..3 {
+        // a no-op in f64 builds
+        #[allow(clippy::unnecessary_cast)]
+        data.extend_from_slice(&(v[c] as f64).to_le_bytes());
+    }
+}
+
+fn read<R: Read>(input: &mut R, buf: &mut [u8]) -> Result<(), String> {
+    input.read_exact(buf).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => "connection closed".to_string(),
+        _ => e.to_string(),
+    })
+}
+fn get_u8<R: Read>(input: &mut R) -> Result<u8, String> {
+    let mut buf = [0; 1];
+    read(input, &mut buf)?;
+    Ok(buf[0])
+}
+fn get_u32<R: Read>(input: &mut R) -> Result<u32, String> {
+    let mut buf = [0; 4];
+    read(input, &mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+fn get_u64<R: Read>(input: &mut R) -> Result<u64, String> {
+    let mut buf = [0; 8];
+    read(input, &mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+fn get_str<R: Read>(input: &mut R) -> Result<String, String> {
+    let len = get_u32(input)? as usize;
+    let mut buf = vec![];
+    input
+        .take(len as u64)
+        .read_to_end(&mut buf)
+        .map_err(|e| e.to_string())?;
+    if buf.len() != len {
+        return Err("connection closed".to_string());
+    }
+    String::from_utf8(buf).map_err(|_| "invalid text".to_string())
+}
+fn get_vec3<R: Read>(input: &mut R) -> Result<Vec3, String> {
+    let mut buf = [0; 24];
+    read(input, &mut buf)?;
+    let f = |i: usize| f64::from_le_bytes(buf[i..i + 8].try_into().unwrap()) as Float;
+    Ok(Vec3::new(f(0), f(8), f(16)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{work, Coordinator, Progress, RenderJob, Renderer, Tile};
+    use crate::geometry::Vec3;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+    #[test]
+    fn tiles_cover_the_image() {
+        let tiles = Tile::split(10, 7, 4);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles.iter().map(|t| t.width * t.height).sum::<usize>(), 70);
+        assert_eq!(
+            tiles[5],
+            Tile {
+                x: 8,
+                y: 4,
+                width: 2,
+                height: 3
+            }
+        );
+    }
+    fn job() -> RenderJob {
+        RenderJob {
+            scene: "image width=20 height=12\n\
+                    camera look_from=0,1,5 look_at=0,0.5,0 fov=40\n\
+                    material ground albedo=0.5,0.5,0.5\n\
+                    material red albedo=0.7,0.2,0.1\n\
+                    sphere center=0,-1000,0 radius=1000 material=ground\n\
+                    sphere center=0,0.5,0 radius=0.5 material=red\n\
+                    light point position=-1.5,1.5,1 intensity=4,3,2\n\
+                    integrator type=bdpt max_depth=3\n"
+                .to_string(),
+            frame: 0,
+            integrator: None,
+            spectral: false,
+            seed: 7,
+            spp: 4,
+        }
+    }
+    #[test]
+    fn workers_render_the_pass() {
+        let job = job();
+        let scene = job.load().unwrap();
+        let mut local = Renderer::new(20, 12, 7);
+        local.render_pass(&*scene.camera, 4, &|r| scene.integrator.li(r, &scene));
+        let coordinator = Coordinator::new(TcpListener::bind("127.0.0.1:0").unwrap())
+            .with_tile_size(8)
+            .with_timeout(Duration::from_secs(5));
+        let addr = coordinator.local_addr().unwrap().to_string();
+        let failures = Mutex::new(vec![]);
+        // a worker that takes a tile and leaves, whose tile must be rendered
+        // by the others
+        let mut leaving = TcpStream::connect(&addr).unwrap();
+        let mut setup_and_tile = vec![0; job.encode().len() + 17];
+        let film = thread::scope(|s| {
+            s.spawn(move || {
+                leaving.read_exact(&mut setup_and_tile).unwrap();
+                leaving.write_all(&[0; 4]).unwrap();
+            });
+            let workers: Vec<_> = (0..2).map(|_| s.spawn(|| work(&addr, Some(2)))).collect();
+            let film = coordinator
+                .render(&job, 20, 12, |p| {
+                    if let Progress::Failed(_, e) = p {
+                        failures.lock().unwrap().push(e);
+                    }
+                })
+                .unwrap();
+            // a worker connecting after this finds no coordinator
+            drop(coordinator);
+            let worked: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+            assert!(worked.iter().any(Result::is_ok), "{:?}", worked);
+            film
+        });
+        assert_eq!(failures.into_inner().unwrap(), ["connection closed"]);
+        assert_eq!(film.sum, local.film.sum);
+        assert_eq!(film.samples, local.film.samples);
+        for (a, b) in film.splats.iter().zip(&local.film.splats) {
+            assert!((*a - *b).length() <= 1e-4 * b.length(), "{:?} {:?}", a, b);
+        }
+        assert!(film.splats.iter().any(|s| *s != Vec3::new(0.0, 0.0, 0.0)));
+    }
+    #[test]
+    fn zero_timeout_is_no_limit() {
+        let coordinator = Coordinator::new(TcpListener::bind("127.0.0.1:0").unwrap())
+            .with_timeout(Duration::ZERO);
+        let addr = coordinator.local_addr().unwrap().to_string();
+        let failures = Mutex::new(vec![]);
+        let film = thread::scope(|s| {
+            let worker = s.spawn(|| work(&addr, Some(1)));
+            let film = coordinator.render(&job(), 20, 12, |p| {
+                if let Progress::Failed(_, e) = p {
+                    failures.lock().unwrap().push(e);
+                }
+            });
+            assert_eq!(worker.join().unwrap(), Ok(()));
+            film.unwrap()
+        });
+        assert!(failures.into_inner().unwrap().is_empty());
+        assert_eq!(film.samples.iter().sum::<u32>(), 20 * 12 * 4);
+    }
+}
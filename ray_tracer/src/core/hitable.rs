@@ -1,8 +1,11 @@
-use crate::geometry::{dot, Ray, Vec3};
-use std::f32;
-use std::rc::Rc;
+use crate::core::stats::{count, Counter};
+use crate::core::{Interior, SampledSpectrum, SampledWavelengths};
+use crate::geometry::{
+    consts, dot, gamma, offset_ray_origin, quadratic, Aabb, EFloat, Float, Ray, Vec3,
+};
+use std::sync::Arc;
 
-pub trait Material {
+pub trait Material: Send + Sync {
     fn scatter(
         &self,
         r_in: &Ray,
@@ -10,27 +13,148 @@ pub trait Material {
         attenuation: &mut Vec3,
         scattered: &mut Ray,
     ) -> bool;
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wl: &mut SampledWavelengths,
+        attenuation: &mut SampledSpectrum,
+        scattered: &mut Ray,
+    ) -> bool {
+        let mut rgb = Vec3::new(0.0, 0.0, 0.0);
+        let scatters = self.scatter(r_in, rec, &mut rgb, scattered);
+        *attenuation = SampledSpectrum::from_rgb(&rgb, wl);
+        scatters
+    }
+    fn interior(&self) -> Option<&Interior> {
+        None
+    }
+    // BSDF (or phase function) times the cosine at the shading normal, for
+    // light arriving from the unit direction wi. None for specular materials,
+    // which only scatter into directions they sample themselves.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _wi: &Vec3) -> Option<Vec3> {
+        None
+    }
+    // Solid angle density of scatter sampling wi, zero for specular materials.
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _wi: &Vec3) -> Float {
+        0.0
+    }
+    // Radiance given off at the hit point, towards the incoming ray.
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+    // Index in the scene lights of the light this material is the surface of.
+    fn light_index(&self) -> Option<usize> {
+        None
+    }
+    // Scatters inside a volume, where directions are not foreshortened by a
+    // surface.
+    fn is_volumetric(&self) -> bool {
+        false
+    }
+    // The normal eval and scatter shade with at rec.
+    fn shading_normal(&self, _r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        rec.shading_normal
+    }
 }
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Vec3,
+    // bound on the absolute rounding error of p
+    pub p_error: Vec3,
+    // normal of the actual surface, used to leave it and to tell sides
     pub normal: Vec3,
-    pub t: f32,
-    pub mat: Option<Rc<dyn Material>>,
+    // normal materials shade with, on the same side as the geometric one
+    pub shading_normal: Vec3,
+    pub t: Float,
+    // surface coordinates in [0, 1]
+    pub u: Float,
+    pub v: Float,
+    // derivatives of p along u and v, zero when the surface has no tangents
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub mat: Option<Arc<dyn Material>>,
+    // index of refraction on the other side of the surface
+    pub outer_ior: Float,
 }
 impl HitRecord {
     pub fn new() -> Self {
         Self {
-            p: Vec3::new(0f32, 0f32, 0f32),
-            normal: Vec3::new(0f32, 0f32, 0f32),
-            t: 0f32,
+            p: Vec3::new(0.0, 0.0, 0.0),
+            p_error: Vec3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            shading_normal: Vec3::new(0.0, 0.0, 0.0),
+            t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
             mat: None,
+            outer_ior: 1.0,
+        }
+    }
+    // A ray leaving the hit point in direction d that cannot hit the same
+    // surface again at t = 0.
+    pub fn spawn_ray(&self, d: Vec3) -> Ray {
+        Ray::new(
+            offset_ray_origin(&self.p, &self.p_error, &self.normal, &d),
+            d,
+        )
+    }
+    // Whether d is on the same side of the surface for both normals. Other
+    // directions would let light through the surface.
+    pub fn is_consistent(&self, d: &Vec3) -> bool {
+        dot(d, &self.normal) * dot(d, &self.shading_normal) > 0.0
+    }
+}
+impl Default for HitRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A stretch of a ray inside a solid, between the surface hits where the ray
+// enters and leaves it. Normals point out of the solid at both ends.
+#[derive(Clone)]
+pub struct Span {
+    pub enter: HitRecord,
+    pub exit: HitRecord,
+}
+
+pub trait Hitable: Send + Sync {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool;
+    // None for unbounded objects
+    fn bounding_box(&self) -> Option<Aabb>;
+    // The spans of the whole line of the ray inside the object, in order.
+    // None for objects that are not closed solids, which CSG cannot combine.
+    fn spans(&self, _ray: &Ray) -> Option<Vec<Span>> {
+        None
+    }
+    // Fraction of light that gets through along the ray between t_min and
+    // t_max. Surfaces block it, participating media may let some through.
+    fn transmittance(&self, ray: &Ray, t_min: Float, t_max: Float) -> Float {
+        if self.hit(ray, t_min, t_max, &mut HitRecord::new()) {
+            0.0
+        } else {
+            1.0
         }
     }
 }
 
-pub trait Hitable {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool;
+impl<H: Hitable + ?Sized> Hitable for Box<H> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        (**self).hit(ray, t_min, t_max, rec)
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span>> {
+        (**self).spans(ray)
+    }
+    fn transmittance(&self, ray: &Ray, t_min: Float, t_max: Float) -> Float {
+        (**self).transmittance(ray, t_min, t_max)
+    }
 }
 
 pub struct HitList {
@@ -41,59 +165,218 @@ impl HitList {
         Self { list: vec![] }
     }
 }
+impl Default for HitList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl Hitable for HitList {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
-        let mut temp_rec = HitRecord {
-            mat: rec.mat.clone(),
-            ..*rec
-        };
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        let mut temp_rec = rec.clone();
         let mut hit_anything = false;
         let mut closest_so_far = t_max;
         for h in self.list.iter() {
-            if h.hit(ray, t_min, closest_so_far, &mut temp_rec) == true {
+            if h.hit(ray, t_min, closest_so_far, &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
-                rec.normal = temp_rec.normal;
-                rec.p = temp_rec.p;
-                rec.t = temp_rec.t;
-                rec.mat = temp_rec.mat.clone();
+                *rec = temp_rec.clone();
             }
         }
         hit_anything
     }
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut boxes = self.list.iter().map(|h| h.bounding_box());
+        let first = boxes.next()??;
+        boxes.try_fold(first, |acc, b| Some(acc.union(&b?)))
+    }
+    fn transmittance(&self, ray: &Ray, t_min: Float, t_max: Float) -> Float {
+        let mut tr = 1.0;
+        for h in self.list.iter() {
+            tr *= h.transmittance(ray, t_min, t_max);
+            if tr == 0.0 {
+                break;
+            }
+        }
+        tr
+    }
 }
 
 #[derive(Clone)]
 pub struct SphereObject {
     pub center: Vec3,
-    pub radius: f32,
-    pub mat: Option<Rc<dyn Material>>,
+    pub radius: Float,
+    pub mat: Option<Arc<dyn Material>>,
 }
 impl Hitable for SphereObject {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        count(Counter::IntersectionTests, 1);
+        // plain floating point test with a generous tolerance, which rejects
+        // most spheres before the exact one
         let oc = ray.o - self.center;
         let a = dot(&ray.d, &ray.d);
         let b = dot(&oc, &ray.d);
-        let c = dot(&oc, &oc) - self.radius * self.radius;
-        let discriminant = b * b - a * c;
-        if discriminant > 0f32 {
-            let mut temp = (-b - (b * b - a * c).sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                rec.t = temp;
-                rec.p = ray.point_at_parameter(rec.t);
-                rec.normal = (rec.p - self.center) / self.radius;
-                rec.mat = self.mat.clone();
-                return true;
-            }
-            temp = (-b + (b * b - a * c).sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                rec.t = temp;
-                rec.p = ray.point_at_parameter(rec.t);
-                rec.normal = (rec.p - self.center) / self.radius;
-                rec.mat = self.mat.clone();
-                return true;
-            }
+        let (oc2, r2) = (dot(&oc, &oc), self.radius * self.radius);
+        let e = 64.0 * Float::EPSILON;
+        let discriminant = b * b - a * (oc2 - r2) + e * (b * b + a * (oc2 + r2));
+        if discriminant < 0.0 {
+            return false;
         }
-        false
+        let root = discriminant.sqrt();
+        let err = e * (b.abs() + root) / a;
+        if (-b + root) / a + err <= t_min || (-b - root) / a - err >= t_max {
+            return false;
+        }
+        self.intersect(ray, t_min, t_max, rec)
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span>> {
+        let (t0, t1) = match self.roots(ray) {
+            Some((t0, t1)) if t0.v < t1.v => (t0, t1),
+            _ => return Some(vec![]),
+        };
+        let mut span = Span {
+            enter: HitRecord::new(),
+            exit: HitRecord::new(),
+        };
+        self.record(ray, t0.v, &mut span.enter);
+        self.record(ray, t1.v, &mut span.exit);
+        Some(vec![span])
+    }
+}
+impl SphereObject {
+    // Roots are computed with error bounds and only accepted when they are
+    // certainly inside (t_min, t_max).
+    pub fn intersect(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        let (t0, t1) = match self.roots(ray) {
+            Some(roots) => roots,
+            None => return false,
+        };
+        if t0.upper_bound() >= t_max || t1.lower_bound() <= t_min {
+            return false;
+        }
+        let t = if t0.lower_bound() > t_min {
+            t0
+        } else if t1.upper_bound() < t_max {
+            t1
+        } else {
+            return false;
+        };
+        self.record(ray, t.v, rec);
+        true
+    }
+    fn roots(&self, ray: &Ray) -> Option<(EFloat, EFloat)> {
+        let o = [0, 1, 2].map(|i| EFloat::from(ray.o[i]) - EFloat::from(self.center[i]));
+        let d = [0, 1, 2].map(|i| EFloat::from(ray.d[i]));
+        let a = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+        let b = EFloat::from(2.0) * (d[0] * o[0] + d[1] * o[1] + d[2] * o[2]);
+        let r = EFloat::from(self.radius);
+        let c = o[0] * o[0] + o[1] * o[1] + o[2] * o[2] - r * r;
+        quadratic(a, b, c)
+    }
+    fn record(&self, ray: &Ray, t: Float, rec: &mut HitRecord) {
+        // reprojects the hit point onto the surface, see pbrt 3.9.4
+        let mut p_rel = ray.point_at_parameter(t) - self.center;
+        p_rel = p_rel * (self.radius / p_rel.length());
+        rec.t = t;
+        rec.p = self.center + p_rel;
+        rec.p_error = Vec3::new(
+            gamma(5) * p_rel.x.abs() + gamma(1) * rec.p.x.abs(),
+            gamma(5) * p_rel.y.abs() + gamma(1) * rec.p.y.abs(),
+            gamma(5) * p_rel.z.abs() + gamma(1) * rec.p.z.abs(),
+        );
+        rec.normal = p_rel / self.radius;
+        rec.shading_normal = rec.normal;
+        // u around the y axis from -x, v from the bottom
+        rec.u = (Float::atan2(-rec.normal.z, rec.normal.x) + consts::PI) / (2.0 * consts::PI);
+        rec.v = (-rec.normal.y).clamp(-1.0, 1.0).acos() / consts::PI;
+        // derivatives of p(u, v) = r (sin(pi v) cos(phi), -cos(pi v), -sin(pi v) sin(phi))
+        // with phi = 2 pi u - pi, degenerate at the poles
+        let rho = (p_rel.x * p_rel.x + p_rel.z * p_rel.z).sqrt();
+        rec.dpdu = 2.0 * consts::PI * Vec3::new(p_rel.z, 0.0, -p_rel.x);
+        rec.dpdv = if rho > 0.0 {
+            consts::PI * Vec3::new(-p_rel.y * p_rel.x / rho, rho, -p_rel.y * p_rel.z / rho)
+        } else {
+            Vec3::new(0.0, 0.0, 0.0)
+        };
+        rec.mat = self.mat.clone();
+    }
+}
+
+// Checks that rays leaving a hit to either side of the surface, spread by
+// `spread` around its normal, travel further than `clearance` before hitting
+// the object again.
+#[cfg(test)]
+pub(crate) fn assert_no_self_intersection(
+    object: &dyn Hitable,
+    rec: &HitRecord,
+    spread: Float,
+    clearance: Float,
+) {
+    use crate::geometry::{normalize, random_in_unit_sphere};
+    for n in [rec.normal, -1.0 * rec.normal] {
+        let d = n + spread * normalize(random_in_unit_sphere());
+        let mut next = HitRecord::new();
+        if object.hit(&rec.spawn_ray(d), 0.0, Float::INFINITY, &mut next) {
+            assert!(next.t * d.length() > clearance, "self intersection");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{HitList, HitRecord, Hitable, SphereObject};
+    use crate::geometry::{
+        dot, normalize, random, random_in_unit_sphere, seed_random, Float, Ray, Vec3,
+    };
+    fn sphere(center: Vec3, radius: Float) -> SphereObject {
+        SphereObject {
+            center,
+            radius,
+            mat: None,
+        }
+    }
+    #[test]
+    fn spawned_rays_do_not_self_intersect() {
+        seed_random(2);
+        let s = sphere(Vec3::new(1e4, -1e3, 0.0), 1e3);
+        for _ in 0..10000 {
+            let o = Vec3::new(1e4, 0.0, 0.0) + 50.0 * random_in_unit_sphere();
+            let r = Ray::new(o, Vec3::new(random() - 0.5, -1.0, random() - 0.5));
+            let mut rec = HitRecord::new();
+            assert!(s.hit(&r, 0.0, Float::INFINITY, &mut rec));
+            // leaving the surface never hits it again
+            let out = rec.normal + 0.9 * normalize(random_in_unit_sphere());
+            assert!(!s.hit(
+                &rec.spawn_ray(out),
+                0.0,
+                Float::INFINITY,
+                &mut HitRecord::new()
+            ));
+            // entering it hits the far side
+            let mut far = HitRecord::new();
+            let d = -1.0 * rec.normal + 0.9 * normalize(random_in_unit_sphere());
+            assert!(s.hit(&rec.spawn_ray(d), 0.0, Float::INFINITY, &mut far));
+            let chord = -2.0 * 1e3 * dot(&normalize(d), &rec.normal);
+            assert!((far.t * d.length() - chord).abs() < 1.0);
+        }
+    }
+    #[test]
+    fn thin_shells_are_not_skipped() {
+        let mut world = HitList::new();
+        world
+            .list
+            .push(Box::new(sphere(Vec3::new(0.0, 0.0, 0.0), 1.0)));
+        world
+            .list
+            .push(Box::new(sphere(Vec3::new(0.0, 0.0, 0.0), 1.0 + 1e-4)));
+        let r = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::new();
+        assert!(world.hit(&r, 0.0, Float::INFINITY, &mut rec));
+        let mut inner = HitRecord::new();
+        assert!(world.hit(&rec.spawn_ray(r.d), 0.0, Float::INFINITY, &mut inner));
+        assert!((inner.t - 1e-4).abs() < 1e-5);
     }
 }
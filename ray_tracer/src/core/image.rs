@@ -0,0 +1,227 @@
+use crate::geometry::{Float, Vec3};
+use std::fs;
+
+// Linear RGB image, row 0 at the top.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Vec3::new(0.0, 0.0, 0.0); width * height],
+        }
+    }
+    // False color visualization of per pixel values, scaled to their maximum.
+    pub fn heatmap(values: &[Float], width: usize, height: usize) -> Self {
+        let max = values.iter().cloned().fold(0.0, Float::max);
+        Self {
+            width,
+            height,
+            pixels: values
+                .iter()
+                .map(|v| false_color(if max > 0.0 { v / max } else { 0.0 }))
+                .collect(),
+        }
+    }
+    pub fn get(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+    pub fn set(&mut self, x: usize, y: usize, v: Vec3) {
+        self.pixels[y * self.width + x] = v;
+    }
+    // Scaled to the given size, averaging the pixels under each new one.
+    pub fn resize(&self, width: usize, height: usize) -> Self {
+        let mut resized = Self::new(width, height);
+        let span = |i: usize, from: usize, to: usize| {
+            let start = i * from / to;
+            start..((i + 1) * from / to).max(start + 1)
+        };
+        for y in 0..height {
+            for x in 0..width {
+                let (mut sum, mut n) = (Vec3::new(0.0, 0.0, 0.0), 0.0);
+                for sy in span(y, self.height, height) {
+                    for sx in span(x, self.width, width) {
+                        sum += self.get(sx, sy);
+                        n += 1.0;
+                    }
+                }
+                resized.set(x, y, sum / n);
+            }
+        }
+        resized
+    }
+    pub fn luminance(&self) -> Vec<Float> {
+        self.pixels
+            .iter()
+            .map(|p| 0.2126 * p.x + 0.7152 * p.y + 0.0722 * p.z)
+            .collect()
+    }
+    // ASCII (P3) netpbm image, values clamped to [0,1] and quantized to 8 bits.
+    pub fn to_ppm(&self) -> String {
+        let mut s = format!("P3\n{} {}\n255\n", self.width, self.height);
+        for p in self.pixels.iter() {
+            let q = |c: Float| (255.99 * c.clamp(0.0, 1.0)) as i32;
+            s += &format!("{} {} {}\n", q(p.x), q(p.y), q(p.z));
+        }
+        s
+    }
+    // Writes through a temporary file so that readers never see a partial image.
+    pub fn write_ppm(&self, path: &str) -> Result<(), String> {
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, self.to_ppm()).map_err(|e| format!("{}: {}", tmp, e))?;
+        fs::rename(&tmp, path).map_err(|e| format!("{}: {}", path, e))
+    }
+    // Reads ASCII (P2, P3) and binary (P5, P6) netpbm images.
+    pub fn read_pnm(path: &str) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse_pnm(&data).map_err(|e| format!("{}: {}", path, e))
+    }
+    pub fn parse_pnm(data: &[u8]) -> Result<Self, String> {
+        // header: magic, width, height, maxval separated by whitespace/comments
+        let mut pos = 0;
+        let mut header: Vec<String> = vec![];
+        while header.len() < 4 {
+            while pos < data.len() && (data[pos] as char).is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < data.len() && data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            let start = pos;
+            while pos < data.len() && !(data[pos] as char).is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err("truncated header".to_string());
+            }
+            header.push(String::from_utf8_lossy(&data[start..pos]).to_string());
+        }
+        let number = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|_| format!("invalid header '{}'", s))
+        };
+        let (width, height, maxval) = (
+            number(&header[1])?,
+            number(&header[2])?,
+            number(&header[3])?,
+        );
+        if maxval == 0 || maxval > 65535 {
+            return Err(format!("invalid maximum value {}", maxval));
+        }
+        let channels = match header[0].as_str() {
+            "P2" | "P5" => 1,
+            "P3" | "P6" => 3,
+            m => return Err(format!("unsupported format '{}'", m)),
+        };
+        let count = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(channels))
+            .ok_or_else(|| format!("image of {}x{} pixels is too large", width, height))?;
+        let samples: Vec<Float> = match header[0].as_str() {
+            "P2" | "P3" => String::from_utf8_lossy(&data[pos..])
+                .split_whitespace()
+                .take(count)
+                .map(|s| {
+                    s.parse::<Float>()
+                        .map_err(|_| format!("invalid sample '{}'", s))
+                })
+                .collect::<Result<_, _>>()?,
+            _ => {
+                let body = &data[(pos + 1).min(data.len())..];
+                if maxval < 256 {
+                    body.iter().take(count).map(|b| *b as Float).collect()
+                } else {
+                    body.chunks(2)
+                        .take(count)
+                        .map(|b| (b[0] as u32 * 256 + *b.get(1).unwrap_or(&0) as u32) as Float)
+                        .collect()
+                }
+            }
+        };
+        if samples.len() < count {
+            return Err("truncated pixel data".to_string());
+        }
+        let scale = 1.0 / maxval as Float;
+        let pixels = samples
+            .chunks(channels)
+            .map(|c| match c.len() {
+                1 => Vec3::new(c[0], c[0], c[0]) * scale,
+                _ => Vec3::new(c[0], c[1], c[2]) * scale,
+            })
+            .collect();
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+// Black -> blue -> magenta -> red -> yellow -> white ramp over [0,1].
+pub fn false_color(t: Float) -> Vec3 {
+    let stops = [
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 1.0),
+        Vec3::new(1.0, 0.0, 1.0),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(1.0, 1.0, 0.0),
+        Vec3::new(1.0, 1.0, 1.0),
+    ];
+    let x = t.clamp(0.0, 1.0) * (stops.len() - 1) as Float;
+    let i = (x as usize).min(stops.len() - 2);
+    let f = x - i as Float;
+    (1.0 - f) * stops[i] + f * stops[i + 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Image;
+    use crate::geometry::{Float, Vec3};
+    #[test]
+    fn parse_ascii_and_binary() {
+        let a = Image::parse_pnm(b"P2\n# mask\n2 1\n255\n0 255\n").unwrap();
+        let mut binary = b"P5 2 1 255\n".to_vec();
+        binary.extend_from_slice(&[0, 255]);
+        let b = Image::parse_pnm(&binary).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a.luminance(), vec![0.0, 1.0]);
+        assert!(Image::parse_pnm(b"P3 2 2 255 1 2 3").is_err());
+        assert!(Image::parse_pnm(b"P2 1 1 0 0").is_err());
+        assert!(Image::parse_pnm(b"P2 1 1 65536 0").is_err());
+        let huge = format!("P3 {} {} 255 0 0 0", usize::MAX, 2);
+        assert!(Image::parse_pnm(huge.as_bytes())
+            .unwrap_err()
+            .ends_with("too large"));
+    }
+    #[test]
+    fn ppm_round_trip() {
+        let mut image = Image::new(2, 1);
+        image.set(0, 0, Vec3::new(1.0, 0.0, 2.0));
+        let parsed = Image::parse_pnm(image.to_ppm().as_bytes()).unwrap();
+        assert_eq!(parsed.get(0, 0), Vec3::new(1.0, 0.0, 1.0));
+        assert_eq!(parsed.get(1, 0), Vec3::new(0.0, 0.0, 0.0));
+    }
+    #[test]
+    fn resize_averages_pixels() {
+        let mut image = Image::new(4, 2);
+        for x in 0..4 {
+            image.set(x, 0, Vec3::new(x as Float, 0.0, 1.0));
+        }
+        let small = image.resize(2, 1);
+        assert_eq!(small.get(0, 0), Vec3::new(0.25, 0.0, 0.5));
+        assert_eq!(small.get(1, 0), Vec3::new(1.25, 0.0, 0.5));
+        // larger sizes repeat pixels
+        let large = image.resize(8, 3);
+        assert_eq!(large.get(7, 0), Vec3::new(3.0, 0.0, 1.0));
+        assert_eq!(large.get(7, 2), Vec3::new(0.0, 0.0, 0.0));
+    }
+}
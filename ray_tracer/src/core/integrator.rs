@@ -0,0 +1,531 @@
+use crate::core::stats;
+use crate::core::{
+    power_heuristic, HitRecord, Hitable, InteriorStack, Material, SampledSpectrum,
+    SampledWavelengths, Scene,
+};
+use crate::geometry::{dot, normalize, random, random_unit_vector, Float, Ray, Vec3};
+use std::sync::Arc;
+
+// Estimates the light arriving at the camera along a ray.
+pub trait Integrator: Send + Sync {
+    // Work done once for a scene before rendering it.
+    fn preprocess(&self, _scene: &Scene) {}
+    fn li(&self, r: &Ray, scene: &Scene) -> Vec3;
+    // For the wavelengths of a spectral render, converted from RGB by
+    // integrators that ignore dispersion.
+    fn li_spectral(&self, r: &Ray, scene: &Scene, wl: &mut SampledWavelengths) -> SampledSpectrum {
+        SampledSpectrum::from_rgb(&self.li(r, scene), wl)
+    }
+}
+
+// Unidirectional path tracing, with light sampling at every diffuse or
+// glossy vertex weighted against hitting the lights by scattering.
+pub struct PathIntegrator {
+    max_depth: i32,
+    // only the light that reaches the first diffuse or glossy vertex
+    direct_only: bool,
+}
+impl PathIntegrator {
+    pub fn new(max_depth: i32) -> Self {
+        Self {
+            max_depth,
+            direct_only: false,
+        }
+    }
+    // `from` is the previous vertex and the density its material sampled r
+    // with, None for camera rays and after specular bounces. `lit` is set once
+    // the path has passed a diffuse or glossy vertex.
+    fn color(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        media: &InteriorStack,
+        from: Option<(Vec3, Float)>,
+        lit: bool,
+        depth: i32,
+    ) -> Vec3 {
+        if depth > 0 {
+            stats::count(stats::Counter::SecondaryRays, 1);
+        }
+        let world: &dyn Hitable = &scene.world;
+        let mut rec = HitRecord::new();
+        if world.hit(r, 0.0, Float::INFINITY, &mut rec) {
+            stats::count(stats::Counter::PathVertices, 1);
+            let transmittance = media.current().map_or(Vec3::new(1.0, 1.0, 1.0), |i| {
+                i.transmittance(rec.t * r.d.length())
+            });
+            let mut scattered = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+            let mut attenuation = Vec3::new(0.0, 0.0, 0.0);
+            let mut media = media.clone();
+            match rec.mat.clone() {
+                Some(ref mat) if depth < self.max_depth && media.is_false_hit(mat) => {
+                    media.cross(mat, dot(&r.d, &rec.normal) < 0.0);
+                    let continued = rec.spawn_ray(r.d);
+                    return transmittance
+                        * self.color(&continued, scene, &media, from, lit, depth + 1);
+                }
+                Some(ref mat) if self.direct_only && lit => {
+                    return transmittance * emission(r, &rec, mat, scene, from);
+                }
+                Some(ref mat) if depth < self.max_depth => {
+                    rec.outer_ior = media.outside_of(mat).map_or(1.0, |i| i.ior.nominal());
+                    let emitted = emission(r, &rec, mat, scene, from) + direct(r, &rec, mat, scene);
+                    let emitted = transmittance * emitted;
+                    // shading normals must not scatter through the surface
+                    if mat.scatter(r, &rec, &mut attenuation, &mut scattered)
+                        && rec.is_consistent(&scattered.d)
+                    {
+                        cross_interface(&mut media, mat, r, &scattered, &rec);
+                        let from = scattered_from(r, &rec, mat, &scattered);
+                        let lit = lit || from.is_some();
+                        return emitted
+                            + transmittance
+                                * attenuation
+                                * self.color(&scattered, scene, &media, from, lit, depth + 1);
+                    }
+                    return emitted;
+                }
+                _ => return Vec3::new(0.0, 0.0, 0.0),
+            }
+        }
+        match media.current() {
+            Some(i) => i.transmittance(Float::INFINITY) * sky(scene, r),
+            None => sky(scene, r),
+        }
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn color_spectral(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        media: &InteriorStack,
+        wl: &mut SampledWavelengths,
+        from: Option<(Vec3, Float)>,
+        lit: bool,
+        depth: i32,
+    ) -> SampledSpectrum {
+        if depth > 0 {
+            stats::count(stats::Counter::SecondaryRays, 1);
+        }
+        let world: &dyn Hitable = &scene.world;
+        let mut rec = HitRecord::new();
+        if world.hit(r, 0.0, Float::INFINITY, &mut rec) {
+            stats::count(stats::Counter::PathVertices, 1);
+            let transmittance = media.current().map_or(SampledSpectrum::new(1.0), |i| {
+                i.transmittance_spectral(rec.t * r.d.length(), wl)
+            });
+            let mut scattered = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+            let mut attenuation = SampledSpectrum::new(0.0);
+            let mut media = media.clone();
+            match rec.mat.clone() {
+                Some(ref mat) if depth < self.max_depth && media.is_false_hit(mat) => {
+                    media.cross(mat, dot(&r.d, &rec.normal) < 0.0);
+                    let continued = rec.spawn_ray(r.d);
+                    return transmittance
+                        * self.color_spectral(&continued, scene, &media, wl, from, lit, depth + 1);
+                }
+                Some(ref mat) if self.direct_only && lit => {
+                    let emitted = emission(r, &rec, mat, scene, from);
+                    return transmittance * SampledSpectrum::from_rgb(&emitted, wl);
+                }
+                Some(ref mat) if depth < self.max_depth => {
+                    if let Some(outside) = media.outside_of(mat) {
+                        if outside.ior.is_dispersive() {
+                            wl.terminate_secondary();
+                        }
+                        rec.outer_ior = outside.ior.at(wl.hero());
+                    }
+                    let emitted = emission(r, &rec, mat, scene, from) + direct(r, &rec, mat, scene);
+                    let emitted = transmittance * SampledSpectrum::from_rgb(&emitted, wl);
+                    if mat.scatter_spectral(r, &rec, wl, &mut attenuation, &mut scattered)
+                        && rec.is_consistent(&scattered.d)
+                    {
+                        cross_interface(&mut media, mat, r, &scattered, &rec);
+                        let from = scattered_from(r, &rec, mat, &scattered);
+                        let lit = lit || from.is_some();
+                        return emitted
+                            + transmittance
+                                * attenuation
+                                * self.color_spectral(
+                                    &scattered,
+                                    scene,
+                                    &media,
+                                    wl,
+                                    from,
+                                    lit,
+                                    depth + 1,
+                                );
+                    }
+                    return emitted;
+                }
+                _ => return SampledSpectrum::new(0.0),
+            }
+        }
+        let l = SampledSpectrum::from_rgb(&sky(scene, r), wl);
+        match media.current() {
+            Some(i) => i.transmittance_spectral(Float::INFINITY, wl) * l,
+            None => l,
+        }
+    }
+}
+impl Integrator for PathIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene) -> Vec3 {
+        self.color(r, scene, &InteriorStack::new(), None, false, 0)
+    }
+    fn li_spectral(&self, r: &Ray, scene: &Scene, wl: &mut SampledWavelengths) -> SampledSpectrum {
+        self.color_spectral(r, scene, &InteriorStack::new(), wl, None, false, 0)
+    }
+}
+
+// Light reaching the first diffuse or glossy surface straight from the
+// lights or the sky, seen directly or through specular bounces.
+pub struct DirectIntegrator {
+    path: PathIntegrator,
+}
+impl DirectIntegrator {
+    pub fn new(max_depth: i32) -> Self {
+        Self {
+            path: PathIntegrator {
+                max_depth,
+                direct_only: true,
+            },
+        }
+    }
+}
+impl Integrator for DirectIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene) -> Vec3 {
+        self.path.li(r, scene)
+    }
+    fn li_spectral(&self, r: &Ray, scene: &Scene, wl: &mut SampledWavelengths) -> SampledSpectrum {
+        self.path.li_spectral(r, scene, wl)
+    }
+}
+
+// Classic recursive ray tracing: every light is sampled once at each hit,
+// and only specular materials are followed.
+pub struct WhittedIntegrator {
+    max_depth: i32,
+}
+impl WhittedIntegrator {
+    pub fn new(max_depth: i32) -> Self {
+        Self { max_depth }
+    }
+    fn color(&self, r: &Ray, scene: &Scene, media: &InteriorStack, depth: i32) -> Vec3 {
+        if depth > 0 {
+            stats::count(stats::Counter::SecondaryRays, 1);
+        }
+        let world: &dyn Hitable = &scene.world;
+        let mut rec = HitRecord::new();
+        if !world.hit(r, 0.0, Float::INFINITY, &mut rec) {
+            return match media.current() {
+                Some(i) => i.transmittance(Float::INFINITY) * sky(scene, r),
+                None => sky(scene, r),
+            };
+        }
+        stats::count(stats::Counter::PathVertices, 1);
+        let transmittance = media.current().map_or(Vec3::new(1.0, 1.0, 1.0), |i| {
+            i.transmittance(rec.t * r.d.length())
+        });
+        let mut media = media.clone();
+        let mat = match rec.mat.clone() {
+            Some(mat) if depth < self.max_depth => mat,
+            _ => return Vec3::new(0.0, 0.0, 0.0),
+        };
+        if media.is_false_hit(&mat) {
+            media.cross(&mat, dot(&r.d, &rec.normal) < 0.0);
+            let continued = rec.spawn_ray(r.d);
+            return transmittance * self.color(&continued, scene, &media, depth + 1);
+        }
+        rec.outer_ior = media.outside_of(&mat).map_or(1.0, |i| i.ior.nominal());
+        let mut l = mat.emitted(r, &rec);
+        for i in 0..scene.lights.len() {
+            l += sample_light(r, &rec, &mat, scene, i, 1.0, false);
+        }
+        let mut scattered = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let mut attenuation = Vec3::new(0.0, 0.0, 0.0);
+        if mat.scatter(r, &rec, &mut attenuation, &mut scattered)
+            && rec.is_consistent(&scattered.d)
+            && scattered_from(r, &rec, &mat, &scattered).is_none()
+        {
+            cross_interface(&mut media, &mat, r, &scattered, &rec);
+            l += attenuation * self.color(&scattered, scene, &media, depth + 1);
+        }
+        transmittance * l
+    }
+}
+impl Integrator for WhittedIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene) -> Vec3 {
+        self.color(r, scene, &InteriorStack::new(), 0)
+    }
+}
+
+// Ambient occlusion: the fraction of cosine weighted rays from the first hit
+// that escape within `distance`, white for fully open surfaces.
+pub struct AoIntegrator {
+    samples: u32,
+    distance: Float,
+}
+impl AoIntegrator {
+    pub fn new(samples: u32, distance: Float) -> Self {
+        Self {
+            samples: samples.max(1),
+            distance,
+        }
+    }
+}
+impl Integrator for AoIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene) -> Vec3 {
+        let world: &dyn Hitable = &scene.world;
+        let mut rec = HitRecord::new();
+        if !world.hit(r, 0.0, Float::INFINITY, &mut rec) {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        stats::count(stats::Counter::PathVertices, 1);
+        let n = if dot(&r.d, &rec.normal) > 0.0 {
+            -rec.shading_normal
+        } else {
+            rec.shading_normal
+        };
+        let mut open = 0.0;
+        for _ in 0..self.samples {
+            let d = n + random_unit_vector();
+            if d.length2() < 1e-8 || !rec.is_consistent(&d) {
+                continue;
+            }
+            stats::count(stats::Counter::ShadowRays, 1);
+            let ray = rec.spawn_ray(normalize(d));
+            open += world.transmittance(&ray, 0.0, self.distance);
+        }
+        let a = open / self.samples as Float;
+        Vec3::new(a, a, a)
+    }
+}
+
+// False color views of the first hit, black where rays escape.
+pub enum DebugIntegrator {
+    // shading normals mapped from [-1, 1] to [0, 1]
+    Normals,
+    Uv,
+    // white at the camera fading to black at the given distance
+    Depth(Float),
+}
+impl Integrator for DebugIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene) -> Vec3 {
+        let world: &dyn Hitable = &scene.world;
+        let mut rec = HitRecord::new();
+        if !world.hit(r, 0.0, Float::INFINITY, &mut rec) {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        match self {
+            DebugIntegrator::Normals => 0.5 * (rec.shading_normal + Vec3::new(1.0, 1.0, 1.0)),
+            DebugIntegrator::Uv => Vec3::new(rec.u, rec.v, 0.0),
+            DebugIntegrator::Depth(far) => {
+                let d = 1.0 - (rec.t * r.d.length() / far).min(1.0);
+                Vec3::new(d, d, d)
+            }
+        }
+    }
+}
+
+pub(crate) fn sky(scene: &Scene, r: &Ray) -> Vec3 {
+    let unit_direction = normalize(r.d);
+    let t = 0.5 * (unit_direction.y + 1.0);
+    let c = (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0);
+    scene.sky * c
+}
+
+// Light reaching a diffuse or glossy hit point straight from one light,
+// chosen by the light sampler.
+pub(crate) fn direct(r: &Ray, rec: &HitRecord, mat: &Arc<dyn Material>, scene: &Scene) -> Vec3 {
+    match scene.light_sampler.sample(&rec.p, random()) {
+        Some((index, pmf)) => sample_light(r, rec, mat, scene, index, pmf, true),
+        None => Vec3::new(0.0, 0.0, 0.0),
+    }
+}
+
+// Light from the light `index`, chosen with probability pmf, reached by a
+// shadow ray and scattered towards the incoming ray. With mis, area lights
+// are weighted against finding them by scattering.
+fn sample_light(
+    r: &Ray,
+    rec: &HitRecord,
+    mat: &Arc<dyn Material>,
+    scene: &Scene,
+    index: usize,
+    pmf: Float,
+    mis: bool,
+) -> Vec3 {
+    let black = Vec3::new(0.0, 0.0, 0.0);
+    let light = &scene.lights[index];
+    let s = match light.sample(&rec.p) {
+        Some(s) if rec.is_consistent(&s.wi) && s.pdf > 0.0 => s,
+        _ => return black,
+    };
+    let f = match mat.eval(r, rec, &s.wi) {
+        Some(f) if f.length2() > 0.0 => f,
+        // specular materials can not scatter light from a point
+        _ => return black,
+    };
+    stats::count(stats::Counter::ShadowRays, 1);
+    let world: &dyn Hitable = &scene.world;
+    // stopping short of the surface of area lights
+    let tr = world.transmittance(&rec.spawn_ray(s.wi), 0.0, (1.0 - 1e-3) * s.distance);
+    let light_pdf = pmf * s.pdf;
+    let weight = if mis && !light.is_delta() {
+        power_heuristic(light_pdf, mat.pdf(r, rec, &s.wi))
+    } else {
+        1.0
+    };
+    (tr * weight / light_pdf) * f * s.radiance
+}
+
+// Light emitted at a hit point towards the incoming ray. Area lights that
+// the previous vertex could also have sampled directly are weighted against
+// that.
+pub(crate) fn emission(
+    r: &Ray,
+    rec: &HitRecord,
+    mat: &Arc<dyn Material>,
+    scene: &Scene,
+    from: Option<(Vec3, Float)>,
+) -> Vec3 {
+    let le = mat.emitted(r, rec);
+    match (from, mat.light_index()) {
+        (Some((p, pdf)), Some(i)) if le.length2() > 0.0 => {
+            let wi = normalize(r.d);
+            let light_pdf = scene.light_sampler.pmf(&p, i) * scene.lights[i].pdf(&p, &wi);
+            power_heuristic(pdf, light_pdf) * le
+        }
+        _ => le,
+    }
+}
+
+// The previous vertex for rays scattered at rec, None after specular
+// bounces.
+pub(crate) fn scattered_from(
+    r: &Ray,
+    rec: &HitRecord,
+    mat: &Arc<dyn Material>,
+    scattered: &Ray,
+) -> Option<(Vec3, Float)> {
+    let pdf = mat.pdf(r, rec, &normalize(scattered.d));
+    if pdf > 0.0 {
+        Some((rec.p, pdf))
+    } else {
+        None
+    }
+}
+
+// Updates the interiors a path is in after it is transmitted through the
+// boundary of `mat`.
+pub(crate) fn cross_interface(
+    media: &mut InteriorStack,
+    mat: &Arc<dyn Material>,
+    r_in: &Ray,
+    scattered: &Ray,
+    rec: &HitRecord,
+) {
+    if mat.interior().is_none() {
+        return;
+    }
+    let cos_in = dot(&r_in.d, &rec.normal);
+    let cos_out = dot(&scattered.d, &rec.normal);
+    if cos_in * cos_out > 0.0 {
+        media.cross(mat, cos_in < 0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{
+        AoIntegrator, DebugIntegrator, DirectIntegrator, Integrator, PathIntegrator, Scene,
+        WhittedIntegrator,
+    };
+    use crate::geometry::{seed_random, Float, Ray, Vec3};
+    #[test]
+    fn integrators_agree_on_direct_light() {
+        seed_random(13);
+        // a lambertian ground under a sphere light, where all the light is
+        // direct: albedo / pi * pi L sin^2 of the cone
+        let scene = Scene::parse(
+            "sky intensity=0\n\
+             material lamp type=light emission=4,4,4\n\
+             material ground albedo=0.5,0.5,0.5\n\
+             sphere center=0,-1000,0 radius=1000 material=ground\n\
+             sphere center=0,2,0 radius=0.5 material=lamp\n",
+        )
+        .unwrap();
+        let expected = 0.5 * 4.0 * 0.25 / 4.0;
+        let r = Ray::new(Vec3::new(3.0, 1.0, 0.0), Vec3::new(-3.0, -1.0, 0.0));
+        let integrators: Vec<Box<dyn Integrator>> = vec![
+            Box::new(PathIntegrator::new(50)),
+            Box::new(DirectIntegrator::new(5)),
+            Box::new(WhittedIntegrator::new(5)),
+        ];
+        let n = 20000;
+        for integrator in integrators {
+            let mut sum = 0.0;
+            for _ in 0..n {
+                sum += integrator.li(&r, &scene).x;
+            }
+            let mean = sum / n as Float;
+            assert!((mean - expected).abs() < 0.03 * expected, "{}", mean);
+        }
+        // the light itself, seen directly
+        let up = Ray::new(Vec3::new(0.0, 0.5, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(PathIntegrator::new(50).li(&up, &scene).x, 4.0);
+    }
+    #[test]
+    fn direct_light_follows_specular_chains_to_the_first_diffuse_vertex() {
+        seed_random(15);
+        let scene = Scene::parse(
+            "sky intensity=0\n\
+             material lamp type=light emission=4,4,4\n\
+             material ground albedo=0.5,0.5,0.5\n\
+             material mirror type=metal albedo=1,1,1\n\
+             sphere center=0,-1000,0 radius=1000 material=ground\n\
+             sphere center=0,2,0 radius=0.5 material=lamp\n\
+             sphere center=-2.5,1,0 radius=1 material=mirror\n",
+        )
+        .unwrap();
+        let direct = DirectIntegrator::new(5);
+        // light reflected by the mirror onto the ground is not direct
+        let r = Ray::new(Vec3::new(3.0, 1.0, 0.0), Vec3::new(-3.0, -1.0, 0.0));
+        let n = 20000;
+        let mean = (0..n).map(|_| direct.li(&r, &scene).x).sum::<Float>() / n as Float;
+        let expected = 0.5 * 4.0 * 0.25 / 4.0;
+        assert!((mean - expected).abs() < 0.03 * expected, "{}", mean);
+        // but the ground seen in the mirror is lit
+        let seen = Ray::new(
+            Vec3::new(2.0, 1.0 - Float::sqrt(0.5), 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+        );
+        assert!(direct.li(&seen, &scene).x > 0.0);
+    }
+    #[test]
+    fn ambient_occlusion_and_debug_views() {
+        seed_random(14);
+        let scene = Scene::parse(
+            "sphere center=0,-1000,0 radius=1000\n\
+             sphere center=0,1,0 radius=1\n",
+        )
+        .unwrap();
+        let ao = AoIntegrator::new(64, Float::INFINITY);
+        let open = Ray::new(Vec3::new(5.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(ao.li(&open, &scene).x, 1.0);
+        // under the sphere, next to where it touches the ground
+        let corner = Ray::new(Vec3::new(0.5, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let occluded = Ray::new(Vec3::new(0.3, 0.01, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let a = ao.li(&occluded, &scene).x;
+        assert!(a > 0.0 && a < 0.6, "{}", a);
+        let down = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let n = DebugIntegrator::Normals.li(&down, &scene);
+        assert!((n - Vec3::new(0.5, 1.0, 0.5)).length() < 1e-5, "{:?}", n);
+        let d = DebugIntegrator::Depth(10.0).li(&down, &scene);
+        assert!((d.x - 0.7).abs() < 1e-4, "{:?}", d);
+        let uv = DebugIntegrator::Uv.li(&corner, &scene);
+        assert!(uv.x >= 0.0 && uv.x <= 1.0 && uv.y >= 0.0 && uv.y <= 1.0);
+        let sky = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(ao.li(&sky, &scene), Vec3::new(0.0, 0.0, 0.0));
+    }
+}
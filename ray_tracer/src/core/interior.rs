@@ -0,0 +1,136 @@
+use crate::core::materials::Ior;
+use crate::core::{Material, SampledSpectrum, SampledWavelengths};
+use crate::geometry::{Float, Vec3};
+use std::sync::Arc;
+
+// Volume enclosed by a closed surface. Overlapping interiors are resolved by
+// priority: where two of them overlap, the one with the higher priority wins.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interior {
+    pub ior: Ior,
+    pub sigma_a: Vec3,
+    pub priority: u32,
+}
+impl Interior {
+    pub fn new(ior: Ior) -> Self {
+        Self {
+            ior,
+            sigma_a: Vec3::new(0.0, 0.0, 0.0),
+            priority: 0,
+        }
+    }
+    pub fn transmittance(&self, distance: Float) -> Vec3 {
+        let mut tr = Vec3::new(1.0, 1.0, 1.0);
+        for i in 0..3 {
+            if self.sigma_a[i] > 0.0 {
+                tr[i] = (-self.sigma_a[i] * distance).exp();
+            }
+        }
+        tr
+    }
+    pub fn transmittance_spectral(
+        &self,
+        distance: Float,
+        wl: &SampledWavelengths,
+    ) -> SampledSpectrum {
+        let mut tr = SampledSpectrum::new(1.0);
+        let sigma = SampledSpectrum::from_rgb(&self.sigma_a, wl);
+        for i in 0..tr.values.len() {
+            if sigma.values[i] > 0.0 {
+                tr.values[i] = (-sigma.values[i] * distance).exp();
+            }
+        }
+        tr
+    }
+}
+
+// Interiors a path is currently travelling through, e.g. [glass, liquid] for a
+// ray inside the liquid of a filled glass.
+#[derive(Clone, Default)]
+pub struct InteriorStack {
+    entries: Vec<Arc<dyn Material>>,
+}
+impl InteriorStack {
+    pub fn new() -> Self {
+        Self { entries: vec![] }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    // Interior the path is actually inside of.
+    pub fn current(&self) -> Option<Interior> {
+        self.highest(None)
+    }
+    // Interior on the other side of the boundary of `mat`.
+    pub fn outside_of(&self, mat: &Arc<dyn Material>) -> Option<Interior> {
+        self.highest(Some(mat))
+    }
+    // Boundaries of lower priority interiors inside higher priority ones are
+    // not real interfaces and must be crossed without scattering.
+    pub fn is_false_hit(&self, mat: &Arc<dyn Material>) -> bool {
+        match (mat.interior(), self.outside_of(mat)) {
+            (Some(interior), Some(outside)) => outside.priority > interior.priority,
+            _ => false,
+        }
+    }
+    pub fn cross(&mut self, mat: &Arc<dyn Material>, entering: bool) {
+        if entering {
+            self.entries.push(mat.clone());
+        } else if let Some(i) = self.entries.iter().rposition(|m| Arc::ptr_eq(m, mat)) {
+            self.entries.remove(i);
+        }
+    }
+    fn highest(&self, exclude: Option<&Arc<dyn Material>>) -> Option<Interior> {
+        let mut best: Option<Interior> = None;
+        for m in self.entries.iter() {
+            if exclude.is_some_and(|e| Arc::ptr_eq(m, e)) {
+                continue;
+            }
+            if let Some(interior) = m.interior() {
+                if best.is_none_or(|b| interior.priority >= b.priority) {
+                    best = Some(*interior);
+                }
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::materials::{Dielectric, Ior, Lambertian};
+    use crate::core::{InteriorStack, Material};
+    use crate::geometry::Vec3;
+    use std::sync::Arc;
+    #[test]
+    fn liquid_in_glass() {
+        let glass: Arc<dyn Material> = Arc::new(Dielectric::new(1.5).with_priority(2));
+        let liquid: Arc<dyn Material> = Arc::new(Dielectric::new(1.33).with_priority(1));
+        let mut stack = InteriorStack::new();
+        assert!(!stack.is_false_hit(&glass));
+        stack.cross(&glass, true);
+        // the liquid surface overlapping the glass wall is ignored
+        assert!(stack.is_false_hit(&liquid));
+        stack.cross(&liquid, true);
+        // glass -> liquid interface sees the liquid on the other side
+        assert_eq!(stack.outside_of(&glass).unwrap().ior, Ior::Constant(1.33));
+        stack.cross(&glass, false);
+        assert_eq!(stack.current().unwrap().ior, Ior::Constant(1.33));
+        stack.cross(&liquid, false);
+        assert!(stack.is_empty());
+    }
+    #[test]
+    fn opaque_materials_have_no_interior() {
+        let diffuse: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+        let mut stack = InteriorStack::new();
+        stack.cross(&diffuse, true);
+        assert!(stack.current().is_none());
+        assert!(!stack.is_false_hit(&diffuse));
+    }
+    #[test]
+    fn transmittance_at_reference_distance() {
+        let glass = Dielectric::new(1.5).with_transmittance(Vec3::new(0.8, 0.5, 1.0), 2.0);
+        let tr = glass.interior().unwrap().transmittance(2.0);
+        assert!((tr - Vec3::new(0.8, 0.5, 1.0)).length() < 1e-5);
+    }
+}
@@ -0,0 +1,404 @@
+use crate::geometry::{
+    consts, cross, dot, normalize, random, random_unit_vector, Aabb, Float, Ray, Vec3,
+};
+
+// Light arriving at a point from a light source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightSample {
+    // unit direction towards the light
+    pub wi: Vec3,
+    // along wi, infinite for distant lights
+    pub distance: Float,
+    pub radiance: Vec3,
+    // solid angle density of wi, 1 for delta lights
+    pub pdf: Float,
+    // of the light at the sampled point, zero for point and distant lights
+    pub normal: Vec3,
+}
+
+// A ray leaving a light, for paths traced from the lights.
+#[derive(Debug, Clone, Copy)]
+pub struct EmissionSample {
+    pub ray: Ray,
+    pub normal: Vec3,
+    // radiance, or intensity for point lights, along the ray
+    pub radiance: Vec3,
+    // area density of the origin, 1 for point lights
+    pub pdf_pos: Float,
+    // solid angle density of the direction
+    pub pdf_dir: Float,
+}
+
+// Light sources sampled with shadow rays. Delta lights are not part of the
+// geometry and paths can only reach them that way; area lights can also be
+// hit by scattered rays.
+pub trait Light: Send + Sync {
+    fn sample(&self, p: &Vec3) -> Option<LightSample>;
+    // Density with which sample would choose the direction wi from p.
+    fn pdf(&self, _p: &Vec3, _wi: &Vec3) -> Float {
+        0.0
+    }
+    fn is_delta(&self) -> bool {
+        true
+    }
+    // Total emitted power, as the mean over the color channels.
+    fn power(&self) -> Float;
+    // None for lights at infinity
+    fn bounds(&self) -> Option<Aabb>;
+    // None for lights paths can not be traced from.
+    fn sample_le(&self) -> Option<EmissionSample> {
+        None
+    }
+    // Densities with which sample_le would choose a point with normal n and
+    // the direction w from there.
+    fn pdf_le(&self, _n: &Vec3, _w: &Vec3) -> (Float, Float) {
+        (0.0, 0.0)
+    }
+    // Emitted along w from a point with normal n.
+    fn le(&self, _n: &Vec3, _w: &Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+}
+
+// Weight of a sample taken with density f_pdf, against another strategy
+// that could have taken it with density g_pdf.
+pub fn power_heuristic(f_pdf: Float, g_pdf: Float) -> Float {
+    let (f2, g2) = (f_pdf * f_pdf, g_pdf * g_pdf);
+    if f2.is_infinite() {
+        return 1.0;
+    }
+    if f2 + g2 == 0.0 {
+        return 0.0;
+    }
+    f2 / (f2 + g2)
+}
+
+fn mean(c: &Vec3) -> Float {
+    (c.x + c.y + c.z) / 3.0
+}
+
+// Direction at angles theta and phi from the unit vector w.
+fn around(w: &Vec3, cos_theta: Float, phi: Float) -> Vec3 {
+    let a = if w.x.abs() > 0.9 {
+        Vec3::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let u = normalize(cross(&a, w));
+    let v = cross(w, &u);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    normalize(sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * *w)
+}
+
+pub struct PointLight {
+    position: Vec3,
+    intensity: Vec3,
+}
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+impl Light for PointLight {
+    fn sample(&self, p: &Vec3) -> Option<LightSample> {
+        let d = self.position - *p;
+        let distance2 = d.length2();
+        if distance2 == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi: normalize(d),
+            distance: distance2.sqrt(),
+            radiance: self.intensity / distance2,
+            pdf: 1.0,
+            normal: Vec3::new(0.0, 0.0, 0.0),
+        })
+    }
+    fn power(&self) -> Float {
+        4.0 * consts::PI * mean(&self.intensity)
+    }
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.position, self.position))
+    }
+    fn sample_le(&self) -> Option<EmissionSample> {
+        Some(EmissionSample {
+            ray: Ray::new(self.position, random_unit_vector()),
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            radiance: self.intensity,
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (4.0 * consts::PI),
+        })
+    }
+    fn pdf_le(&self, _n: &Vec3, _w: &Vec3) -> (Float, Float) {
+        (1.0, 1.0 / (4.0 * consts::PI))
+    }
+    fn le(&self, _n: &Vec3, _w: &Vec3) -> Vec3 {
+        self.intensity
+    }
+}
+
+// A point light shining into a cone, fading out smoothly from the falloff
+// angle to the edge of the cone.
+pub struct SpotLight {
+    light: PointLight,
+    direction: Vec3,
+    cos_total: Float,
+    cos_falloff: Float,
+}
+impl SpotLight {
+    // angles in degrees from the axis
+    pub fn new(
+        position: Vec3,
+        look_at: Vec3,
+        intensity: Vec3,
+        cone_angle: Float,
+        falloff_angle: Float,
+    ) -> Self {
+        Self {
+            light: PointLight::new(position, intensity),
+            direction: normalize(look_at - position),
+            cos_total: cone_angle.to_radians().cos(),
+            cos_falloff: falloff_angle.min(cone_angle).to_radians().cos(),
+        }
+    }
+    fn falloff(&self, cos_theta: Float) -> Float {
+        if cos_theta >= self.cos_falloff {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_total {
+            return 0.0;
+        }
+        let t = (cos_theta - self.cos_total) / (self.cos_falloff - self.cos_total);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+impl Light for SpotLight {
+    fn sample(&self, p: &Vec3) -> Option<LightSample> {
+        let mut s = self.light.sample(p)?;
+        let falloff = self.falloff(-dot(&s.wi, &self.direction));
+        if falloff == 0.0 {
+            return None;
+        }
+        s.radiance = falloff * s.radiance;
+        Some(s)
+    }
+    fn power(&self) -> Float {
+        // the smooth falloff counted as halfway between the two cones
+        let cone = 1.0 - 0.5 * (self.cos_total + self.cos_falloff);
+        2.0 * consts::PI * cone * mean(&self.light.intensity)
+    }
+    fn bounds(&self) -> Option<Aabb> {
+        self.light.bounds()
+    }
+    // uniform over the cone
+    fn sample_le(&self) -> Option<EmissionSample> {
+        let cos_theta = 1.0 - random() * (1.0 - self.cos_total);
+        let d = around(&self.direction, cos_theta, 2.0 * consts::PI * random());
+        Some(EmissionSample {
+            ray: Ray::new(self.light.position, d),
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            radiance: self.falloff(cos_theta) * self.light.intensity,
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (2.0 * consts::PI * (1.0 - self.cos_total)),
+        })
+    }
+    fn pdf_le(&self, _n: &Vec3, w: &Vec3) -> (Float, Float) {
+        if dot(w, &self.direction) <= self.cos_total {
+            return (1.0, 0.0);
+        }
+        (1.0, 1.0 / (2.0 * consts::PI * (1.0 - self.cos_total)))
+    }
+    fn le(&self, _n: &Vec3, w: &Vec3) -> Vec3 {
+        self.falloff(dot(w, &self.direction)) * self.light.intensity
+    }
+}
+
+// Parallel light from far away, e.g. the sun, with the irradiance it gives
+// to surfaces facing it.
+pub struct DistantLight {
+    wi: Vec3,
+    irradiance: Vec3,
+}
+impl DistantLight {
+    pub fn new(direction: Vec3, irradiance: Vec3) -> Self {
+        Self {
+            wi: -normalize(direction),
+            irradiance,
+        }
+    }
+}
+impl Light for DistantLight {
+    fn sample(&self, _p: &Vec3) -> Option<LightSample> {
+        Some(LightSample {
+            wi: self.wi,
+            distance: Float::INFINITY,
+            radiance: self.irradiance,
+            pdf: 1.0,
+            normal: Vec3::new(0.0, 0.0, 0.0),
+        })
+    }
+    // only compared with other lights at infinity
+    fn power(&self) -> Float {
+        mean(&self.irradiance)
+    }
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+// Spherical area light, sampled uniformly over the cone it subtends. The
+// geometry is a sphere with a DiffuseLight material of the same emission.
+pub struct SphereLight {
+    center: Vec3,
+    radius: Float,
+    emission: Vec3,
+}
+impl SphereLight {
+    pub fn new(center: Vec3, radius: Float, emission: Vec3) -> Self {
+        Self {
+            center,
+            radius,
+            emission,
+        }
+    }
+    // One minus the cosine of the half angle of the cone seen from p, kept
+    // accurate for small and far away spheres.
+    fn cone(&self, p: &Vec3) -> Option<Float> {
+        let sin2_max = self.radius * self.radius / (self.center - *p).length2();
+        if sin2_max >= 1.0 {
+            return None;
+        }
+        // sin^2 of 1.5 degrees
+        if sin2_max < 0.000_685_23 {
+            return Some(0.5 * sin2_max);
+        }
+        Some(1.0 - (1.0 - sin2_max).sqrt())
+    }
+}
+impl Light for SphereLight {
+    fn sample(&self, p: &Vec3) -> Option<LightSample> {
+        let one_minus_cos_max = self.cone(p)?;
+        let d = self.center - *p;
+        let dc = d.length();
+        let w = d / dc;
+        let one_minus_cos = one_minus_cos_max * random();
+        let cos_theta = 1.0 - one_minus_cos;
+        let sin2_theta = (one_minus_cos * (2.0 - one_minus_cos)).max(0.0);
+        let wi = around(&w, cos_theta, 2.0 * consts::PI * random());
+        // nearest hit of the sphere along wi
+        let r2 = self.radius * self.radius;
+        let distance = dc * cos_theta - (r2 - dc * dc * sin2_theta).max(0.0).sqrt();
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: self.emission,
+            pdf: 1.0 / (2.0 * consts::PI * one_minus_cos_max),
+            normal: normalize(*p + distance * wi - self.center),
+        })
+    }
+    fn pdf(&self, p: &Vec3, wi: &Vec3) -> Float {
+        // whether the line along wi passes through the sphere, which unlike
+        // the angle is accurate for tiny cones
+        let d = self.center - *p;
+        let inside = dot(wi, &d) > 0.0 && cross(wi, &d).length2() <= self.radius * self.radius;
+        match self.cone(p) {
+            Some(one_minus_cos_max) if inside => 1.0 / (2.0 * consts::PI * one_minus_cos_max),
+            _ => 0.0,
+        }
+    }
+    fn is_delta(&self) -> bool {
+        false
+    }
+    fn power(&self) -> Float {
+        let area = 4.0 * consts::PI * self.radius * self.radius;
+        consts::PI * area * mean(&self.emission)
+    }
+    fn bounds(&self) -> Option<Aabb> {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+    // uniform over the surface, cosine weighted around the normal
+    fn sample_le(&self) -> Option<EmissionSample> {
+        let n = random_unit_vector();
+        let d = n + random_unit_vector();
+        if d.length2() < 1e-8 {
+            return None;
+        }
+        let d = normalize(d);
+        // off the surface by the rounding error of the point
+        let error = 1e-5 * (self.center.length() + self.radius);
+        let origin = self.center + (self.radius + error) * n;
+        Some(EmissionSample {
+            ray: Ray::new(origin, d),
+            normal: n,
+            radiance: self.emission,
+            pdf_pos: 1.0 / (4.0 * consts::PI * self.radius * self.radius),
+            pdf_dir: dot(&n, &d) / consts::PI,
+        })
+    }
+    fn pdf_le(&self, n: &Vec3, w: &Vec3) -> (Float, Float) {
+        let pdf_pos = 1.0 / (4.0 * consts::PI * self.radius * self.radius);
+        (pdf_pos, dot(n, w).max(0.0) / consts::PI)
+    }
+    fn le(&self, n: &Vec3, w: &Vec3) -> Vec3 {
+        if dot(n, w) > 0.0 {
+            self.emission
+        } else {
+            Vec3::new(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{DistantLight, Light, PointLight, SphereLight, SpotLight};
+    use crate::geometry::{consts, dot, seed_random, Float, Vec3};
+    #[test]
+    fn punctual_lights() {
+        let white = Vec3::new(4.0, 4.0, 4.0);
+        let point = PointLight::new(Vec3::new(0.0, 2.0, 0.0), white);
+        let s = point.sample(&Vec3::new(0.0, 0.0, 0.0)).unwrap();
+        assert_eq!((s.wi, s.distance), (Vec3::new(0.0, 1.0, 0.0), 2.0));
+        assert_eq!(s.radiance, Vec3::new(1.0, 1.0, 1.0));
+        // pointing down, full inside 30 degrees and dark past 45
+        let down = Vec3::new(0.0, 0.0, 0.0);
+        let spot = SpotLight::new(Vec3::new(0.0, 1.0, 0.0), down, white, 45.0, 30.0);
+        let at = |x| spot.sample(&Vec3::new(x, 0.0, 0.0)).map(|s| s.radiance.x);
+        assert_eq!(at(0.0), Some(4.0));
+        assert!(at(0.5).unwrap() > 4.0 / 1.25 - 1e-5);
+        let edge = at(0.8).unwrap();
+        assert!(edge > 0.0 && edge < 4.0 / 1.64, "{}", edge);
+        assert_eq!(at(1.1), None);
+        let sun = DistantLight::new(Vec3::new(0.0, -1.0, 1.0), white);
+        let s = sun.sample(&Vec3::new(5.0, 0.0, 0.0)).unwrap();
+        assert!((s.wi - Vec3::new(0.0, 0.707_106_77, -0.707_106_77)).length() < 1e-6);
+        assert_eq!(s.radiance, white);
+    }
+    #[test]
+    fn sphere_light_cone() {
+        seed_random(11);
+        let light = SphereLight::new(Vec3::new(0.0, 0.0, 10.0), 1.0, Vec3::new(1.0, 1.0, 1.0));
+        let p = Vec3::new(0.0, 0.0, 0.0);
+        let cos_max = (1.0 - 0.01 as Float).sqrt();
+        let pdf = 1.0 / (2.0 * consts::PI * (1.0 - cos_max));
+        for _ in 0..1000 {
+            let s = light.sample(&p).unwrap();
+            assert!(s.wi.z >= cos_max - 1e-6);
+            // the sample lands on the sphere
+            let q = s.distance * s.wi;
+            assert!(((q - Vec3::new(0.0, 0.0, 10.0)).length() - 1.0).abs() < 1e-3);
+            assert!((s.pdf - pdf).abs() < 1e-3 * pdf);
+            assert_eq!(light.pdf(&p, &s.wi), s.pdf);
+        }
+        assert_eq!(light.pdf(&p, &Vec3::new(0.0, 0.2, 0.98)), 0.0);
+        // tiny and far away
+        let star = SphereLight::new(Vec3::new(0.0, 0.0, 1e3), 1e-2, Vec3::new(1.0, 1.0, 1.0));
+        let s = star.sample(&p).unwrap();
+        assert!(s.pdf.is_finite() && dot(&s.wi, &Vec3::new(0.0, 0.0, 1.0)) > 0.999);
+        assert_eq!(star.pdf(&p, &s.wi), s.pdf);
+        assert!(light.sample(&Vec3::new(0.0, 0.5, 10.0)).is_none());
+    }
+}
@@ -0,0 +1,235 @@
+use crate::core::Light;
+use crate::geometry::{Aabb, Float, Vec3};
+
+// Chooses one light for a shading point with probability proportional to an
+// estimate of its contribution there, its power over the squared distance,
+// evaluated for whole subtrees of a bounding volume hierarchy of the lights.
+// Lights at infinity are chosen uniformly instead, and as often as the whole
+// tree.
+pub struct LightBvh {
+    nodes: Vec<Node>,
+    infinite: Vec<usize>,
+    // branches from the root to each bounded light, one bit per level, set
+    // for the second child
+    trails: Vec<Option<(u64, u32)>>,
+}
+
+struct Node {
+    bounds: Aabb,
+    power: Float,
+    // light index of leaves, or the second child of interior nodes, the
+    // first one following the node
+    index: usize,
+    leaf: bool,
+}
+
+impl LightBvh {
+    pub fn new(lights: &[Box<dyn Light>]) -> Self {
+        let mut bounded = vec![];
+        let mut infinite = vec![];
+        for (i, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(b) if light.power() > 0.0 => bounded.push((i, b, light.power())),
+                Some(_) => {}
+                None => infinite.push(i),
+            }
+        }
+        let mut bvh = Self {
+            nodes: vec![],
+            infinite,
+            trails: vec![None; lights.len()],
+        };
+        if !bounded.is_empty() {
+            bvh.build(&mut bounded, 0, 0);
+        }
+        bvh
+    }
+    // Splits at the median of the centroids along their widest axis, which
+    // keeps the trails shorter than 64 levels.
+    fn build(&mut self, lights: &mut [(usize, Aabb, Float)], trail: u64, depth: u32) {
+        let node = self.nodes.len();
+        let bounds = lights[1..].iter().fold(lights[0].1, |b, l| b.union(&l.1));
+        let power = lights.iter().map(|l| l.2).sum();
+        if lights.len() == 1 {
+            self.trails[lights[0].0] = Some((trail, depth));
+            self.nodes.push(Node {
+                bounds,
+                power,
+                index: lights[0].0,
+                leaf: true,
+            });
+            return;
+        }
+        self.nodes.push(Node {
+            bounds,
+            power,
+            index: 0,
+            leaf: false,
+        });
+        let centroids = lights[1..].iter().fold(
+            Aabb::new(lights[0].1.centroid(), lights[0].1.centroid()),
+            |b, l| b.union(&Aabb::new(l.1.centroid(), l.1.centroid())),
+        );
+        let extent = centroids.max - centroids.min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        let mid = lights.len() / 2;
+        lights.select_nth_unstable_by(mid, |a, b| {
+            a.1.centroid()[axis]
+                .partial_cmp(&b.1.centroid()[axis])
+                .unwrap()
+        });
+        let (first, second) = lights.split_at_mut(mid);
+        self.build(first, trail, depth + 1);
+        self.nodes[node].index = self.nodes.len();
+        self.build(second, trail | 1 << depth, depth + 1);
+    }
+    fn importance(&self, p: &Vec3, node: &Node) -> Float {
+        let d2 = (*p - node.bounds.centroid()).length2();
+        let r = 0.5 * (node.bounds.max - node.bounds.min).length();
+        // inside the bounds any light may be right next to p
+        node.power / d2.max(r * r).max(Float::MIN_POSITIVE)
+    }
+    fn infinite_probability(&self) -> Float {
+        let trees = if self.nodes.is_empty() { 0 } else { 1 };
+        let n = self.infinite.len();
+        if n == 0 {
+            return 0.0;
+        }
+        n as Float / (n + trees) as Float
+    }
+    // A light index and the probability of choosing it, from a uniform u.
+    pub fn sample(&self, p: &Vec3, u: Float) -> Option<(usize, Float)> {
+        self.choose(u, |n| self.importance(p, n))
+    }
+    // Probability that sample chooses the light from p.
+    pub fn pmf(&self, p: &Vec3, light: usize) -> Float {
+        self.probability(light, |n| self.importance(p, n))
+    }
+    // Chooses in proportion to power alone, for paths started at the lights.
+    pub fn sample_power(&self, u: Float) -> Option<(usize, Float)> {
+        self.choose(u, |n| n.power)
+    }
+    pub fn pmf_power(&self, light: usize) -> Float {
+        self.probability(light, |n| n.power)
+    }
+    fn choose<F: Fn(&Node) -> Float>(&self, u: Float, importance: F) -> Option<(usize, Float)> {
+        let p_infinite = self.infinite_probability();
+        if u < p_infinite {
+            let n = self.infinite.len();
+            let i = ((u / p_infinite * n as Float) as usize).min(n - 1);
+            return Some((self.infinite[i], p_infinite / n as Float));
+        }
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut u = ((u - p_infinite) / (1.0 - p_infinite)).min(1.0 - Float::EPSILON);
+        let mut pmf = 1.0 - p_infinite;
+        let mut node = 0;
+        while !self.nodes[node].leaf {
+            let (a, b) = (node + 1, self.nodes[node].index);
+            let ia = importance(&self.nodes[a]);
+            let ib = importance(&self.nodes[b]);
+            if ia + ib == 0.0 {
+                return None;
+            }
+            let pa = ia / (ia + ib);
+            if u < pa {
+                u = (u / pa).min(1.0 - Float::EPSILON);
+                pmf *= pa;
+                node = a;
+            } else {
+                u = ((u - pa) / (1.0 - pa)).min(1.0 - Float::EPSILON);
+                pmf *= 1.0 - pa;
+                node = b;
+            }
+        }
+        Some((self.nodes[node].index, pmf))
+    }
+    fn probability<F: Fn(&Node) -> Float>(&self, light: usize, importance: F) -> Float {
+        if self.infinite.contains(&light) {
+            return self.infinite_probability() / self.infinite.len() as Float;
+        }
+        let (trail, depth) = match self.trails.get(light) {
+            Some(Some(t)) => *t,
+            _ => return 0.0,
+        };
+        let mut pmf = 1.0 - self.infinite_probability();
+        let mut node = 0;
+        for level in 0..depth {
+            let (a, b) = (node + 1, self.nodes[node].index);
+            let ia = importance(&self.nodes[a]);
+            let ib = importance(&self.nodes[b]);
+            if ia + ib == 0.0 {
+                return 0.0;
+            }
+            if trail & 1 << level == 0 {
+                pmf *= ia / (ia + ib);
+                node = a;
+            } else {
+                pmf *= ib / (ia + ib);
+                node = b;
+            }
+        }
+        pmf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{DistantLight, Light, LightBvh, PointLight, SphereLight};
+    use crate::geometry::{random, seed_random, Float, Vec3};
+    #[test]
+    fn choose_by_contribution() {
+        seed_random(12);
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let mut lights: Vec<Box<dyn Light>> = vec![];
+        for i in 0..100 {
+            let center = Vec3::new((i % 10) as Float, 0.0, (i / 10) as Float);
+            lights.push(Box::new(SphereLight::new(center, 0.1, white)));
+        }
+        lights.push(Box::new(PointLight::new(Vec3::new(5.0, 5.0, 5.0), white)));
+        lights.push(Box::new(DistantLight::new(
+            Vec3::new(0.0, -1.0, 0.0),
+            white,
+        )));
+        let bvh = LightBvh::new(&lights);
+        let p = Vec3::new(0.0, 0.5, 0.0);
+        // the probabilities sum to one and match what is sampled
+        let total: Float = (0..lights.len()).map(|i| bvh.pmf(&p, i)).sum();
+        assert!((total - 1.0).abs() < 1e-4, "{}", total);
+        let mut counts = vec![0; lights.len()];
+        let n = 100000;
+        for _ in 0..n {
+            let (i, pmf) = bvh.sample(&p, random()).unwrap();
+            assert!((pmf - bvh.pmf(&p, i)).abs() < 1e-5 * pmf.max(1.0));
+            counts[i] += 1;
+        }
+        for (i, &c) in counts.iter().enumerate() {
+            let expected = bvh.pmf(&p, i) * n as Float;
+            assert!(
+                (c as Float - expected).abs() < 5.0 * expected.sqrt() + 5.0,
+                "{} {} {}",
+                i,
+                c,
+                expected
+            );
+        }
+        // the distant light as often as the rest, the nearest light more
+        // often than the farthest one
+        assert_eq!(bvh.pmf(&p, 101), 0.5);
+        assert!(bvh.pmf(&p, 0) > 10.0 * bvh.pmf(&p, 99));
+        // by power alone the equal spheres are equally likely
+        let (i, pmf) = bvh.sample_power(0.75).unwrap();
+        assert!(i < 100 && (pmf - bvh.pmf_power(99)).abs() < 1e-6);
+        let sphere = SphereLight::new(Vec3::new(0.0, 0.0, 0.0), 0.1, white).power();
+        let point = PointLight::new(Vec3::new(5.0, 5.0, 5.0), white).power();
+        let expected = 0.5 * sphere / (100.0 * sphere + point);
+        assert!((bvh.pmf_power(42) - expected).abs() < 1e-4 * expected);
+    }
+}
@@ -1,14 +1,82 @@
-use crate::core::{HitRecord, Material};
-use crate::geometry::{dot, reflect, refract, Ray, Vec3};
-use rand::Rng;
+use crate::core::{HitRecord, Interior, Material, SampledSpectrum, SampledWavelengths};
+use crate::geometry::{dot, normalize, random, reflect, refract, Float, Ray, Vec3};
 
-pub struct Dielectric {
-    ref_idx: f32
+// Wavelength used for dispersive materials when rendering in RGB (sodium d-line).
+const LAMBDA_D: Float = 587.6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ior {
+    Constant(Float),
+    // n = a + b / lambda^2, lambda in micrometers
+    Cauchy { a: Float, b: Float },
+    // n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i), lambda in micrometers
+    Sellmeier { b: [Float; 3], c: [Float; 3] },
+}
+impl Ior {
+    pub fn bk7() -> Self {
+        Ior::Sellmeier {
+            b: [1.039_612, 0.231_792, 1.010_469],
+            c: [0.006_000_7, 0.020_017_9, 103.560_65],
+        }
+    }
+    pub fn diamond() -> Self {
+        Ior::Sellmeier {
+            b: [0.3306, 4.3356, 0.0],
+            c: [0.030_625, 0.011_236, 0.0],
+        }
+    }
+    pub fn at(&self, lambda: Float) -> Float {
+        let l = lambda / 1000.0;
+        let l2 = l * l;
+        match *self {
+            Ior::Constant(n) => n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                let mut n2 = 1.0;
+                for i in 0..3 {
+                    n2 += b[i] * l2 / (l2 - c[i]);
+                }
+                n2.sqrt()
+            }
+        }
+    }
+    pub fn nominal(&self) -> Float {
+        self.at(LAMBDA_D)
+    }
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
 
+pub struct Dielectric {
+    interior: Interior,
 }
 impl Dielectric {
-    pub fn new( ref_idx : f32) -> Self {
-        Self { ref_idx }
+    pub fn new(ref_idx: Float) -> Self {
+        Self::with_ior(Ior::Constant(ref_idx))
+    }
+    pub fn with_ior(ior: Ior) -> Self {
+        Self {
+            interior: Interior::new(ior),
+        }
+    }
+    // Beer-Lambert absorption coefficient of the interior, per unit distance.
+    pub fn with_absorption(mut self, sigma_a: Vec3) -> Self {
+        self.interior.sigma_a = sigma_a;
+        self
+    }
+    // Absorption such that light travelling `distance` through the interior
+    // is tinted by `color`.
+    pub fn with_transmittance(self, color: Vec3, distance: Float) -> Self {
+        let mut sigma_a = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..3 {
+            sigma_a[i] = -color[i].max(1e-6).ln() / distance;
+        }
+        self.with_absorption(sigma_a)
+    }
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.interior.priority = priority;
+        self
     }
 }
 impl Material for Dielectric {
@@ -19,39 +87,66 @@ impl Material for Dielectric {
         attenuation: &mut Vec3,
         scattered: &mut Ray,
     ) -> bool {
-        let outward_normal : Vec3;
-        let reflected = reflect(&r_in.d, &rec.normal);
-        let ni_over_nt : f32;
-        *attenuation = Vec3::new(1.0,1.0,1.0);
-        let mut refracted = Vec3::new(1.0,0.0,0.0);
-        let reflect_prob : f32;
-        let cosine : f32;
-        if dot(&r_in.d, &rec.normal) > 0.0 {
-            outward_normal = -rec.normal;
-            ni_over_nt = self.ref_idx;
-            cosine = self.ref_idx * dot(&r_in.d, &rec.normal) / r_in.d.length();
-        } else {
-            outward_normal = rec.normal;
-            ni_over_nt = 1.0 / self.ref_idx;
-            cosine = -dot(&r_in.d, &rec.normal) / r_in.d.length();
-        }
-        if refract(&r_in.d, &outward_normal, ni_over_nt, &mut refracted) == true {
-            reflect_prob = schlick(cosine, self.ref_idx);
-        } else {
-            reflect_prob = 1.0;
-        }
-        let rand_value : f32 = rand::thread_rng().gen();
-        if rand_value < reflect_prob {
-            *scattered = Ray::new(rec.p, reflected);
+        *attenuation = Vec3::new(1.0, 1.0, 1.0);
+        let ref_idx = self.interior.ior.nominal() / rec.outer_ior;
+        *scattered = scatter_dielectric(r_in, rec, ref_idx);
+        true
+    }
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wl: &mut SampledWavelengths,
+        attenuation: &mut SampledSpectrum,
+        scattered: &mut Ray,
+    ) -> bool {
+        if self.interior.ior.is_dispersive() {
+            wl.terminate_secondary();
         }
-        else {
-        }    *scattered = Ray::new(rec.p, refracted);
-            
+        *attenuation = SampledSpectrum::new(1.0);
+        let ref_idx = self.interior.ior.at(wl.hero()) / rec.outer_ior;
+        *scattered = scatter_dielectric(r_in, rec, ref_idx);
         true
     }
+    fn interior(&self) -> Option<&Interior> {
+        Some(&self.interior)
+    }
+}
+
+fn scatter_dielectric(r_in: &Ray, rec: &HitRecord, ref_idx: Float) -> Ray {
+    let outward_normal: Vec3;
+    let ni_over_nt: Float;
+    // the geometric normal tells the side, the shading one bends the ray
+    let n = rec.shading_normal;
+    if dot(&r_in.d, &rec.normal) > 0.0 {
+        outward_normal = -n;
+        ni_over_nt = ref_idx;
+    } else {
+        outward_normal = n;
+        ni_over_nt = 1.0 / ref_idx;
+    }
+    let mut refracted = Vec3::new(1.0, 0.0, 0.0);
+    // total internal reflection when nothing refracts
+    let reflect_prob = if refract(&r_in.d, &outward_normal, ni_over_nt, &mut refracted) {
+        // the angle on the side of the lower index, so that light is
+        // reflected alike both ways through the surface
+        let cosine = if ni_over_nt > 1.0 {
+            dot(&normalize(refracted), &n).abs()
+        } else {
+            dot(&r_in.d, &n).abs() / r_in.d.length()
+        };
+        schlick(cosine, ref_idx)
+    } else {
+        1.0
+    };
+    if random() < reflect_prob {
+        rec.spawn_ray(reflect(&r_in.d, &n))
+    } else {
+        rec.spawn_ray(refracted)
+    }
 }
 
-fn schlick(cosine : f32, ref_idx : f32) -> f32 {
+fn schlick(cosine: Float, ref_idx: Float) -> Float {
     let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     r0 = r0 * r0;
     r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
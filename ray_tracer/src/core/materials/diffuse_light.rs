@@ -0,0 +1,40 @@
+use crate::core::{HitRecord, Material};
+use crate::geometry::{dot, Ray, Vec3};
+
+// Surface of an area light, emitting the same radiance in every direction
+// from its front side.
+#[derive(Clone)]
+pub struct DiffuseLight {
+    emission: Vec3,
+    light: Option<usize>,
+}
+impl DiffuseLight {
+    pub fn new(emission: Vec3) -> Self {
+        Self {
+            emission,
+            light: None,
+        }
+    }
+    // The light it is the surface of, so that hits can be weighted against
+    // light sampling.
+    pub fn with_light(mut self, index: usize) -> Self {
+        self.light = Some(index);
+        self
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _: &Ray, _: &HitRecord, _: &mut Vec3, _: &mut Ray) -> bool {
+        false
+    }
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        if dot(&r_in.d, &rec.normal) < 0.0 {
+            self.emission
+        } else {
+            Vec3::new(0.0, 0.0, 0.0)
+        }
+    }
+    fn light_index(&self) -> Option<usize> {
+        self.light
+    }
+}
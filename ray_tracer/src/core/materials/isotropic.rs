@@ -0,0 +1,36 @@
+use crate::core::{HitRecord, Material};
+use crate::geometry::{consts, random_unit_vector, Float, Ray, Vec3};
+
+// Phase function of participating media, scattering equally in every
+// direction.
+pub struct Isotropic {
+    albedo: Vec3,
+}
+impl Isotropic {
+    pub fn new(albedo: Vec3) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        *scattered = Ray::new(rec.p, random_unit_vector());
+        *attenuation = self.albedo;
+        true
+    }
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _wi: &Vec3) -> Option<Vec3> {
+        Some(self.albedo / (4.0 * consts::PI))
+    }
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _wi: &Vec3) -> Float {
+        1.0 / (4.0 * consts::PI)
+    }
+    fn is_volumetric(&self) -> bool {
+        true
+    }
+}
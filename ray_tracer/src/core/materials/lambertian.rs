@@ -1,5 +1,5 @@
 use crate::core::{HitRecord, Material};
-use crate::geometry::{random_in_unit_sphere, Ray, Vec3};
+use crate::geometry::{consts, dot, random_unit_vector, Float, Ray, Vec3};
 
 pub struct Lambertian {
     albedo: Vec3,
@@ -13,14 +13,33 @@ impl Lambertian {
 impl Material for Lambertian {
     fn scatter(
         &self,
-        _r_in: &Ray,
+        r_in: &Ray,
         rec: &HitRecord,
         attenuation: &mut Vec3,
         scattered: &mut Ray,
     ) -> bool {
-        let target = rec.p + rec.normal + random_in_unit_sphere();
-        *scattered = Ray::new(rec.p, target - rec.p);
+        // open surfaces are seen from both sides
+        let n = if dot(&r_in.d, &rec.normal) > 0.0 {
+            -rec.shading_normal
+        } else {
+            rec.shading_normal
+        };
+        // cosine distributed, matching pdf()
+        let target = rec.p + n + random_unit_vector();
+        *scattered = rec.spawn_ray(target - rec.p);
         *attenuation = self.albedo;
         true
     }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Option<Vec3> {
+        Some(self.pdf(r_in, rec, wi) * self.albedo)
+    }
+    // cosine weighted, so that f cos = albedo pdf
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Float {
+        let n = if dot(&r_in.d, &rec.normal) > 0.0 {
+            -rec.shading_normal
+        } else {
+            rec.shading_normal
+        };
+        dot(wi, &n).max(0.0) / consts::PI
+    }
 }
@@ -1,18 +1,17 @@
 use crate::core::{HitRecord, Material};
-use crate::geometry::{dot, normalize, reflect, random_in_unit_sphere, Ray, Vec3};
+use crate::geometry::{consts, dot, normalize, random_in_unit_sphere, reflect, Float, Ray, Vec3};
 
 pub struct Metal {
     albedo: Vec3,
-    fuzz: f32
-
+    fuzz: Float,
 }
 impl Metal {
-    pub fn new(albedo: Vec3, fuzz : f32) -> Self {
-        let mut f = 1f32;
+    pub fn new(albedo: Vec3, fuzz: Float) -> Self {
+        let mut f = 1.0;
         if fuzz < 1.0 {
             f = fuzz;
         }
-        Self { albedo, fuzz : f }
+        Self { albedo, fuzz: f }
     }
 }
 impl Material for Metal {
@@ -24,9 +23,81 @@ impl Material for Metal {
         scattered: &mut Ray,
     ) -> bool {
         let v = normalize(r_in.d);
-        let reflected = reflect(&v, &rec.normal);
-        *scattered = Ray::new(rec.p, reflected + self.fuzz * random_in_unit_sphere());
+        // open surfaces are seen from both sides
+        let n = if dot(&v, &rec.normal) > 0.0 {
+            -rec.shading_normal
+        } else {
+            rec.shading_normal
+        };
+        let reflected = reflect(&v, &n);
+        *scattered = rec.spawn_ray(reflected + self.fuzz * random_in_unit_sphere());
         *attenuation = self.albedo;
-        dot(&scattered.d, &rec.normal) > 0f32
+        dot(&scattered.d, &n) > 0.0
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Option<Vec3> {
+        if self.fuzz == 0.0 {
+            return None;
+        }
+        // the sampling weight is the albedo, so f cos = albedo pdf
+        Some(self.pdf(r_in, rec, wi) * self.albedo)
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Float {
+        if self.fuzz == 0.0 {
+            return 0.0;
+        }
+        let v = normalize(r_in.d);
+        let n = if dot(&v, &rec.normal) > 0.0 {
+            -rec.shading_normal
+        } else {
+            rec.shading_normal
+        };
+        if dot(wi, &n) <= 0.0 {
+            return 0.0;
+        }
+        // Scattered directions point from the origin to uniform points of the
+        // fuzz ball around the reflection. Their density is the volume of the
+        // ball along wi, s^2 ds over the chord.
+        let b = dot(wi, &reflect(&v, &n));
+        let disc = b * b - 1.0 + self.fuzz * self.fuzz;
+        if disc <= 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = ((b - disc.sqrt()).max(0.0), b + disc.sqrt());
+        if s1 <= 0.0 {
+            return 0.0;
+        }
+        (s1 * s1 * s1 - s0 * s0 * s0) / (4.0 * consts::PI * self.fuzz.powi(3))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::materials::Metal;
+    use crate::core::{HitRecord, Material};
+    use crate::geometry::{consts, Float, Ray, Vec3};
+    #[test]
+    fn fuzz_density_integrates_to_one() {
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.shading_normal = rec.normal;
+        let r = Ray::new(Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, -1.0));
+        let metal = Metal::new(Vec3::new(1.0, 1.0, 1.0), 0.5);
+        // midpoint rule over the hemisphere, in z = cos theta and phi
+        let n = 400;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let z = (i as Float + 0.5) / n as Float;
+                let phi = 2.0 * consts::PI * (j as Float + 0.5) / n as Float;
+                let r_xy = (1.0 - z * z).sqrt();
+                let wi = Vec3::new(r_xy * phi.cos(), r_xy * phi.sin(), z);
+                sum += metal.eval(&r, &rec, &wi).unwrap().x;
+            }
+        }
+        let integral = sum * 2.0 * consts::PI / (n * n) as Float;
+        // all of the fuzz ball is above the surface
+        assert!((integral - 1.0).abs() < 1e-2, "{}", integral);
+        let mirror = Metal::new(Vec3::new(1.0, 1.0, 1.0), 0.0);
+        assert!(mirror.eval(&r, &rec, &Vec3::new(0.0, 0.0, 1.0)).is_none());
     }
 }
@@ -1,7 +1,297 @@
+mod dielectric;
+mod diffuse_light;
+mod isotropic;
 mod lambertian;
 mod metal;
-mod dielectric;
+mod normal_map;
 
+pub use self::dielectric::*;
+pub use self::diffuse_light::*;
+pub use self::isotropic::*;
 pub use self::lambertian::*;
 pub use self::metal::*;
-pub use self::dielectric::*;
\ No newline at end of file
+pub use self::normal_map::*;
+
+// Statistical checks of every material against its own eval and pdf, at a
+// surface at the origin facing +z.
+#[cfg(test)]
+mod tests {
+    use crate::core::materials::{
+        Dielectric, Isotropic, Lambertian, Metal, NormalMap, NormalMapped,
+    };
+    use crate::core::{HitRecord, Material, Texture};
+    use crate::geometry::{consts, dot, normalize, seed_random, Float, Ray, Vec3};
+    use std::sync::Arc;
+    fn surface() -> HitRecord {
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.shading_normal = rec.normal;
+        rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 1.0, 0.0);
+        rec
+    }
+    // A ray arriving at the origin from direction wo.
+    fn arriving(wo: Vec3) -> Ray {
+        Ray::new(wo, -wo)
+    }
+    fn direction(z: Float, phi: Float) -> Vec3 {
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+    fn white() -> Vec3 {
+        Vec3::new(1.0, 1.0, 1.0)
+    }
+    // Non-specular materials with an albedo of one.
+    fn materials() -> Vec<(&'static str, Arc<dyn Material>)> {
+        let tilt = normalize(Vec3::new(0.3, -0.2, 1.0));
+        let rgb = 0.5 * (tilt + white());
+        vec![
+            ("lambertian", Arc::new(Lambertian::new(white()))),
+            ("metal", Arc::new(Metal::new(white(), 0.3))),
+            ("rough metal", Arc::new(Metal::new(white(), 0.8))),
+            ("isotropic", Arc::new(Isotropic::new(white()))),
+            (
+                "normal mapped",
+                Arc::new(NormalMapped::new(
+                    Arc::new(Lambertian::new(white())),
+                    NormalMap::Tangent(Texture::Constant(rgb)),
+                )),
+            ),
+        ]
+    }
+    fn incoming() -> Vec<Vec3> {
+        vec![
+            direction(1.0, 0.0),
+            direction(0.7, 0.5),
+            direction(0.2, 2.0),
+        ]
+    }
+    // Midpoint rule over the sphere, in z = cos theta and phi.
+    fn integrate<F: Fn(Vec3) -> f64>(n: usize, f: F) -> f64 {
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..2 * n {
+                let z = -1.0 + 2.0 * (i as Float + 0.5) / n as Float;
+                let phi = consts::PI * (j as Float + 0.5) / n as Float;
+                sum += f(direction(z, phi));
+            }
+        }
+        sum * 4.0 * std::f64::consts::PI / (2 * n * n) as f64
+    }
+    fn sample(mat: &dyn Material, r: &Ray, rec: &HitRecord) -> Option<(Vec3, Vec3)> {
+        let mut scattered = Ray::new(white(), white());
+        let mut attenuation = white();
+        if mat.scatter(r, rec, &mut attenuation, &mut scattered) {
+            Some((normalize(scattered.d), attenuation))
+        } else {
+            None
+        }
+    }
+    #[test]
+    #[allow(clippy::unnecessary_cast)]
+    fn white_furnace() {
+        seed_random(11);
+        let rec = surface();
+        for (name, mat) in materials() {
+            for wo in incoming() {
+                let r = arriving(wo);
+                // reflected energy from the sampling weights and from eval,
+                // which must agree and can not be more than what arrived
+                let n = 100000;
+                let sampled = (0..n)
+                    .filter_map(|_| sample(&*mat, &r, &rec))
+                    .map(|(_, attenuation)| attenuation.x as f64)
+                    .sum::<f64>()
+                    / n as f64;
+                let evaluated = integrate(400, |wi| {
+                    mat.eval(&r, &rec, &wi).map_or(0.0, |f| f.x as f64)
+                });
+                assert!(
+                    (sampled - evaluated).abs() < 0.01,
+                    "{} at {:?}: sampled {} evaluated {}",
+                    name,
+                    wo,
+                    sampled,
+                    evaluated
+                );
+                assert!(
+                    evaluated < 1.0 + 1e-3,
+                    "{} at {:?}: {}",
+                    name,
+                    wo,
+                    evaluated
+                );
+                if name == "lambertian" || name == "isotropic" {
+                    assert!((evaluated - 1.0).abs() < 1e-3, "{}: {}", name, evaluated);
+                }
+            }
+        }
+        // glass sends all the light on, reflected or refracted, from either
+        // side and past the critical angle
+        let glass = Dielectric::new(1.5);
+        for wo in incoming() {
+            for r in [arriving(wo), arriving(-wo)] {
+                let n = 100000;
+                let (mut reflected, mut refracted) = (0.0, 0.0);
+                for _ in 0..n {
+                    if let Some((wi, attenuation)) = sample(&glass, &r, &rec) {
+                        let throughput = (attenuation.x + attenuation.y + attenuation.z) / 3.0;
+                        if dot(&wi, &rec.normal) * dot(&r.d, &rec.normal) < 0.0 {
+                            reflected += throughput as f64 / n as f64;
+                        } else {
+                            refracted += throughput as f64 / n as f64;
+                        }
+                    }
+                }
+                assert!(
+                    (reflected + refracted - 1.0).abs() < 1e-3,
+                    "{:?}: reflected {} refracted {}",
+                    r.d,
+                    reflected,
+                    refracted
+                );
+                let sin = (1.0 - wo.z * wo.z).sqrt();
+                if r.d.z > 0.0 && 1.5 * sin > 1.0 {
+                    assert_eq!(refracted, 0.0, "{:?}", r.d);
+                } else if wo.z == 1.0 {
+                    // ((1 - 1.5) / (1 + 1.5))^2 head on
+                    assert!((reflected - 0.04).abs() < 0.005, "{}", reflected);
+                } else {
+                    assert!(reflected > 0.04 && refracted > 0.5, "{}", reflected);
+                }
+            }
+        }
+    }
+    #[test]
+    fn reciprocity() {
+        seed_random(12);
+        let rec = surface();
+        // the fuzz ball of metals is a sampling rule rather than a reciprocal
+        // BRDF, and shading normals break reciprocity on purpose
+        for (name, mat) in materials() {
+            if name.contains("metal") || name == "normal mapped" {
+                continue;
+            }
+            // f(wo, wi), without the cosine eval includes on surfaces
+            let f = |wo: Vec3, wi: Vec3| {
+                let f = mat.eval(&arriving(wo), &rec, &wi).unwrap().x;
+                if mat.is_volumetric() {
+                    f
+                } else {
+                    f / dot(&wi, &rec.normal).abs()
+                }
+            };
+            for wo in incoming() {
+                for wi in incoming() {
+                    let wi = Vec3::new(-wi.x, wi.y, wi.z);
+                    let (a, b) = (f(wo, wi), f(wi, wo));
+                    assert!((a - b).abs() < 1e-4 * a.max(b), "{}: {} {}", name, a, b);
+                }
+            }
+        }
+        // glass reflects as much entering at some angle as leaving along the
+        // ray refracted from there
+        let glass = Dielectric::new(1.5);
+        let wo = direction(0.5, 0.0);
+        let reflected = |r: &Ray| {
+            let n = 200000;
+            let count = (0..n)
+                .filter(|_| {
+                    let (wi, _) = sample(&glass, r, &rec).unwrap();
+                    dot(&wi, &rec.normal) * dot(&r.d, &rec.normal) < 0.0
+                })
+                .count();
+            count as Float / n as Float
+        };
+        // from inside, along the reverse of the refracted ray
+        let sin_t = (1.0 - wo.z * wo.z).sqrt() / 1.5;
+        let inside = Ray::new(
+            Vec3::new(-sin_t, 0.0, -(1.0 - sin_t * sin_t).sqrt()),
+            Vec3::new(sin_t, 0.0, (1.0 - sin_t * sin_t).sqrt()),
+        );
+        let (entering, leaving) = (reflected(&arriving(wo)), reflected(&inside));
+        assert!(entering > 0.05, "{}", entering);
+        assert!(
+            (entering - leaving).abs() < 0.005,
+            "{} {}",
+            entering,
+            leaving
+        );
+        // past the critical angle nothing leaves
+        let grazing = Ray::new(Vec3::new(-0.8, 0.0, -0.6), Vec3::new(0.8, 0.0, 0.6));
+        assert_eq!(reflected(&grazing), 1.0);
+    }
+
+    // Pearson's test that sampled directions follow the pdf, over bins of
+    // z and phi. Bins expecting too few samples are pooled.
+    #[test]
+    #[allow(clippy::unnecessary_cast)]
+    fn chi_square() {
+        seed_random(13);
+        let rec = surface();
+        let (nz, nphi, n) = (10, 20, 200000);
+        for (name, mat) in materials() {
+            for wo in incoming() {
+                let r = arriving(wo);
+                let bin = |d: Vec3| {
+                    let z = ((d.z + 1.0) / 2.0 * nz as Float) as usize;
+                    let phi = (d.y.atan2(d.x) + consts::PI) / (2.0 * consts::PI);
+                    z.min(nz - 1) * nphi + ((phi * nphi as Float) as usize).min(nphi - 1)
+                };
+                let mut observed = vec![0.0; nz * nphi];
+                for _ in 0..n {
+                    if let Some((d, _)) = sample(&*mat, &r, &rec) {
+                        observed[bin(d)] += 1.0;
+                    }
+                }
+                // pdf integrated over each bin
+                let mut expected = vec![0.0; nz * nphi];
+                let sub = 32;
+                let area = 4.0 * std::f64::consts::PI / (nz * nphi * sub * sub) as f64;
+                for (i, e) in expected.iter_mut().enumerate() {
+                    let (iz, iphi) = (i / nphi, i % nphi);
+                    for s in 0..sub * sub {
+                        let z = -1.0
+                            + 2.0 * (iz as Float + ((s / sub) as Float + 0.5) / sub as Float)
+                                / nz as Float;
+                        let phi = -consts::PI
+                            + 2.0
+                                * consts::PI
+                                * (iphi as Float + ((s % sub) as Float + 0.5) / sub as Float)
+                                / nphi as Float;
+                        *e += mat.pdf(&r, &rec, &direction(z, phi)) as f64 * area * n as f64;
+                    }
+                }
+                let (mut stat, mut cells) = (0.0, 0);
+                let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+                for (o, e) in observed.iter().zip(&expected) {
+                    if *e < 5.0 {
+                        pooled_observed += o;
+                        pooled_expected += e;
+                    } else {
+                        stat += (o - e) * (o - e) / e;
+                        cells += 1;
+                    }
+                }
+                if pooled_expected > 0.0 {
+                    stat += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+                    cells += 1;
+                } else {
+                    assert_eq!(pooled_observed, 0.0, "{}: samples where the pdf is 0", name);
+                }
+                // the 99.9% quantile, by the Wilson-Hilferty approximation
+                let k = (cells - 1) as f64;
+                let limit = k * (1.0 - 2.0 / (9.0 * k) + 3.09 * (2.0 / (9.0 * k)).sqrt()).powi(3);
+                assert!(
+                    stat < limit,
+                    "{} at {:?}: chi square {} over {} bins, limit {}",
+                    name,
+                    wo,
+                    stat,
+                    cells,
+                    limit
+                );
+            }
+        }
+    }
+}
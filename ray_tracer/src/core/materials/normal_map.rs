@@ -0,0 +1,186 @@
+use crate::core::{HitRecord, Interior, Material, SampledSpectrum, SampledWavelengths, Texture};
+use crate::geometry::{cross, dot, normalize, Float, Ray, Vec3};
+use std::sync::Arc;
+
+pub enum NormalMap {
+    // heights along the normal, in scene units per texture unit
+    Bump(Texture, Float),
+    // tangent space normals encoded as rgb = (n + 1) / 2
+    Tangent(Texture),
+}
+
+// Another material shaded with the normals of surface detail that is not in
+// the geometry. Surfaces without tangents are left as they are.
+pub struct NormalMapped {
+    material: Arc<dyn Material>,
+    map: NormalMap,
+}
+impl NormalMapped {
+    pub fn new(material: Arc<dyn Material>, map: NormalMap) -> Self {
+        Self { material, map }
+    }
+    // The record with the mapped shading normal, flipped to the side of the
+    // geometric normal. Keeps the original one when the ray would see the
+    // mapped surface from behind.
+    pub fn shade(&self, r_in: &Ray, rec: &HitRecord) -> HitRecord {
+        let mut shading = rec.clone();
+        let n = rec.shading_normal;
+        if rec.dpdu.length2() == 0.0 || rec.dpdv.length2() == 0.0 {
+            return shading;
+        }
+        let mut mapped = match &self.map {
+            NormalMap::Bump(height, scale) => {
+                // normal of p + h(u, v) n, neglecting the change of n
+                let d = 1.0 / 1024.0;
+                let h = height.scalar(rec.u, rec.v);
+                let dhdu = (height.scalar(rec.u + d, rec.v) - h) / d;
+                let dhdv = (height.scalar(rec.u, rec.v + d) - h) / d;
+                let dpdu = rec.dpdu + scale * dhdu * n;
+                let dpdv = rec.dpdv + scale * dhdv * n;
+                normalize(cross(&dpdu, &dpdv))
+            }
+            NormalMap::Tangent(texture) => {
+                let c = 2.0 * texture.value(rec.u, rec.v) - Vec3::new(1.0, 1.0, 1.0);
+                let t = normalize(rec.dpdu - dot(&n, &rec.dpdu) * n);
+                let mut b = cross(&n, &t);
+                if dot(&b, &rec.dpdv) < 0.0 {
+                    b = -b;
+                }
+                normalize(c.x * t + c.y * b + c.z * n)
+            }
+        };
+        if dot(&mapped, &n) < 0.0 {
+            mapped = -mapped;
+        }
+        if dot(&r_in.d, &mapped) * dot(&r_in.d, &rec.normal) > 0.0 {
+            shading.shading_normal = mapped;
+        }
+        shading
+    }
+}
+impl Material for NormalMapped {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.material
+            .scatter(r_in, &self.shade(r_in, rec), attenuation, scattered)
+    }
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wl: &mut SampledWavelengths,
+        attenuation: &mut SampledSpectrum,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.material
+            .scatter_spectral(r_in, &self.shade(r_in, rec), wl, attenuation, scattered)
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Option<Vec3> {
+        self.material.eval(r_in, &self.shade(r_in, rec), wi)
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Float {
+        self.material.pdf(r_in, &self.shade(r_in, rec), wi)
+    }
+    fn interior(&self) -> Option<&Interior> {
+        self.material.interior()
+    }
+    fn shading_normal(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.material.shading_normal(r_in, &self.shade(r_in, rec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::materials::{Lambertian, NormalMap, NormalMapped};
+    use crate::core::{HitRecord, Hitable, SphereObject, Texture};
+    use crate::geometry::{consts, dot, normalize, Float, Ray, Vec3};
+    use std::sync::Arc;
+    fn hit_sphere(r: &Ray) -> HitRecord {
+        let sphere = SphereObject {
+            center: Vec3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            mat: None,
+        };
+        let mut rec = HitRecord::new();
+        assert!(sphere.hit(r, 0.0, Float::INFINITY, &mut rec));
+        rec
+    }
+    fn mapped(map: NormalMap) -> NormalMapped {
+        NormalMapped::new(Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))), map)
+    }
+    #[test]
+    fn sphere_tangents() {
+        let r = Ray::new(Vec3::new(3.0, 2.0, 1.0), Vec3::new(-3.0, -2.0, -1.5));
+        let rec = hit_sphere(&r);
+        // finite differences of the uv mapping
+        let d = 1e-3;
+        let point = |u: Float, v: Float| {
+            let (phi, theta) = (2.0 * consts::PI * u - consts::PI, consts::PI * v);
+            Vec3::new(
+                theta.sin() * phi.cos(),
+                -theta.cos(),
+                -theta.sin() * phi.sin(),
+            )
+        };
+        assert!((point(rec.u, rec.v) - rec.p).length() < 1e-4);
+        let dpdu = (point(rec.u + d, rec.v) - point(rec.u - d, rec.v)) / (2.0 * d);
+        let dpdv = (point(rec.u, rec.v + d) - point(rec.u, rec.v - d)) / (2.0 * d);
+        assert!(
+            (dpdu - rec.dpdu).length() < 1e-2,
+            "{:?} {:?}",
+            dpdu,
+            rec.dpdu
+        );
+        assert!(
+            (dpdv - rec.dpdv).length() < 1e-2,
+            "{:?} {:?}",
+            dpdv,
+            rec.dpdv
+        );
+    }
+    #[test]
+    fn flat_maps_keep_the_normal() {
+        let r = Ray::new(Vec3::new(3.0, 2.0, 1.0), Vec3::new(-3.0, -2.0, -1.5));
+        let rec = hit_sphere(&r);
+        for map in [
+            NormalMap::Bump(Texture::Constant(Vec3::new(0.3, 0.3, 0.3)), 0.1),
+            NormalMap::Tangent(Texture::Constant(Vec3::new(0.5, 0.5, 1.0))),
+        ] {
+            let n = mapped(map).shade(&r, &rec).shading_normal;
+            assert!(dot(&n, &rec.normal) > 0.9999, "{:?} {:?}", n, rec.normal);
+        }
+    }
+    #[test]
+    fn bumps_tilt_the_normal() {
+        // facing +x, where u grows along -z and v along +y
+        let r = Ray::new(Vec3::new(3.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let rec = hit_sphere(&r);
+        // a slope rising with u tilts the normal back along -u, that is +z
+        let ramp = mapped(NormalMap::Bump(
+            Texture::from_fn(|u, _| Vec3::new(u, u, u)),
+            1.0,
+        ));
+        let n = ramp.shade(&r, &rec).shading_normal;
+        assert!(n.z > 0.05 && n.x > 0.9 && n.y.abs() < 1e-3, "{:?}", n);
+        // a normal map tilted towards +u, that is -z
+        let tilt = normalize(Vec3::new(0.3, 0.0, 1.0));
+        let rgb = 0.5 * (tilt + Vec3::new(1.0, 1.0, 1.0));
+        let map = mapped(NormalMap::Tangent(Texture::Constant(rgb)));
+        let n = map.shade(&r, &rec).shading_normal;
+        assert!(
+            (n - Vec3::new(tilt.z, 0.0, -tilt.x)).length() < 1e-4,
+            "{:?}",
+            n
+        );
+        // a grazing ray that would see the tilted surface from behind
+        let r = Ray::new(Vec3::new(3.0, 0.0, -10.0), Vec3::new(-2.0, 0.0, 10.0));
+        let rec = hit_sphere(&r);
+        let n = ramp.shade(&r, &rec).shading_normal;
+        assert!(dot(&r.d, &n) < 0.0);
+    }
+}
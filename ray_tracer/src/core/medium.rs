@@ -0,0 +1,398 @@
+use crate::core::materials::Isotropic;
+use crate::core::stats::{count, Counter};
+use crate::core::{HitRecord, Hitable, Material};
+use crate::geometry::{normalize, random, Aabb, Float, Ray, Vec3};
+use std::fs;
+use std::sync::Arc;
+
+// Number of voxels of a grid, whose dimensions come from files.
+fn voxels(nx: usize, ny: usize, nz: usize) -> Result<usize, String> {
+    nx.checked_mul(ny)
+        .and_then(|n| n.checked_mul(nz))
+        .ok_or_else(|| format!("grid of {}x{}x{} voxels is too large", nx, ny, nz))
+}
+
+// Voxel values over the unit cube, x varying fastest, then y, then z. Files
+// are either ASCII, "nx ny nz" followed by the values, or binary, "GRID"
+// followed by the dimensions as little endian u32 and the values as f32.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DensityGrid {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    pub values: Vec<Float>,
+    max: Float,
+}
+impl DensityGrid {
+    pub fn new(nx: usize, ny: usize, nz: usize, values: Vec<Float>) -> Result<Self, String> {
+        let n = voxels(nx, ny, nz)?;
+        if n == 0 || values.len() != n {
+            return Err(format!("expected {} values, found {}", n, values.len()));
+        }
+        if values.iter().any(|&v| v < 0.0 || v.is_nan()) {
+            return Err("negative density".to_string());
+        }
+        let max = values.iter().cloned().fold(0.0, Float::max);
+        Ok(Self {
+            nx,
+            ny,
+            nz,
+            values,
+            max,
+        })
+    }
+    pub fn load(path: &str) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse(&data).map_err(|e| format!("{}: {}", path, e))
+    }
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if let Some(data) = data.strip_prefix(b"GRID") {
+            let word = |i: usize| {
+                data.get(4 * i..4 * i + 4)
+                    .map(|b| [b[0], b[1], b[2], b[3]])
+                    .ok_or("truncated grid")
+            };
+            let dim = |i| word(i).map(|w| u32::from_le_bytes(w) as usize);
+            let (nx, ny, nz) = (dim(0)?, dim(1)?, dim(2)?);
+            let values = (0..voxels(nx, ny, nz)?)
+                .map(|i| word(3 + i).map(|w| f32::from_le_bytes(w) as Float))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Self::new(nx, ny, nz, values);
+        }
+        let text = String::from_utf8_lossy(data);
+        let mut tokens = text
+            .lines()
+            .map(|l| l.split('#').next().unwrap_or(""))
+            .flat_map(|l| l.split_whitespace());
+        let mut dim = || -> Result<usize, String> {
+            let t = tokens.next().ok_or("missing grid size")?;
+            t.parse().map_err(|_| format!("invalid grid size '{}'", t))
+        };
+        let (nx, ny, nz) = (dim()?, dim()?, dim()?);
+        let values = tokens
+            .map(|t| t.parse().map_err(|_| format!("invalid value '{}'", t)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(nx, ny, nz, values)
+    }
+    pub fn max(&self) -> Float {
+        self.max
+    }
+    // Trilinear interpolation between voxel centers, with p in [0, 1]^3 and
+    // the values at the border held constant.
+    pub fn lookup(&self, p: &Vec3) -> Float {
+        let n = [self.nx, self.ny, self.nz];
+        let mut i0 = [0; 3];
+        let mut i1 = [0; 3];
+        let mut f = [0.0; 3];
+        for k in 0..3 {
+            let x = (p[k] * n[k] as Float - 0.5)
+                .max(0.0)
+                .min((n[k] - 1) as Float);
+            i0[k] = x.floor() as usize;
+            i1[k] = (i0[k] + 1).min(n[k] - 1);
+            f[k] = x - i0[k] as Float;
+        }
+        let v = |x: usize, y: usize, z: usize| self.values[(z * self.ny + y) * self.nx + x];
+        let lerp = |t: Float, a: Float, b: Float| (1.0 - t) * a + t * b;
+        let plane = |z: usize| {
+            lerp(
+                f[1],
+                lerp(f[0], v(i0[0], i0[1], z), v(i1[0], i0[1], z)),
+                lerp(f[0], v(i0[0], i1[1], z), v(i1[0], i1[1], z)),
+            )
+        };
+        lerp(f[2], plane(i0[2]), plane(i1[2]))
+    }
+}
+
+// Light given off by a medium where it absorbs, color times the value of a
+// grid over the same box.
+struct Emission {
+    color: Vec3,
+    grid: Option<DensityGrid>,
+}
+
+// Absorption events of a medium, which end paths with the emitted light.
+struct Absorber {
+    bounds: Aabb,
+    density: Arc<DensityGrid>,
+    emission: Option<Emission>,
+}
+impl Material for Absorber {
+    fn scatter(&self, _: &Ray, _: &HitRecord, _: &mut Vec3, _: &mut Ray) -> bool {
+        false
+    }
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        match self.emission {
+            Some(ref e) => {
+                let grid = e.grid.as_ref().unwrap_or(&self.density);
+                grid.lookup(&local(&self.bounds, &rec.p)) * e.color
+            }
+            None => Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+fn scatter_probability(albedo: &Vec3) -> Float {
+    albedo.x.max(albedo.y).max(albedo.z).min(1.0)
+}
+
+fn local(bounds: &Aabb, p: &Vec3) -> Vec3 {
+    let d = bounds.max - bounds.min;
+    let o = *p - bounds.min;
+    Vec3::new(o.x / d.x, o.y / d.y, o.z / d.z)
+}
+
+// Heterogeneous participating medium with the extinction coefficient
+// sigma_t times a density grid stretched over a box. A boundary shape inside
+// the box can clip it, e.g. to a sphere. Collisions scatter isotropically
+// with probability given by the albedo and absorb otherwise.
+pub struct GridMedium {
+    bounds: Aabb,
+    density: Arc<DensityGrid>,
+    sigma_t: Float,
+    albedo: Vec3,
+    boundary: Option<Box<dyn Hitable>>,
+    phase: Arc<dyn Material>,
+    absorber: Arc<Absorber>,
+}
+impl GridMedium {
+    pub fn new(density: DensityGrid, bounds: Aabb, sigma_t: Float, albedo: Vec3) -> Self {
+        let density = Arc::new(density);
+        // scattered paths carry the albedo over the probability to scatter
+        let p = scatter_probability(&albedo);
+        let phase = Isotropic::new(if p > 0.0 { albedo / p } else { albedo });
+        Self {
+            bounds,
+            density: density.clone(),
+            sigma_t,
+            albedo,
+            boundary: None,
+            phase: Arc::new(phase),
+            absorber: Arc::new(Absorber {
+                bounds,
+                density,
+                emission: None,
+            }),
+        }
+    }
+    pub fn with_boundary(mut self, boundary: Box<dyn Hitable>) -> Self {
+        self.boundary = Some(boundary);
+        self
+    }
+    // Emission proportional to the density, or to another grid, e.g. the
+    // temperature of a fire.
+    pub fn with_emission(mut self, color: Vec3, grid: Option<DensityGrid>) -> Self {
+        self.absorber = Arc::new(Absorber {
+            bounds: self.bounds,
+            density: self.density.clone(),
+            emission: Some(Emission { color, grid }),
+        });
+        self
+    }
+    fn sigma_maj(&self) -> Float {
+        self.sigma_t * self.density.max()
+    }
+    fn sigma_t_at(&self, p: &Vec3) -> Float {
+        self.sigma_t * self.density.lookup(&local(&self.bounds, p))
+    }
+    // Parameter ranges of the ray inside the medium.
+    fn segments(&self, ray: &Ray, t_min: Float, t_max: Float) -> Vec<(Float, Float)> {
+        let (t0, t1) = match self.bounds.intersect(ray, t_min, t_max) {
+            Some(range) => range,
+            None => return vec![],
+        };
+        let boundary = match self.boundary {
+            Some(ref b) => b,
+            None => return vec![(t0, t1)],
+        };
+        let spans = match boundary.spans(ray) {
+            Some(spans) => spans.iter().map(|s| (s.enter.t, s.exit.t)).collect(),
+            None => {
+                // surfaces that are not solids are taken as entered once
+                let mut enter = HitRecord::new();
+                let mut exit = HitRecord::new();
+                if !boundary.hit(ray, -Float::INFINITY, Float::INFINITY, &mut enter)
+                    || !boundary.hit(ray, enter.t, Float::INFINITY, &mut exit)
+                {
+                    return vec![];
+                }
+                vec![(enter.t, exit.t)]
+            }
+        };
+        spans
+            .into_iter()
+            .map(|(a, b): (Float, Float)| (a.max(t0), b.min(t1)))
+            .filter(|(a, b)| a < b)
+            .collect()
+    }
+    // Delta tracking: tentative collisions with the majorant, accepted with
+    // probability sigma_t / sigma_maj.
+    fn sample_collision(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<Float> {
+        let sigma_maj = self.sigma_maj();
+        if sigma_maj <= 0.0 {
+            return None;
+        }
+        let scale = 1.0 / (sigma_maj * ray.d.length());
+        for (t0, t1) in self.segments(ray, t_min, t_max) {
+            let mut t = t0;
+            loop {
+                t -= (1.0 - random()).ln() * scale;
+                if t >= t1 {
+                    break;
+                }
+                count(Counter::IntersectionTests, 1);
+                if random() * sigma_maj < self.sigma_t_at(&ray.point_at_parameter(t)) {
+                    return Some(t);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Hitable for GridMedium {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        let t = match self.sample_collision(ray, t_min, t_max) {
+            Some(t) => t,
+            None => return false,
+        };
+        let mat: Arc<dyn Material> = if random() < scatter_probability(&self.albedo) {
+            self.phase.clone()
+        } else {
+            self.absorber.clone()
+        };
+        // media have no surface, any normal facing the ray will do
+        let n = -normalize(ray.d);
+        *rec = HitRecord {
+            p: ray.point_at_parameter(t),
+            normal: n,
+            shading_normal: n,
+            t,
+            mat: Some(mat),
+            ..HitRecord::new()
+        };
+        true
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+    // Ratio tracking: the product of the null collision probabilities at
+    // tentative collisions.
+    fn transmittance(&self, ray: &Ray, t_min: Float, t_max: Float) -> Float {
+        let sigma_maj = self.sigma_maj();
+        if sigma_maj <= 0.0 {
+            return 1.0;
+        }
+        let scale = 1.0 / (sigma_maj * ray.d.length());
+        let mut tr = 1.0;
+        for (t0, t1) in self.segments(ray, t_min, t_max) {
+            let mut t = t0;
+            loop {
+                t -= (1.0 - random()).ln() * scale;
+                if t >= t1 {
+                    break;
+                }
+                count(Counter::IntersectionTests, 1);
+                tr *= 1.0 - self.sigma_t_at(&ray.point_at_parameter(t)) / sigma_maj;
+            }
+        }
+        tr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{DensityGrid, GridMedium, HitRecord, Hitable, SphereObject};
+    use crate::geometry::{seed_random, Aabb, Float, Ray, Vec3};
+    fn uniform(density: Float) -> DensityGrid {
+        DensityGrid::new(2, 2, 2, vec![density; 8]).unwrap()
+    }
+    fn unit_box() -> Aabb {
+        Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0))
+    }
+    #[test]
+    fn parse_grids() {
+        let ascii = DensityGrid::parse(b"# ramp\n2 1 1\n0 1\n").unwrap();
+        let mut binary = b"GRID".to_vec();
+        for w in [2u32, 1, 1] {
+            binary.extend(&w.to_le_bytes());
+        }
+        for v in [0.0f32, 1.0] {
+            binary.extend(&v.to_le_bytes());
+        }
+        assert_eq!(DensityGrid::parse(&binary).unwrap(), ascii);
+        assert_eq!(ascii.max(), 1.0);
+        // voxel centers, halfway between and clamped outside
+        let at = |x: Float| ascii.lookup(&Vec3::new(x, 0.5, 0.5));
+        assert_eq!((at(0.25), at(0.5), at(0.75)), (0.0, 0.5, 1.0));
+        assert_eq!((at(0.0), at(1.0)), (0.0, 1.0));
+        assert!(DensityGrid::parse(b"2 2 2 1 2 3").is_err());
+        assert!(DensityGrid::parse(&binary[..20]).is_err());
+        let huge = format!("{} {} 2 0", usize::MAX / 2, 3);
+        assert!(DensityGrid::parse(huge.as_bytes())
+            .err()
+            .unwrap()
+            .contains("too large"));
+    }
+    #[test]
+    fn homogeneous_tracking() {
+        seed_random(9);
+        // sigma_t = 2 along a unit long path halfway up a ramp, so that half
+        // of the tentative collisions are null
+        let ramp = DensityGrid::new(2, 1, 1, vec![0.0, 1.0]).unwrap();
+        let medium = GridMedium::new(ramp, unit_box(), 4.0, Vec3::new(1.0, 1.0, 1.0));
+        let r = Ray::new(Vec3::new(0.5, 0.5, -1.0), Vec3::new(0.0, 0.0, 2.0));
+        let n = 20000;
+        let (mut hits, mut tr) = (0, 0.0);
+        for _ in 0..n {
+            let mut rec = HitRecord::new();
+            if medium.hit(&r, 0.0, Float::INFINITY, &mut rec) {
+                hits += 1;
+                assert!(rec.t > 0.5 && rec.t < 1.0);
+            }
+            tr += medium.transmittance(&r, 0.0, Float::INFINITY);
+        }
+        let expected = (-2.0 as Float).exp();
+        let escaped = 1.0 - hits as Float / n as Float;
+        assert!((escaped - expected).abs() < 0.01, "{}", escaped);
+        assert!(
+            (tr / n as Float - expected).abs() < 0.01,
+            "{}",
+            tr / n as Float
+        );
+        // up to the middle of the box
+        let tr = (0..n)
+            .map(|_| medium.transmittance(&r, 0.0, 0.75))
+            .sum::<Float>();
+        assert!((tr / n as Float - (-1.0 as Float).exp()).abs() < 0.01);
+    }
+    #[test]
+    fn clipped_by_a_boundary() {
+        seed_random(10);
+        let ball = SphereObject {
+            center: Vec3::new(0.5, 0.5, 0.5),
+            radius: 0.25,
+            mat: None,
+        };
+        let medium = GridMedium::new(uniform(1.0), unit_box(), 10.0, Vec3::new(0.0, 0.0, 0.0))
+            .with_boundary(Box::new(ball))
+            .with_emission(Vec3::new(1.0, 0.5, 0.0), None);
+        let r = Ray::new(Vec3::new(0.5, 0.5, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let mut attenuation = Vec3::new(0.0, 0.0, 0.0);
+        let mut scattered = r;
+        for _ in 0..1000 {
+            let mut rec = HitRecord::new();
+            if medium.hit(&r, 0.0, Float::INFINITY, &mut rec) {
+                assert!(rec.p.z >= 0.25 && rec.p.z <= 0.75, "{:?}", rec.p);
+                // black media only absorb, giving off their emission
+                let mat = rec.mat.clone().unwrap();
+                assert!(!mat.scatter(&r, &rec, &mut attenuation, &mut scattered));
+                assert_eq!(mat.emitted(&r, &rec), Vec3::new(1.0, 0.5, 0.0));
+            }
+        }
+        // rays missing the ball go through
+        let past = Ray::new(Vec3::new(0.9, 0.9, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(medium.transmittance(&past, 0.0, Float::INFINITY), 1.0);
+    }
+}
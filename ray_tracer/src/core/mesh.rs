@@ -0,0 +1,349 @@
+use crate::core::stats::{count, Counter};
+use crate::core::{HitRecord, Hitable, Material, Tree};
+use crate::geometry::{cross, dot, gamma, normalize, Aabb, Float, Ray, Transform, Vec3};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+
+// Indexed triangles, counterclockwise when seen from outside, with optional
+// per vertex normals and texture coordinates.
+#[derive(Debug, Clone, Default)]
+pub struct TriangleMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(Float, Float)>,
+    pub indices: Vec<[usize; 3]>,
+}
+impl TriangleMesh {
+    pub fn load_obj(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse_obj(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+    // Wavefront OBJ vertices, texture coordinates, normals and polygons, which
+    // are split into fans. Everything else is ignored.
+    pub fn parse_obj(text: &str) -> Result<Self, String> {
+        let (mut v, mut vt, mut vn) = (vec![], vec![], vec![]);
+        let mut mesh = TriangleMesh::default();
+        // mesh vertex of each distinct v/vt/vn triple
+        let mut vertices: HashMap<(usize, usize, usize), usize> = HashMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let err = |e: &str| format!("line {}: {}", n + 1, e);
+            let mut tokens = line.split_whitespace();
+            let numbers = |tokens: std::str::SplitWhitespace| {
+                tokens
+                    .map(|t| t.parse::<Float>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| err("invalid number"))
+            };
+            match tokens.next() {
+                Some("v") | Some("vn") if line.split_whitespace().count() < 4 => {
+                    return Err(err("expected 3 coordinates"));
+                }
+                Some("v") => {
+                    let c = numbers(tokens)?;
+                    v.push(Vec3::new(c[0], c[1], c[2]));
+                }
+                Some("vn") => {
+                    let c = numbers(tokens)?;
+                    vn.push(Vec3::new(c[0], c[1], c[2]));
+                }
+                Some("vt") => {
+                    let c = numbers(tokens)?;
+                    vt.push((
+                        c.first().cloned().unwrap_or(0.0),
+                        c.get(1).cloned().unwrap_or(0.0),
+                    ));
+                }
+                Some("f") => {
+                    let mut face = vec![];
+                    for corner in tokens {
+                        // 1 based, or negative from the end; 0 when missing
+                        let mut ids = [0; 3];
+                        for (i, s) in corner.split('/').enumerate().take(3) {
+                            if s.is_empty() {
+                                continue;
+                            }
+                            let count = [v.len(), vt.len(), vn.len()][i];
+                            let id: isize = s.parse().map_err(|_| err("invalid index"))?;
+                            let id = if id < 0 { count as isize + id + 1 } else { id };
+                            if id < 1 || id as usize > count {
+                                return Err(err("index out of range"));
+                            }
+                            ids[i] = id as usize;
+                        }
+                        if ids[0] == 0 {
+                            return Err(err("missing vertex index"));
+                        }
+                        let key = (ids[0], ids[1], ids[2]);
+                        let next = mesh.positions.len();
+                        let index = *vertices.entry(key).or_insert(next);
+                        if index == next {
+                            mesh.positions.push(v[ids[0] - 1]);
+                            if ids[1] > 0 {
+                                mesh.uvs.push(vt[ids[1] - 1]);
+                            }
+                            if ids[2] > 0 {
+                                mesh.normals.push(vn[ids[2] - 1]);
+                            }
+                        }
+                        face.push(index);
+                    }
+                    for i in 1..face.len().saturating_sub(1) {
+                        mesh.indices.push([face[0], face[i], face[i + 1]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+        // attributes only count when every vertex has them
+        if mesh.uvs.len() != mesh.positions.len() {
+            mesh.uvs.clear();
+        }
+        if mesh.normals.len() != mesh.positions.len() {
+            mesh.normals.clear();
+        }
+        Ok(mesh)
+    }
+    pub fn transform(&mut self, t: &Transform) {
+        for p in self.positions.iter_mut() {
+            *p = t.point(p);
+        }
+        for n in self.normals.iter_mut() {
+            *n = normalize(t.normal(n));
+        }
+    }
+    pub fn bounds(&self, i: usize) -> Aabb {
+        let [a, b, c] = self.indices[i];
+        let (a, b, c) = (self.positions[a], self.positions[b], self.positions[c]);
+        Aabb::new(a, b).union(&Aabb::new(c, c))
+    }
+    // Watertight ray triangle intersection with conservative bounds on t, see
+    // pbrt 3.6.2 and 3.9.6.
+    pub fn intersect(
+        &self,
+        i: usize,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        rec: &mut HitRecord,
+    ) -> bool {
+        let [i0, i1, i2] = self.indices[i];
+        let (p0, p1, p2) = (self.positions[i0], self.positions[i1], self.positions[i2]);
+        // ray space, looking down z from the ray origin
+        let kz = if ray.d.x.abs() > ray.d.y.abs() {
+            if ray.d.x.abs() > ray.d.z.abs() {
+                0
+            } else {
+                2
+            }
+        } else if ray.d.y.abs() > ray.d.z.abs() {
+            1
+        } else {
+            2
+        };
+        let (kx, ky) = ((kz + 1) % 3, (kz + 2) % 3);
+        let permute = |p: Vec3| Vec3::new(p[kx], p[ky], p[kz]);
+        let d = permute(ray.d);
+        let (sx, sy, sz) = (-d.x / d.z, -d.y / d.z, 1.0 / d.z);
+        let shear = |p: Vec3| {
+            let p = permute(p - ray.o);
+            Vec3::new(p.x + sx * p.z, p.y + sy * p.z, p.z * sz)
+        };
+        let (a, b, c) = (shear(p0), shear(p1), shear(p2));
+        let mut e = [
+            b.x * c.y - b.y * c.x,
+            c.x * a.y - c.y * a.x,
+            a.x * b.y - a.y * b.x,
+        ];
+        if e.contains(&0.0) {
+            e = edges_f64(&a, &b, &c);
+        }
+        if e.iter().any(|&e| e < 0.0) && e.iter().any(|&e| e > 0.0) {
+            return false;
+        }
+        let det = e[0] + e[1] + e[2];
+        if det == 0.0 {
+            return false;
+        }
+        let t = (e[0] * a.z + e[1] * b.z + e[2] * c.z) / det;
+        let max_z = a.z.abs().max(b.z.abs()).max(c.z.abs());
+        let max_x = a.x.abs().max(b.x.abs()).max(c.x.abs());
+        let max_y = a.y.abs().max(b.y.abs()).max(c.y.abs());
+        let max_e = e[0].abs().max(e[1].abs()).max(e[2].abs());
+        let delta_z = gamma(3) * max_z;
+        let delta_x = gamma(5) * (max_x + max_z);
+        let delta_y = gamma(5) * (max_y + max_z);
+        let delta_e = 2.0 * (gamma(2) * max_x * max_y + delta_y * max_x + delta_x * max_y);
+        let delta_t =
+            3.0 * (gamma(3) * max_e * max_z + delta_e * max_z + delta_z * max_e) / det.abs();
+        if t <= t_min + delta_t || t >= t_max {
+            return false;
+        }
+        let bary = [e[0] / det, e[1] / det, e[2] / det];
+        let weighted = |v: [Vec3; 3]| bary[0] * v[0] + bary[1] * v[1] + bary[2] * v[2];
+        let p = weighted([p0, p1, p2]);
+        let abs = |p: Vec3| Vec3::new(p.x.abs(), p.y.abs(), p.z.abs());
+        let mut normal = normalize(cross(&(p0 - p2), &(p1 - p2)));
+        let mut shading_normal = normal;
+        if !self.normals.is_empty() {
+            let n = weighted([self.normals[i0], self.normals[i1], self.normals[i2]]);
+            if n.length2() > 0.0 {
+                shading_normal = normalize(n);
+                // the vertex normals tell the outside
+                if dot(&normal, &shading_normal) < 0.0 {
+                    normal = -normal;
+                }
+            }
+        }
+        let uv = if self.uvs.is_empty() {
+            [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)]
+        } else {
+            [self.uvs[i0], self.uvs[i1], self.uvs[i2]]
+        };
+        let (du02, dv02) = (uv[0].0 - uv[2].0, uv[0].1 - uv[2].1);
+        let (du12, dv12) = (uv[1].0 - uv[2].0, uv[1].1 - uv[2].1);
+        let uv_det = du02 * dv12 - dv02 * du12;
+        let (dpdu, dpdv) = if uv_det.abs() < 1e-8 {
+            (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0))
+        } else {
+            let (dp02, dp12) = (p0 - p2, p1 - p2);
+            (
+                (dv12 * dp02 - dv02 * dp12) / uv_det,
+                (du02 * dp12 - du12 * dp02) / uv_det,
+            )
+        };
+        rec.t = t;
+        rec.p = p;
+        rec.p_error = gamma(7) * (abs(bary[0] * p0) + abs(bary[1] * p1) + abs(bary[2] * p2));
+        rec.normal = normal;
+        rec.shading_normal = shading_normal;
+        rec.u = bary[0] * uv[0].0 + bary[1] * uv[1].0 + bary[2] * uv[2].0;
+        rec.v = bary[0] * uv[0].1 + bary[1] * uv[1].1 + bary[2] * uv[2].1;
+        rec.dpdu = dpdu;
+        rec.dpdv = dpdv;
+        true
+    }
+}
+
+// Edge functions in double precision, for rays through edges and vertices
+// where they round to zero.
+// the casts are no-ops in f64 builds
+#[allow(clippy::unnecessary_cast)]
+fn edges_f64(a: &Vec3, b: &Vec3, c: &Vec3) -> [Float; 3] {
+    let cross = |p: &Vec3, q: &Vec3| (p.x as f64 * q.y as f64 - p.y as f64 * q.x as f64) as Float;
+    [cross(b, c), cross(c, a), cross(a, b)]
+}
+
+// A triangle mesh with one material, with its triangles under a bounding
+// volume hierarchy.
+pub struct Mesh {
+    pub mesh: TriangleMesh,
+    pub mat: Option<Arc<dyn Material>>,
+    tree: Tree,
+}
+impl Mesh {
+    pub fn new(mesh: TriangleMesh, mat: Option<Arc<dyn Material>>) -> Self {
+        let bounds: Vec<Aabb> = (0..mesh.indices.len()).map(|i| mesh.bounds(i)).collect();
+        let tree = Tree::new(&bounds);
+        Self { mesh, mat, tree }
+    }
+}
+impl Hitable for Mesh {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        let found = self.tree.closest(ray, t_min, t_max, |i, closest| {
+            count(Counter::IntersectionTests, 1);
+            if self.mesh.intersect(i, ray, t_min, closest, rec) {
+                Some(rec.t)
+            } else {
+                None
+            }
+        });
+        if found {
+            rec.mat = self.mat.clone();
+        }
+        found
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.tree.bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{HitRecord, Hitable, Mesh, TriangleMesh};
+    use crate::geometry::{dot, normalize, random, random_in_unit_sphere, seed_random};
+    use crate::geometry::{Float, Ray, Vec3};
+    const CUBE: &str = "\
+        v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\n\
+        v -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+        vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+        f 1/1 4/2 3/3 2/4\nf 5/1 6/2 7/3 8/4\nf 1/1 2/2 6/3 5/4\n\
+        f 4/1 8/2 7/3 3/4\nf 1/1 5/2 8/3 4/4\nf 2/1 3/2 7/3 6/4\n";
+    #[test]
+    fn parse_obj() {
+        let cube = TriangleMesh::parse_obj(CUBE).unwrap();
+        assert_eq!(cube.indices.len(), 12);
+        assert_eq!((cube.positions.len(), cube.uvs.len()), (20, 20));
+        assert!(cube.normals.is_empty());
+        let tri =
+            TriangleMesh::parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf -3//1 -2//1 -1//1")
+                .unwrap();
+        assert_eq!((tri.indices.len(), tri.normals.len()), (1, 3));
+        let err = TriangleMesh::parse_obj("v 0 0 0\nf 1 2 3").err().unwrap();
+        assert!(err.starts_with("line 2"), "{}", err);
+    }
+    #[test]
+    fn watertight_cube() {
+        seed_random(8);
+        let cube = Mesh::new(TriangleMesh::parse_obj(CUBE).unwrap(), None);
+        let sign = || if random() < 0.5 { 1.0 } else { -1.0 };
+        for _ in 0..5000 {
+            // rays through the edges of the cube, from the center or from
+            // outside, must not slip between the triangles
+            let target = Vec3::new(
+                sign(),
+                if random() < 0.5 {
+                    sign()
+                } else {
+                    1.0 - 2.0 * random()
+                },
+                1.0 - 2.0 * random(),
+            );
+            let o = if random() < 0.5 {
+                Vec3::new(0.0, 0.0, 0.0)
+            } else {
+                4.0 * target
+            };
+            let r = Ray::new(o, target - o);
+            let mut rec = HitRecord::new();
+            assert!(cube.hit(&r, 0.0, Float::INFINITY, &mut rec), "{:?}", target);
+            let out = rec.normal + 0.9 * normalize(random_in_unit_sphere());
+            if dot(&r.d, &rec.normal) < 0.0 {
+                // outward normals, and no self intersections when leaving
+                assert!(!cube.hit(
+                    &rec.spawn_ray(out),
+                    0.0,
+                    Float::INFINITY,
+                    &mut HitRecord::new()
+                ));
+            }
+        }
+    }
+    #[test]
+    fn interpolated_attributes() {
+        let mesh = TriangleMesh::parse_obj(
+            "v 0 0 0\nv 2 0 0\nv 0 2 0\nvt 0 0\nvt 1 0\nvt 0 1\n\
+             vn 0 0 1\nvn 1 0 1\nvn 0 1 1\nf 1/1/1 2/2/2 3/3/3",
+        )
+        .unwrap();
+        let mesh = Mesh::new(mesh, None);
+        let r = Ray::new(Vec3::new(0.5, 0.5, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::new();
+        assert!(mesh.hit(&r, 0.0, Float::INFINITY, &mut rec));
+        assert!((rec.u - 0.25).abs() < 1e-6 && (rec.v - 0.25).abs() < 1e-6);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(rec.shading_normal.x > 0.1 && rec.shading_normal.y > 0.1);
+        assert!((rec.dpdu - Vec3::new(2.0, 0.0, 0.0)).length() < 1e-6);
+        assert!((rec.dpdv - Vec3::new(0.0, 2.0, 0.0)).length() < 1e-6);
+    }
+}
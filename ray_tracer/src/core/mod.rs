@@ -1,7 +1,50 @@
+mod animation;
+mod bdpt;
+mod bvh;
+pub mod cameras;
+mod compare;
+mod csg;
+mod distributed;
 mod hitable;
-mod camera;
+mod sphere_soa;
+mod image;
+mod integrator;
+mod interior;
+mod light;
+mod light_bvh;
+mod medium;
+mod mesh;
+mod photon;
+mod preview;
 pub mod materials;
+pub mod shapes;
+mod renderer;
+mod scene;
+mod sdf;
+mod spectrum;
+pub mod stats;
+mod texture;
 
+pub use self::animation::*;
+pub use self::bdpt::*;
+pub use self::bvh::*;
+pub use self::cameras::Camera;
+pub use self::compare::*;
+pub use self::csg::*;
+pub use self::distributed::*;
 pub use self::hitable::*;
-pub use self::camera::Camera;
-pub use self::materials::*;
\ No newline at end of file
+pub use self::sphere_soa::*;
+pub use self::image::*;
+pub use self::integrator::*;
+pub use self::interior::*;
+pub use self::light::*;
+pub use self::light_bvh::*;
+pub use self::medium::*;
+pub use self::mesh::*;
+pub use self::photon::*;
+pub use self::preview::*;
+pub use self::renderer::*;
+pub use self::scene::*;
+pub use self::sdf::*;
+pub use self::spectrum::*;
+pub use self::texture::*;
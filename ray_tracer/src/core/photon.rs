@@ -0,0 +1,315 @@
+use crate::core::integrator::{cross_interface, direct, emission, scattered_from, sky};
+use crate::core::stats;
+use crate::core::{HitRecord, Hitable, Integrator, InteriorStack, Material, Scene};
+use crate::geometry::{consts, dot, normalize, random, Float, Ray, Vec3};
+use std::sync::{Arc, OnceLock};
+
+// A photon that reached a diffuse or glossy surface, with the flux it
+// carries.
+#[derive(Debug, Clone, Copy)]
+pub struct Photon {
+    pub p: Vec3,
+    // unit direction towards where the photon came from
+    pub wi: Vec3,
+    pub power: Vec3,
+}
+
+// Balanced kd-tree kept in place: every range of photons has its splitting
+// photon in the middle, those below the split before it and those above
+// after it.
+pub struct KdTree {
+    photons: Vec<Photon>,
+    axes: Vec<u8>,
+}
+impl KdTree {
+    pub fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        Self { photons, axes }
+    }
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+    // Calls f with every photon within radius of p.
+    pub fn within<F: FnMut(&Photon)>(&self, p: &Vec3, radius: Float, f: &mut F) {
+        self.visit(0, self.photons.len(), p, radius * radius, f);
+    }
+    fn visit<F: FnMut(&Photon)>(&self, lo: usize, hi: usize, p: &Vec3, r2: Float, f: &mut F) {
+        if lo >= hi {
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        let photon = &self.photons[mid];
+        if (photon.p - *p).length2() <= r2 {
+            f(photon);
+        }
+        let d = p[self.axes[mid] as usize] - photon.p[self.axes[mid] as usize];
+        if d <= 0.0 || d * d <= r2 {
+            self.visit(lo, mid, p, r2, f);
+        }
+        if d >= 0.0 || d * d <= r2 {
+            self.visit(mid + 1, hi, p, r2, f);
+        }
+    }
+}
+
+// Splits at the median along the axis the photons spread most on.
+fn build(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.len() < 2 {
+        return;
+    }
+    let mut lo = photons[0].p;
+    let mut hi = photons[0].p;
+    for photon in photons.iter() {
+        for a in 0..3 {
+            lo[a] = lo[a].min(photon.p[a]);
+            hi[a] = hi[a].max(photon.p[a]);
+        }
+    }
+    let extent = hi - lo;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| a.p[axis].total_cmp(&b.p[axis]));
+    axes[mid] = axis as u8;
+    let (below, above) = photons.split_at_mut(mid);
+    let (axes_below, axes_above) = axes.split_at_mut(mid);
+    build(below, axes_below);
+    build(&mut above[1..], &mut axes_above[1..]);
+}
+
+// Path tracing that leaves caustics, light reaching diffuse or glossy
+// surfaces through specular bounces only, to a photon map. Photons are
+// traced from the lights through glass and mirrors and stored where they
+// land, and the light they bring is estimated from those within radius of
+// each diffuse hit. Light from the sky is path traced.
+pub struct PhotonIntegrator {
+    max_depth: i32,
+    photons: usize,
+    radius: Float,
+    map: OnceLock<KdTree>,
+}
+impl PhotonIntegrator {
+    pub fn new(max_depth: i32, photons: usize, radius: Float) -> Self {
+        Self {
+            max_depth,
+            photons,
+            radius,
+            map: OnceLock::new(),
+        }
+    }
+    fn map(&self, scene: &Scene) -> &KdTree {
+        self.map.get_or_init(|| KdTree::new(self.trace(scene)))
+    }
+    // Photons emitted by the lights, chosen by power, that reached a diffuse
+    // or glossy surface after at least one specular bounce.
+    fn trace(&self, scene: &Scene) -> Vec<Photon> {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        let world: &dyn Hitable = &scene.world;
+        let mut photons = vec![];
+        for _ in 0..self.photons {
+            let (i, pmf) = match scene.light_sampler.sample_power(random()) {
+                Some(choice) => choice,
+                None => break,
+            };
+            let e = match scene.lights[i].sample_le() {
+                Some(e) if e.pdf_pos > 0.0 && e.pdf_dir > 0.0 => e,
+                _ => continue,
+            };
+            let w = normalize(e.ray.d);
+            let cos = if e.normal == black {
+                1.0
+            } else {
+                dot(&e.normal, &w).abs()
+            };
+            let mut power =
+                e.radiance * cos / (pmf * e.pdf_pos * e.pdf_dir * self.photons as Float);
+            let mut r = e.ray;
+            let mut media = InteriorStack::new();
+            let mut bounces = 0;
+            loop {
+                let mut rec = HitRecord::new();
+                if !world.hit(&r, 0.0, Float::INFINITY, &mut rec) {
+                    break;
+                }
+                let mat = match rec.mat.clone() {
+                    Some(mat) => mat,
+                    None => break,
+                };
+                if media.is_false_hit(&mat) {
+                    media.cross(&mat, dot(&r.d, &rec.normal) < 0.0);
+                    r = rec.spawn_ray(r.d);
+                    continue;
+                }
+                if mat.eval(&r, &rec, &-normalize(r.d)).is_some() {
+                    // lights are sampled directly at diffuse surfaces
+                    if bounces > 0 {
+                        photons.push(Photon {
+                            p: rec.p,
+                            wi: -normalize(r.d),
+                            power,
+                        });
+                    }
+                    break;
+                }
+                if bounces == self.max_depth {
+                    break;
+                }
+                rec.outer_ior = media.outside_of(&mat).map_or(1.0, |i| i.ior.nominal());
+                let mut scattered = Ray::new(black, black);
+                let mut attenuation = black;
+                if !mat.scatter(&r, &rec, &mut attenuation, &mut scattered)
+                    || !rec.is_consistent(&scattered.d)
+                {
+                    break;
+                }
+                cross_interface(&mut media, &mat, &r, &scattered, &rec);
+                power = power * attenuation;
+                r = scattered;
+                bounces += 1;
+            }
+        }
+        photons
+    }
+    // Reflected radiance from the photons around a diffuse or glossy hit.
+    fn caustics(&self, r: &Ray, scene: &Scene, rec: &HitRecord, mat: &Arc<dyn Material>) -> Vec3 {
+        let mut l = Vec3::new(0.0, 0.0, 0.0);
+        self.map(scene).within(&rec.p, self.radius, &mut |photon| {
+            let cos = dot(&photon.wi, &rec.shading_normal).abs();
+            if !rec.is_consistent(&photon.wi) || cos == 0.0 {
+                return;
+            }
+            if let Some(f) = mat.eval(r, rec, &photon.wi) {
+                l += f * photon.power / cos;
+            }
+        });
+        l / (consts::PI * self.radius * self.radius)
+    }
+    // `diffuse` is whether the path went through a diffuse or glossy vertex,
+    // so that lights hit after specular bounces from there are caustics.
+    fn color(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        media: &InteriorStack,
+        from: Option<(Vec3, Float)>,
+        diffuse: bool,
+        depth: i32,
+    ) -> Vec3 {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        if depth > 0 {
+            stats::count(stats::Counter::SecondaryRays, 1);
+        }
+        let world: &dyn Hitable = &scene.world;
+        let mut rec = HitRecord::new();
+        if !world.hit(r, 0.0, Float::INFINITY, &mut rec) {
+            return match media.current() {
+                Some(i) => i.transmittance(Float::INFINITY) * sky(scene, r),
+                None => sky(scene, r),
+            };
+        }
+        stats::count(stats::Counter::PathVertices, 1);
+        let transmittance = media.current().map_or(Vec3::new(1.0, 1.0, 1.0), |i| {
+            i.transmittance(rec.t * r.d.length())
+        });
+        let mut media = media.clone();
+        let mat = match rec.mat.clone() {
+            Some(mat) if depth < self.max_depth => mat,
+            _ => return black,
+        };
+        if media.is_false_hit(&mat) {
+            media.cross(&mat, dot(&r.d, &rec.normal) < 0.0);
+            let continued = rec.spawn_ray(r.d);
+            return transmittance * self.color(&continued, scene, &media, from, diffuse, depth + 1);
+        }
+        rec.outer_ior = media.outside_of(&mat).map_or(1.0, |i| i.ior.nominal());
+        let caustic = diffuse && from.is_none() && mat.light_index().is_some();
+        let mut emitted = direct(r, &rec, &mat, scene) + self.caustics(r, scene, &rec, &mat);
+        if !caustic {
+            emitted += emission(r, &rec, &mat, scene, from);
+        }
+        let mut scattered = Ray::new(black, black);
+        let mut attenuation = black;
+        // shading normals must not scatter through the surface
+        if mat.scatter(r, &rec, &mut attenuation, &mut scattered) && rec.is_consistent(&scattered.d)
+        {
+            cross_interface(&mut media, &mat, r, &scattered, &rec);
+            let from = scattered_from(r, &rec, &mat, &scattered);
+            let diffuse = diffuse || from.is_some();
+            return transmittance
+                * (emitted
+                    + attenuation
+                        * self.color(&scattered, scene, &media, from, diffuse, depth + 1));
+        }
+        transmittance * emitted
+    }
+}
+impl Integrator for PhotonIntegrator {
+    fn preprocess(&self, scene: &Scene) {
+        self.map(scene);
+    }
+    fn li(&self, r: &Ray, scene: &Scene) -> Vec3 {
+        self.color(r, scene, &InteriorStack::new(), None, false, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{Integrator, KdTree, Photon, PhotonIntegrator, Scene};
+    use crate::geometry::{consts, random, seed_random, Float, Ray, Vec3};
+    #[test]
+    fn kd_tree_finds_photons_within_radius() {
+        seed_random(3);
+        let photons: Vec<Photon> = (0..1000)
+            .map(|_| Photon {
+                p: Vec3::new(random(), 0.2 * random(), random()),
+                wi: Vec3::new(0.0, 1.0, 0.0),
+                power: Vec3::new(random(), 0.0, 0.0),
+            })
+            .collect();
+        let tree = KdTree::new(photons.clone());
+        assert_eq!(tree.len(), 1000);
+        for _ in 0..50 {
+            let p = Vec3::new(random(), 0.2 * random(), random());
+            let radius = 0.2 * random();
+            let mut found = 0.0;
+            tree.within(&p, radius, &mut |photon| found += photon.power.x);
+            let expected: Float = photons
+                .iter()
+                .filter(|photon| (photon.p - p).length2() <= radius * radius)
+                .map(|photon| photon.power.x)
+                .sum();
+            assert!((found - expected).abs() < 1e-3, "{} {}", found, expected);
+        }
+    }
+    #[test]
+    fn point_light_seen_in_a_mirror() {
+        seed_random(5);
+        // a point light between a diffuse floor and a mirror, where the
+        // mirror image of the light at 3 adds 0.8 / 9 of its direct light
+        let scene = Scene::parse(
+            "sky intensity=0\n\
+             material floor albedo=0.5,0.5,0.5\n\
+             material mirror type=metal albedo=0.8,0.8,0.8 fuzz=0\n\
+             sphere center=0,-1000,0 radius=1000 material=floor\n\
+             sphere center=0,1002,0 radius=1000 material=mirror\n\
+             light point position=0,1,0 intensity=1,1,1\n",
+        )
+        .unwrap();
+        let integrator = PhotonIntegrator::new(1, 500000, 0.25);
+        integrator.preprocess(&scene);
+        let direct = 0.5 / consts::PI;
+        let caustic = direct * 0.8 / 9.0;
+        let r = Ray::new(Vec3::new(0.01, 0.5, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let l = integrator.li(&r, &scene).x;
+        assert!((l - direct - caustic).abs() < 0.1 * caustic, "{}", l);
+    }
+}
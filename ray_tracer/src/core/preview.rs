@@ -0,0 +1,214 @@
+use crate::core::Image;
+use crate::geometry::Float;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, ClearType};
+use crossterm::{cursor, execute, queue};
+use std::io::{IsTerminal, Stderr, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Progressive render drawn on the alternate screen of the terminal on
+// stderr, so that images can still be written to stdout. Every character
+// shows two pixels, the upper half block in the color of one on the
+// background color of the other. Keys are read on another thread until the
+// preview is dropped, which restores the terminal.
+pub struct Preview {
+    out: Stderr,
+    start: Instant,
+    start_spp: u32,
+    quit: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    keys: Option<JoinHandle<()>>,
+}
+impl Preview {
+    // start_spp is what the film had before this run, so that the time left
+    // is estimated from the samples rendered since.
+    pub fn new(start_spp: u32) -> Result<Self, String> {
+        let mut out = std::io::stderr();
+        if !out.is_terminal() {
+            return Err("the preview needs a terminal on stderr".to_string());
+        }
+        let err = |e: std::io::Error| e.to_string();
+        terminal::enable_raw_mode().map_err(err)?;
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide).map_err(err)?;
+        let (quit, stop) = (
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        );
+        let keys = {
+            let (quit, stop) = (quit.clone(), stop.clone());
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    // raw mode turns Ctrl-C into a key
+                    match read_key(Duration::from_millis(50)) {
+                        Ok(Some((KeyCode::Char('q'), _))) => quit.store(true, Ordering::Relaxed),
+                        Ok(Some((KeyCode::Char('c'), m))) if m.contains(KeyModifiers::CONTROL) => {
+                            quit.store(true, Ordering::Relaxed)
+                        }
+                        Ok(_) => {}
+                        Err(_) => break,
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            out,
+            start: Instant::now(),
+            start_spp,
+            quit,
+            stop,
+            keys: Some(keys),
+        })
+    }
+    // Whether q or Ctrl-C was pressed.
+    pub fn quit_requested(&self) -> bool {
+        self.quit.load(Ordering::Relaxed)
+    }
+    // Set once q or Ctrl-C is pressed, to stop rendering mid pass.
+    pub fn quit_flag(&self) -> Arc<AtomicBool> {
+        self.quit.clone()
+    }
+    pub fn draw(&mut self, image: &Image, spp: u32, total_spp: u32) -> Result<(), String> {
+        let err = |e: std::io::Error| e.to_string();
+        let (columns, rows) = terminal::size().map_err(err)?;
+        let (width, height) = fit(
+            image.width,
+            image.height,
+            columns as usize,
+            2 * (rows as usize).saturating_sub(1),
+        );
+        let small = image.resize(width, height);
+        let rgb = |x: usize, y: usize| {
+            let p = small.get(x, y);
+            let q = |c: Float| (255.99 * c.clamp(0.0, 1.0)) as u8;
+            Color::Rgb {
+                r: q(p.x),
+                g: q(p.y),
+                b: q(p.z),
+            }
+        };
+        let out = &mut self.out;
+        for row in 0..height.div_ceil(2) {
+            queue!(out, cursor::MoveTo(0, row as u16)).map_err(err)?;
+            for x in 0..width {
+                let below = if 2 * row + 1 < height {
+                    rgb(x, 2 * row + 1)
+                } else {
+                    Color::Reset
+                };
+                queue!(
+                    out,
+                    SetForegroundColor(rgb(x, 2 * row)),
+                    SetBackgroundColor(below),
+                    Print('\u{2580}')
+                )
+                .map_err(err)?;
+            }
+            queue!(out, ResetColor, terminal::Clear(ClearType::UntilNewLine)).map_err(err)?;
+        }
+        let line = status(
+            spp - self.start_spp.min(spp),
+            total_spp - self.start_spp.min(total_spp),
+            self.start.elapsed(),
+        );
+        queue!(
+            out,
+            cursor::MoveTo(0, height.div_ceil(2) as u16),
+            terminal::Clear(ClearType::FromCursorDown),
+            cursor::MoveTo(0, rows.saturating_sub(1)),
+            Print(format!("{}/{} spp  {}", spp, total_spp, line)),
+        )
+        .map_err(err)?;
+        out.flush().map_err(err)
+    }
+}
+impl Drop for Preview {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(keys) = self.keys.take() {
+            let _ = keys.join();
+        }
+        let _ = execute!(
+            self.out,
+            ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// The key pressed, if one is within the timeout.
+fn read_key(timeout: Duration) -> std::io::Result<Option<(KeyCode, KeyModifiers)>> {
+    if !event::poll(timeout)? {
+        return Ok(None);
+    }
+    Ok(match event::read()? {
+        Event::Key(key) if key.kind == KeyEventKind::Press => Some((key.code, key.modifiers)),
+        _ => None,
+    })
+}
+
+// Size of an image shrunk to fit in the given pixels, keeping its aspect.
+// Images that fit are left as they are.
+pub(crate) fn fit(
+    width: usize,
+    height: usize,
+    max_width: usize,
+    max_height: usize,
+) -> (usize, usize) {
+    let scale = (max_width as Float / width.max(1) as Float)
+        .min(max_height as Float / height.max(1) as Float)
+        .min(1.0);
+    (
+        ((width as Float * scale) as usize).max(1),
+        ((height as Float * scale) as usize).max(1),
+    )
+}
+
+// Elapsed time and the time left at the rate samples were rendered so far,
+// given the samples rendered and still to render in this run.
+pub(crate) fn status(spp: u32, total_spp: u32, elapsed: Duration) -> String {
+    let clock = |d: Duration| {
+        let s = d.as_secs();
+        if s >= 3600 {
+            format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+        } else {
+            format!("{}:{:02}", s / 60, s % 60)
+        }
+    };
+    let eta = if spp == 0 {
+        "-".to_string()
+    } else {
+        clock(elapsed.mul_f64((total_spp.max(spp) - spp) as f64 / spp as f64))
+    };
+    format!("elapsed {}  eta {}  q to stop", clock(elapsed), eta)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{fit, status};
+    use std::time::Duration;
+    #[test]
+    fn fits_and_reports_progress() {
+        // two pixels per character row
+        assert_eq!(fit(640, 480, 80, 2 * 23), (61, 46));
+        assert_eq!(fit(640, 160, 80, 2 * 23), (80, 20));
+        assert_eq!(fit(32, 16, 80, 2 * 23), (32, 16));
+        assert_eq!(
+            status(16, 64, Duration::from_secs(75)),
+            "elapsed 1:15  eta 3:45  q to stop"
+        );
+        assert_eq!(
+            status(0, 64, Duration::from_secs(3)),
+            "elapsed 0:03  eta -  q to stop"
+        );
+        assert_eq!(
+            status(1, 2, Duration::from_secs(3700)),
+            "elapsed 1:01:40  eta 1:01:40  q to stop"
+        );
+    }
+}
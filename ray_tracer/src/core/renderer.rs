@@ -0,0 +1,454 @@
+use crate::core::stats::{count, Counter, PassStats, Stats};
+use crate::core::{Camera, Image};
+use crate::geometry::{random, seed_random, Float, Ray, Vec3};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+const CHECKPOINT_MAGIC: &[u8; 4] = b"RTCK";
+const CHECKPOINT_VERSION: u32 = 4;
+const CHECKPOINT_HEADER: usize = 44;
+
+// image row index, radiance and cost of its pixels
+type Row = (usize, Vec<Vec3>, Vec<Float>);
+// light splatted to the film, summed by pixel index
+type Splats = HashMap<usize, Vec3>;
+
+thread_local! {
+    // width and height of the film, zero when not sampling, and the splats of
+    // the current pass
+    static SPLATS: RefCell<(usize, usize, Splats)> = RefCell::new((0, 0, HashMap::new()));
+}
+
+// Adds light to the film at coordinates (s, t) instead of the pixel being
+// sampled, for paths traced from the lights to the camera. Like samples,
+// splats are divided by the samples per pixel.
+pub fn splat(s: Float, t: Float, v: Vec3) {
+    SPLATS.with(|splats| {
+        let (width, height, ref mut splats) = *splats.borrow_mut();
+        if width == 0 || height == 0 {
+            return;
+        }
+        let x = ((s * width as Float) as usize).min(width - 1);
+        let y = height - 1 - ((t * height as Float) as usize).min(height - 1);
+        *splats
+            .entry(y * width + x)
+            .or_insert(Vec3::new(0.0, 0.0, 0.0)) += v;
+    });
+}
+
+fn take_splats(width: usize, height: usize) -> Splats {
+    SPLATS.with(|splats| {
+        std::mem::replace(&mut *splats.borrow_mut(), (width, height, HashMap::new())).2
+    })
+}
+
+// Accumulated radiance, row 0 at the top.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    pub sum: Vec<Vec3>,
+    pub samples: Vec<u32>,
+    pub splats: Vec<Vec3>,
+}
+impl Film {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            sum: vec![Vec3::new(0.0, 0.0, 0.0); width * height],
+            samples: vec![0; width * height],
+            splats: vec![Vec3::new(0.0, 0.0, 0.0); width * height],
+        }
+    }
+    pub fn image(&self) -> Image {
+        let mut image = Image::new(self.width, self.height);
+        for (i, p) in image.pixels.iter_mut().enumerate() {
+            if self.samples[i] > 0 {
+                *p = (self.sum[i] + self.splats[i]) / self.samples[i] as Float;
+            }
+        }
+        image
+    }
+    pub fn add_tile(&mut self, buffer: &TileBuffer) {
+        let tile = buffer.tile;
+        for y in 0..tile.height {
+            for x in 0..tile.width {
+                let i = (tile.y + y) * self.width + tile.x + x;
+                self.sum[i] += buffer.sum[y * tile.width + x];
+                self.samples[i] += buffer.spp;
+            }
+        }
+        for &(i, splat) in &buffer.splats {
+            self.splats[i] += splat;
+        }
+    }
+}
+
+// A rectangle of pixels, row 0 at the top.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+impl Tile {
+    // Tiles of at most size by size pixels covering an image, row by row.
+    pub fn split(width: usize, height: usize, size: usize) -> Vec<Tile> {
+        let size = size.max(1);
+        let mut tiles = vec![];
+        for y in (0..height).step_by(size) {
+            for x in (0..width).step_by(size) {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: size.min(width - x),
+                    height: size.min(height - y),
+                });
+            }
+        }
+        tiles
+    }
+}
+
+// Radiance summed over the samples of the pixels of a tile, row by row, and
+// the splats they made anywhere on the film by pixel index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileBuffer {
+    pub tile: Tile,
+    pub spp: u32,
+    pub sum: Vec<Vec3>,
+    pub splats: Vec<(usize, Vec3)>,
+}
+
+// Seed of one frame of an animation, so that frames are rendered with
+// different but reproducible noise. Frame 0 keeps the seed of a still.
+pub fn frame_seed(seed: u64, frame: u64) -> u64 {
+    seed ^ frame.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+}
+
+// Progressive renderer: every pass adds a number of samples to all pixels.
+// The random generator is reseeded from (seed, pass, pixel) so a render can be
+// stopped after any pass and resumed from its film alone.
+pub struct Renderer {
+    pub film: Film,
+    pub seed: u64,
+    pub passes: u32,
+    pub spp: u32,
+    pub threads: usize,
+    pub stats: Vec<PassStats>,
+    // intersection tests and BVH node visits per pixel
+    pub cost: Vec<Float>,
+    // what was rendered, checked when resuming from a checkpoint
+    pub scene_hash: u64,
+    pub spectral: bool,
+    // checked before every row, a pass stopped with it is left out of the film
+    pub stop: Option<Arc<AtomicBool>>,
+}
+
+impl Renderer {
+    pub fn new(width: usize, height: usize, seed: u64) -> Self {
+        Self {
+            film: Film::new(width, height),
+            seed,
+            passes: 0,
+            spp: 0,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            stats: vec![],
+            cost: vec![0.0; width * height],
+            scene_hash: 0,
+            spectral: false,
+            stop: None,
+        }
+    }
+    pub fn render_pass<F>(&mut self, camera: &dyn Camera, spp: u32, radiance: &F)
+    where
+        F: Fn(&mut Ray) -> Vec3 + Sync,
+    {
+        let width = self.film.width;
+        let start = Instant::now();
+        let full = Tile {
+            x: 0,
+            y: 0,
+            width,
+            height: self.film.height,
+        };
+        let workers = self.sample(camera, full, spp, radiance);
+        if self.stopped() {
+            return;
+        }
+        let mut pass_stats = PassStats {
+            threads: vec![],
+            time: start.elapsed(),
+        };
+        for (rows, splats, stats) in workers {
+            for (y, row, cost) in rows {
+                for (x, (col, c)) in row.into_iter().zip(cost).enumerate() {
+                    self.film.sum[y * width + x] += col;
+                    self.film.samples[y * width + x] += spp;
+                    self.cost[y * width + x] += c;
+                }
+            }
+            for (i, splat) in splats {
+                self.film.splats[i] += splat;
+            }
+            pass_stats.threads.push(stats);
+        }
+        self.stats.push(pass_stats);
+        self.passes += 1;
+        self.spp += spp;
+    }
+    // Samples of the next pass in one tile only, which leave the renderer as
+    // it is. The tiles of an image add up to the pass.
+    pub fn render_tile<F>(
+        &self,
+        camera: &dyn Camera,
+        tile: Tile,
+        spp: u32,
+        radiance: &F,
+    ) -> TileBuffer
+    where
+        F: Fn(&mut Ray) -> Vec3 + Sync,
+    {
+        let mut buffer = TileBuffer {
+            tile,
+            spp,
+            sum: vec![Vec3::new(0.0, 0.0, 0.0); tile.width * tile.height],
+            splats: vec![],
+        };
+        let mut splats = Splats::new();
+        for (rows, thread_splats, _) in self.sample(camera, tile, spp, radiance) {
+            for (y, row, _) in rows {
+                let start = (y - tile.y) * tile.width;
+                buffer.sum[start..start + tile.width].copy_from_slice(&row);
+            }
+            for (i, splat) in thread_splats {
+                *splats.entry(i).or_insert(Vec3::new(0.0, 0.0, 0.0)) += splat;
+            }
+        }
+        buffer.splats = splats.into_iter().collect();
+        buffer.splats.sort_by_key(|&(i, _)| i);
+        buffer
+    }
+    // Rows of the tile sampled by each thread, with the splats and
+    // statistics of the thread.
+    fn sample<F>(
+        &self,
+        camera: &dyn Camera,
+        tile: Tile,
+        spp: u32,
+        radiance: &F,
+    ) -> Vec<(Vec<Row>, Splats, Stats)>
+    where
+        F: Fn(&mut Ray) -> Vec3 + Sync,
+    {
+        let (width, height) = (self.film.width, self.film.height);
+        let (seed, pass) = (self.seed, self.passes as u64);
+        let next_row = AtomicUsize::new(tile.y);
+        thread::scope(|s| {
+            let workers: Vec<_> = (0..self.threads.max(1))
+                .map(|_| {
+                    s.spawn(|| {
+                        let start = Instant::now();
+                        Stats::take();
+                        take_splats(width, height);
+                        let mut rows = vec![];
+                        loop {
+                            let y = next_row.fetch_add(1, Ordering::Relaxed);
+                            if y >= tile.y + tile.height || self.stopped() {
+                                break;
+                            }
+                            let mut row = vec![Vec3::new(0.0, 0.0, 0.0); tile.width];
+                            let mut cost = vec![0.0; tile.width];
+                            for (i, col) in row.iter_mut().enumerate() {
+                                let x = tile.x + i;
+                                let work = Stats::peek().work();
+                                seed_random(seed ^ (pass << 40) ^ (y * width + x) as u64);
+                                for _ in 0..spp {
+                                    let u = (x as Float + random()) / width as Float;
+                                    let v =
+                                        ((height - 1 - y) as Float + random()) / height as Float;
+                                    if let Some(mut r) = camera.ray(u, v) {
+                                        count(Counter::CameraRays, 1);
+                                        *col += radiance(&mut r);
+                                    }
+                                }
+                                cost[i] = (Stats::peek().work() - work) as Float;
+                            }
+                            rows.push((y, row, cost));
+                        }
+                        let mut stats = Stats::take();
+                        stats.time = start.elapsed();
+                        (rows, take_splats(0, 0), stats)
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        })
+    }
+    fn stopped(&self) -> bool {
+        self.stop
+            .as_ref()
+            .is_some_and(|s| s.load(Ordering::Relaxed))
+    }
+    pub fn heatmap(&self) -> Image {
+        Image::heatmap(&self.cost, self.film.width, self.film.height)
+    }
+    // Binary little endian checkpoint: magic, version, width, height, seed,
+    // passes, spp, scene hash, spectral flag, then per pixel the radiance sum
+    // (as f64, whatever the precision of the build), sample count and splat sum.
+    pub fn save_checkpoint(&self, path: &str) -> Result<(), String> {
+        let mut data = CHECKPOINT_MAGIC.to_vec();
+        data.extend_from_slice(&CHECKPOINT_VERSION.to_le_bytes());
+        data.extend_from_slice(&(self.film.width as u32).to_le_bytes());
+        data.extend_from_slice(&(self.film.height as u32).to_le_bytes());
+        data.extend_from_slice(&self.seed.to_le_bytes());
+        data.extend_from_slice(&self.passes.to_le_bytes());
+        data.extend_from_slice(&self.spp.to_le_bytes());
+        data.extend_from_slice(&self.scene_hash.to_le_bytes());
+        data.extend_from_slice(&(self.spectral as u32).to_le_bytes());
+        for i in 0..self.film.sum.len() {
+            let f64s = |data: &mut Vec<u8>, v: &Vec3| {
+                for c in 0..3 {
+                    // a no-op in f64 builds
+                    #[allow(clippy::unnecessary_cast)]
+                    data.extend_from_slice(&(v[c] as f64).to_le_bytes());
+                }
+            };
+            f64s(&mut data, &self.film.sum[i]);
+            data.extend_from_slice(&self.film.samples[i].to_le_bytes());
+            f64s(&mut data, &self.film.splats[i]);
+        }
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, data).map_err(|e| format!("{}: {}", tmp, e))?;
+        fs::rename(&tmp, path).map_err(|e| format!("{}: {}", path, e))
+    }
+    pub fn load_checkpoint(path: &str) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        let err = |e: &str| format!("{}: {}", path, e);
+        if data.len() < CHECKPOINT_HEADER || &data[0..4] != CHECKPOINT_MAGIC {
+            return Err(err("not a checkpoint file"));
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        if u32_at(4) != CHECKPOINT_VERSION {
+            return Err(err("unsupported checkpoint version"));
+        }
+        let (width, height) = (u32_at(8) as usize, u32_at(12) as usize);
+        let size = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(52))
+            .and_then(|n| n.checked_add(CHECKPOINT_HEADER))
+            .ok_or_else(|| err("corrupt checkpoint"))?;
+        if data.len() != size {
+            return Err(err("truncated checkpoint"));
+        }
+        let mut renderer = Self::new(
+            width,
+            height,
+            u64::from_le_bytes(data[16..24].try_into().unwrap()),
+        );
+        renderer.passes = u32_at(24);
+        renderer.spp = u32_at(28);
+        renderer.scene_hash = u64::from_le_bytes(data[32..40].try_into().unwrap());
+        renderer.spectral = u32_at(40) != 0;
+        for i in 0..width * height {
+            let o = CHECKPOINT_HEADER + i * 52;
+            let f = |j: usize| f64::from_le_bytes(data[j..j + 8].try_into().unwrap()) as Float;
+            renderer.film.sum[i] = Vec3::new(f(o), f(o + 8), f(o + 16));
+            renderer.film.samples[i] = u32_at(o + 24);
+            renderer.film.splats[i] = Vec3::new(f(o + 28), f(o + 36), f(o + 44));
+        }
+        Ok(renderer)
+    }
+}
+
+// FNV-1a, to tell the scenes of checkpoints apart across runs and builds.
+pub fn scene_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::cameras::OrthographicCamera;
+    use crate::core::{scene_hash, splat, Film, Renderer};
+    use crate::geometry::{random, Ray, Vec3};
+    use std::env;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    fn camera() -> OrthographicCamera {
+        OrthographicCamera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            2.0,
+            1.0,
+        )
+    }
+    #[test]
+    fn resume_matches_uninterrupted_render() {
+        let camera = camera();
+        let radiance = |r: &mut Ray| {
+            splat(0.9, 0.9, Vec3::new(0.0, random(), 0.0));
+            Vec3::new(random(), r.o.x, r.o.y)
+        };
+        let mut full = Renderer::new(8, 6, 3);
+        for _ in 0..3 {
+            full.render_pass(&camera, 2, &radiance);
+        }
+        let path = env::temp_dir().join(format!("ray_tracer_{}.ckpt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut first = Renderer::new(8, 6, 3);
+        first.threads = 3;
+        first.scene_hash = scene_hash("image width=8 height=6");
+        first.spectral = true;
+        first.render_pass(&camera, 2, &radiance);
+        first.save_checkpoint(path).unwrap();
+        let mut resumed = Renderer::load_checkpoint(path).unwrap();
+        // a size whose pixels do not fit in memory
+        let mut data = std::fs::read(path).unwrap();
+        data[8..16].copy_from_slice(&[0xff; 8]);
+        std::fs::write(path, data).unwrap();
+        let corrupt = Renderer::load_checkpoint(path).map(|_| ());
+        std::fs::remove_file(path).unwrap();
+        assert!(corrupt.unwrap_err().ends_with("corrupt checkpoint"));
+        assert_eq!(resumed.film, first.film);
+        assert_eq!(
+            (resumed.scene_hash, resumed.spectral),
+            (first.scene_hash, true)
+        );
+        assert_ne!(
+            scene_hash("image width=8 height=6"),
+            scene_hash("image width=6 height=8")
+        );
+        for _ in 0..2 {
+            resumed.render_pass(&camera, 2, &radiance);
+        }
+        assert_eq!((resumed.passes, resumed.spp), (3, 6));
+        assert_eq!(resumed.film.sum, full.film.sum);
+        // splats of the threads are summed in any order
+        let (a, b) = (resumed.film.splats[7].y, full.film.splats[7].y);
+        assert!(a > 0.0 && (a - b).abs() < 1e-4 * b);
+        assert_eq!(resumed.film.splats.iter().filter(|s| s.y > 0.0).count(), 1);
+    }
+    #[test]
+    fn stopped_passes_are_left_out() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut renderer = Renderer::new(8, 6, 3);
+        renderer.stop = Some(stop.clone());
+        renderer.render_pass(&camera(), 2, &|_| {
+            stop.store(true, Ordering::Relaxed);
+            Vec3::new(1.0, 1.0, 1.0)
+        });
+        assert_eq!((renderer.passes, renderer.spp), (0, 0));
+        assert_eq!(renderer.film, Film::new(8, 6));
+    }
+}
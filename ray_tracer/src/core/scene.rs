@@ -0,0 +1,1165 @@
+use crate::core::cameras::*;
+use crate::core::materials::{
+    Dielectric, DiffuseLight, Ior, Lambertian, Metal, NormalMap, NormalMapped,
+};
+use crate::core::shapes::*;
+use crate::core::{
+    AoIntegrator, BdptIntegrator, Bvh, DebugIntegrator, DensityGrid, Difference, DirectIntegrator,
+    DistantLight, GridMedium, HitList, Hitable, Image, Instance, Integrator, Interpolation,
+    Intersection, Light, LightBvh, Material, Mesh, PathIntegrator, PhotonIntegrator, PointLight,
+    Sdf, SdfObject, SphereLight, SphereObject, SphereSoA, SpotLight, Texture, Tlas, Track,
+    TriangleMesh, Union, WhittedIntegrator,
+};
+use crate::geometry::{random, seed_random, Aabb, Float, Ray, Transform, Vec3};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+
+// Scene descriptions are plain text files with one directive per line
+// followed by key=value parameters. Vectors are written as x,y,z.
+//
+//   image width=800 height=400 spp=100
+//   camera type=fisheye mapping=equisolid fov=180 look_from=12,1.2,4 look_at=2,1,0
+//   camera type=perspective aperture=0.2 blades=6 tilt=-10 look_from=12,2,4 look_at=2,1,0
+//   world random seed=7
+//   material glass type=dielectric ior=bk7 transmittance=0.8,1,0.8 distance=2
+//   sphere center=0,1,0 radius=1 material=glass
+//   torus major=1 minor=0.25 phi_max=270 translate=0,1,0 rotate=90,0,0 material=glass
+//
+// Shapes (cylinder, cone, disk, paraboloid, hyperboloid and torus) are built
+// around the z axis and placed with scale, then rotate (degrees around x,
+// then y, then z), then translate.
+//
+//   sdf mandelbulb power=8 iterations=10 center=0,1,0 step_scale=0.5 material=gold
+//   sdf cuboid size=0.5,1,0.1 twist=1.5 repeat=3,0,3 extent=10,1,10
+//
+// Objects given a name are kept out of the world, to be combined into solids
+// by union, intersection or difference (a minus b). Spheres and complete
+// cylinders are solids, as are their combinations and instances of them.
+// Other objects have no inside and can not be combined.
+//
+//   sphere radius=1 name=ball material=glass
+//   cylinder radius=0.3 z_min=-2 z_max=2 name=hole material=glass
+//   difference a=ball b=hole
+//
+// Signed distance primitives (sphere, cuboid, torus, mandelbulb) are traced
+// inside center +- extent, which repeated ones must give.
+//
+// Any material can be given a height map (bump, with bump_scale in scene
+// units) or a tangent space normal map, both netpbm images over the surface
+// uv. Triangle meshes are read from OBJ files and placed like shapes.
+//
+//   material bricks albedo=0.6,0.3,0.2 bump=bricks.pgm bump_scale=0.01
+//   mesh file=bunny.obj scale=10,10,10 translate=0,-0.3,0 material=bricks
+//
+// Named objects can be grouped under a bounding volume hierarchy, and any
+// named object placed many times as instances, which share it. Instances
+// are kept under a hierarchy of their own, over those of the objects.
+//
+//   mesh file=leaf.obj name=leaf material=green
+//   sphere center=0,1,0 radius=0.2 name=berry material=red
+//   group objects=leaf,berry name=sprig
+//   instance object=sprig translate=1,0,2 rotate=0,45,0 scale=2,2,2 id=sprig1
+//
+// Volumes fill the box from min to max with a density grid file scaled by
+// sigma_t, optionally clipped to a named boundary object. Emission is either
+// proportional to the density or to the values of another grid.
+//
+//   sphere center=0,1,0 radius=1 name=puff
+//   volume file=smoke.grid min=-1,0,-1 max=1,2,1 sigma_t=20 albedo=0.9,0.9,0.9 boundary=puff
+//   volume file=fire.grid sigma_t=5 albedo=0.2,0.2,0.2 emission=8,3,1 emission_file=heat.grid
+//
+// Point, spot and distant lights are sampled with shadow rays. The sky can
+// be dimmed or turned off for scenes lit by them alone.
+//
+//   sky intensity=0.1
+//   light point position=0,4,0 intensity=20,20,20
+//   light spot position=0,4,0 look_at=0,0,0 intensity=50,50,50 cone=30 falloff=20
+//   light distant direction=-1,-1,0 irradiance=2,2,2
+//
+// Spheres made of light materials are area lights, sampled like the others.
+// The lit random world turns some of the small spheres into lights.
+//
+//   material lamp type=light emission=4,4,4
+//   sphere center=0,3,0 radius=0.5 material=lamp
+//   world random_lit seed=7
+//
+// The integrator defaults to path tracing. Bidirectional path tracing finds
+// caustics and light coming through glass, but ignores the absorption of
+// interiors. Photon mapping estimates caustics, including those of point
+// lights, from the photons that land within radius of each diffuse hit.
+// Direct lighting, Whitted ray tracing, ambient occlusion and the normals, uv
+// and depth views are there for comparisons and debugging.
+//
+//   integrator type=path max_depth=50
+//   integrator type=bdpt max_depth=8
+//   integrator type=photon photons=200000 radius=0.05
+//   integrator type=ao samples=16 distance=0.5
+//   integrator type=depth far=20
+//
+// Any parameter of the camera, or of a directive given an id, can be
+// keyframed. Values move between keys linearly, easing in and out of both
+// (smooth), or on a Catmull-Rom spline through all the keys, as chosen at the
+// key they leave. Scenes are parsed for one frame at a time.
+//
+//   sphere center=0,1,0 radius=1 id=ball material=glass
+//   animate target=ball frame=0 center=0,1,0 interpolation=smooth
+//   animate target=ball frame=24 center=0,3,0
+//   animate target=camera frame=0 look_from=13,2,3 fov=20 interpolation=catmull_rom
+//   animate target=camera frame=48 look_from=-13,2,3 fov=30
+pub struct Scene {
+    pub width: usize,
+    pub height: usize,
+    pub spp: usize,
+    pub camera: Box<dyn Camera>,
+    pub world: HitList,
+    pub lights: Vec<Box<dyn Light>>,
+    pub light_sampler: LightBvh,
+    // scale of the sky radiance
+    pub sky: Float,
+    pub integrator: Box<dyn Integrator>,
+}
+
+impl Scene {
+    pub fn load(path: &str) -> Result<Self, String> {
+        Self::load_frame(path, 0.0)
+    }
+    pub fn load_frame(path: &str, frame: Float) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse_frame(&text, frame)
+    }
+    pub fn parse(text: &str) -> Result<Self, String> {
+        Self::parse_frame(text, 0.0)
+    }
+    pub fn parse_frame(text: &str, frame: Float) -> Result<Self, String> {
+        let mut tracks = parse_animation(text)?;
+        let mut image = Params::default();
+        let mut camera = Params::default();
+        let mut camera_line = 0;
+        let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+        // emission of the light materials
+        let mut emitters: HashMap<String, Vec3> = HashMap::new();
+        let mut world = HitList::new();
+        let mut spheres = vec![];
+        let mut lights = vec![];
+        let mut sky = 1.0;
+        let mut integrator: Box<dyn Integrator> = Box::new(PathIntegrator::new(50));
+        // objects kept aside for CSG, groups and instances
+        let mut named: HashMap<String, Box<dyn Hitable>> = HashMap::new();
+        let mut instanced: HashMap<String, Arc<dyn Hitable>> = HashMap::new();
+        let mut instances = vec![];
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut params = Params::parse(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            let target = match params.directive.as_str() {
+                "camera" => Some("camera"),
+                _ => params.values.get("id").map(|s| s.as_str()),
+            };
+            if let Some((_, keys)) = target.and_then(|t| tracks.remove(t)) {
+                for (key, track) in keys {
+                    let value: Vec<String> =
+                        track.at(frame).iter().map(|v| v.to_string()).collect();
+                    params.values.insert(key, value.join(","));
+                }
+            }
+            let result = match params.directive.as_str() {
+                "animate" => Ok(()),
+                "image" => {
+                    image = params;
+                    Ok(())
+                }
+                "camera" => {
+                    camera = params;
+                    camera_line = n + 1;
+                    Ok(())
+                }
+                "sky" => params.float_or("intensity", 1.0).map(|s| sky = s),
+                "integrator" => parse_integrator(&params).map(|i| integrator = i),
+                "light" => parse_light(&params).map(|l| lights.push(l)),
+                "world" => match params.args.first().map(|s| s.as_str()) {
+                    Some("random") => {
+                        // the same scene every time, e.g. when resuming a render
+                        seed_random(params.usize_or("seed", 0)? as u64);
+                        spheres.extend(random_spheres());
+                        Ok(())
+                    }
+                    Some("random_lit") => {
+                        seed_random(params.usize_or("seed", 0)? as u64);
+                        spheres.extend(random_lit_spheres(&mut lights));
+                        Ok(())
+                    }
+                    _ => Err("unknown world".to_string()),
+                },
+                "material" => parse_material(&params).and_then(|(name, m)| {
+                    if params.str_or("type", "") == "light" {
+                        emitters.insert(
+                            name.clone(),
+                            params.vec3_or("emission", Vec3::new(1.0, 1.0, 1.0))?,
+                        );
+                    }
+                    materials.insert(name, m);
+                    Ok(())
+                }),
+                "sphere" => parse_sphere(&params, &materials).map(|s| {
+                    let emission = params.values.get("material").and_then(|m| emitters.get(m));
+                    let s = match emission {
+                        Some(e) => emissive_sphere(s.center, s.radius, *e, &mut lights),
+                        None => s,
+                    };
+                    match params.values.get("name") {
+                        Some(name) => {
+                            named.insert(name.clone(), Box::new(s));
+                        }
+                        None => spheres.push(s),
+                    }
+                }),
+                "cylinder" | "cone" | "disk" | "paraboloid" | "hyperboloid" | "torus" => {
+                    parse_shape(&params, &materials)
+                        .map(|s| add_object(&params, s, &mut world, &mut named))
+                }
+                "mesh" => parse_mesh(&params, &materials)
+                    .map(|m| add_object(&params, Box::new(m), &mut world, &mut named)),
+                "sdf" => parse_sdf(&params, &materials)
+                    .map(|s| add_object(&params, Box::new(s), &mut world, &mut named)),
+                "volume" => parse_volume(&params, &mut named)
+                    .map(|v| add_object(&params, Box::new(v), &mut world, &mut named)),
+                "union" | "intersection" | "difference" => parse_csg(&params, &mut named)
+                    .map(|s| add_object(&params, s, &mut world, &mut named)),
+                "group" => parse_group(&params, &mut named)
+                    .map(|g| add_object(&params, Box::new(g), &mut world, &mut named)),
+                "instance" => parse_instance(&params, &mut named, &mut instanced).map(|i| {
+                    match params.values.get("name") {
+                        Some(_) => add_object(&params, Box::new(i), &mut world, &mut named),
+                        None => instances.push(i),
+                    }
+                }),
+                d => Err(format!("unknown directive '{}'", d)),
+            };
+            result.map_err(|e| format!("line {}: {}", n + 1, e))?;
+        }
+        if let Some((target, (line, _))) = tracks.into_iter().min_by_key(|t| (t.1).0) {
+            return Err(format!("line {}: unknown target '{}'", line, target));
+        }
+        if !spheres.is_empty() {
+            world.list.push(Box::new(SphereSoA::new(&spheres)));
+        }
+        if !instances.is_empty() {
+            world.list.push(Box::new(Tlas::new(instances)));
+        }
+        let width = image.usize_or("width", 800)?;
+        let height = image.usize_or("height", 400)?;
+        Ok(Self {
+            width,
+            height,
+            spp: image.usize_or("spp", 100)?,
+            camera: parse_camera(&camera, width as Float / height as Float)
+                .map_err(|e| format!("line {}: {}", camera_line, e))?,
+            world,
+            light_sampler: LightBvh::new(&lights),
+            lights,
+            sky,
+            integrator,
+        })
+    }
+}
+impl Default for Scene {
+    // The cover image of "Ray Tracing in One Weekend".
+    fn default() -> Self {
+        Self::parse(
+            "image width=800 height=400 spp=100\n\
+             camera type=perspective look_from=12,1.2,4 look_at=2,1,0 fov=30 aperture=0.03\n\
+             world random",
+        )
+        .unwrap()
+    }
+}
+
+#[derive(Default)]
+struct Params {
+    directive: String,
+    args: Vec<String>,
+    values: HashMap<String, String>,
+}
+impl Params {
+    fn parse(line: &str) -> Result<Self, String> {
+        let mut tokens = line.split_whitespace();
+        let mut params = Params {
+            directive: tokens.next().unwrap_or("").to_string(),
+            ..Default::default()
+        };
+        for token in tokens {
+            match token.find('=') {
+                Some(i) => {
+                    params
+                        .values
+                        .insert(token[..i].to_string(), token[i + 1..].to_string());
+                }
+                None if params.values.is_empty() => params.args.push(token.to_string()),
+                None => return Err(format!("expected key=value, found '{}'", token)),
+            }
+        }
+        Ok(params)
+    }
+    fn str_or<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        self.values.get(key).map_or(default, |s| s.as_str())
+    }
+    fn float_or(&self, key: &str, default: Float) -> Result<Float, String> {
+        match self.values.get(key) {
+            Some(s) => s
+                .parse()
+                .map_err(|_| format!("invalid number '{}' for {}", s, key)),
+            None => Ok(default),
+        }
+    }
+    fn usize_or(&self, key: &str, default: usize) -> Result<usize, String> {
+        match self.values.get(key) {
+            Some(s) => s
+                .parse()
+                .map_err(|_| format!("invalid integer '{}' for {}", s, key)),
+            None => Ok(default),
+        }
+    }
+    fn vec3(&self, key: &str) -> Result<Option<Vec3>, String> {
+        match self.values.get(key) {
+            Some(s) => {
+                let c: Vec<Float> = s
+                    .split(',')
+                    .map(|c| c.parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("invalid vector '{}' for {}", s, key))?;
+                match c.len() {
+                    1 => Ok(Some(Vec3::new(c[0], c[0], c[0]))),
+                    3 => Ok(Some(Vec3::new(c[0], c[1], c[2]))),
+                    _ => Err(format!("invalid vector '{}' for {}", s, key)),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+    fn vec3_or(&self, key: &str, default: Vec3) -> Result<Vec3, String> {
+        Ok(self.vec3(key)?.unwrap_or(default))
+    }
+}
+
+// Tracks of the animated parameters of every target, with the line of the
+// first of its keys.
+type Tracks = HashMap<String, (usize, HashMap<String, Track>)>;
+
+fn parse_animation(text: &str) -> Result<Tracks, String> {
+    let mut tracks = Tracks::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if !line.starts_with("animate") {
+            continue;
+        }
+        let p = Params::parse(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+        if p.directive != "animate" {
+            continue;
+        }
+        parse_keyframe(&p, n + 1, &mut tracks).map_err(|e| format!("line {}: {}", n + 1, e))?;
+    }
+    Ok(tracks)
+}
+
+fn parse_keyframe(p: &Params, line: usize, tracks: &mut Tracks) -> Result<(), String> {
+    let target = p.values.get("target").ok_or("missing target")?;
+    let frame = p.values.get("frame").ok_or("missing frame")?;
+    let frame: Float = frame
+        .parse()
+        .map_err(|_| format!("invalid frame '{}'", frame))?;
+    let interpolation = match p.str_or("interpolation", "linear") {
+        "linear" => Interpolation::Linear,
+        "smooth" => Interpolation::SmoothStep,
+        "catmull_rom" => Interpolation::CatmullRom,
+        i => return Err(format!("unknown interpolation '{}'", i)),
+    };
+    let keys = &mut tracks
+        .entry(target.clone())
+        .or_insert((line, HashMap::new()))
+        .1;
+    for (key, value) in &p.values {
+        if key == "target" || key == "frame" || key == "interpolation" {
+            continue;
+        }
+        let value = value
+            .split(',')
+            .map(|c| c.parse::<Float>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("invalid value '{}' for {}", value, key))?;
+        keys.entry(key.clone())
+            .or_default()
+            .insert(frame, value, interpolation)
+            .map_err(|e| format!("{} for {}", e, key))?;
+    }
+    Ok(())
+}
+
+fn parse_camera(p: &Params, aspect: Float) -> Result<Box<dyn Camera>, String> {
+    let look_from = p.vec3_or("look_from", Vec3::new(0.0, 0.0, 0.0))?;
+    let look_at = p.vec3_or("look_at", Vec3::new(0.0, 0.0, -1.0))?;
+    let up = p.vec3_or("up", Vec3::new(0.0, 1.0, 0.0))?;
+    Ok(match p.str_or("type", "perspective") {
+        "perspective" => {
+            let aperture = match (p.values.get("aperture_mask"), p.values.get("blades")) {
+                (Some(path), _) => Aperture::mask(&Image::read_pnm(path)?),
+                (None, Some(_)) => Aperture::polygonal(
+                    p.usize_or("blades", 6)? as u32,
+                    p.float_or("blade_rotation", 0.0)?,
+                ),
+                (None, None) => Aperture::Circular,
+            };
+            Box::new(
+                PerspectiveCamera::new(
+                    look_from,
+                    look_at,
+                    up,
+                    p.float_or("fov", 90.0)?,
+                    aspect,
+                    p.float_or("aperture", 0.0)?,
+                    p.float_or("focus_dist", (look_from - look_at).length())?,
+                )
+                .with_aperture(aperture)
+                .with_tilt(p.float_or("tilt", 0.0)?, p.float_or("swing", 0.0)?)
+                .with_shift(p.float_or("shift_x", 0.0)?, p.float_or("shift_y", 0.0)?),
+            )
+        }
+        "orthographic" => Box::new(OrthographicCamera::new(
+            look_from,
+            look_at,
+            up,
+            p.float_or("height", 2.0)?,
+            aspect,
+        )),
+        "fisheye" => Box::new(FisheyeCamera::new(
+            look_from,
+            look_at,
+            up,
+            p.float_or("fov", 180.0)?,
+            aspect,
+            match p.str_or("mapping", "equidistant") {
+                "equidistant" => FisheyeMapping::Equidistant,
+                "equisolid" => FisheyeMapping::Equisolid,
+                m => return Err(format!("unknown fisheye mapping '{}'", m)),
+            },
+        )),
+        "equirectangular" => Box::new(EquirectangularCamera::new(look_from, look_at, up)),
+        t => return Err(format!("unknown camera type '{}'", t)),
+    })
+}
+
+fn parse_light(p: &Params) -> Result<Box<dyn Light>, String> {
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    let position = p.vec3_or("position", Vec3::new(0.0, 0.0, 0.0))?;
+    let direction = |d: Vec3| {
+        if d.length() > 0.0 {
+            Ok(d)
+        } else {
+            Err("light without a direction".to_string())
+        }
+    };
+    Ok(match p.args.first().map(|s| s.as_str()) {
+        Some("point") => Box::new(PointLight::new(position, p.vec3_or("intensity", white)?)),
+        Some("spot") => {
+            let look_at = p.vec3_or("look_at", Vec3::new(0.0, -1.0, 0.0))?;
+            direction(look_at - position)?;
+            Box::new(SpotLight::new(
+                position,
+                look_at,
+                p.vec3_or("intensity", white)?,
+                p.float_or("cone", 30.0)?,
+                p.float_or("falloff", 25.0)?,
+            ))
+        }
+        Some("distant") => Box::new(DistantLight::new(
+            direction(p.vec3_or("direction", Vec3::new(0.0, -1.0, 0.0))?)?,
+            p.vec3_or("irradiance", white)?,
+        )),
+        _ => return Err("unknown light".to_string()),
+    })
+}
+
+// An integrator from the parameters of an integrator directive, e.g.
+// "type=ao samples=4" given on the command line.
+pub fn parse_integrator_spec(spec: &str) -> Result<Box<dyn Integrator>, String> {
+    parse_integrator(&Params::parse(&format!("integrator {}", spec))?)
+}
+
+fn parse_integrator(p: &Params) -> Result<Box<dyn Integrator>, String> {
+    Ok(match p.str_or("type", "path") {
+        "path" => Box::new(PathIntegrator::new(p.usize_or("max_depth", 50)? as i32)),
+        "direct" => Box::new(DirectIntegrator::new(p.usize_or("max_depth", 5)? as i32)),
+        "bdpt" => Box::new(BdptIntegrator::new(p.usize_or("max_depth", 5)?)),
+        "photon" => Box::new(PhotonIntegrator::new(
+            p.usize_or("max_depth", 50)? as i32,
+            p.usize_or("photons", 200000)?,
+            p.float_or("radius", 0.05)?,
+        )),
+        "whitted" => Box::new(WhittedIntegrator::new(p.usize_or("max_depth", 5)? as i32)),
+        "ao" => Box::new(AoIntegrator::new(
+            p.usize_or("samples", 16)? as u32,
+            p.float_or("distance", Float::INFINITY)?,
+        )),
+        "normals" => Box::new(DebugIntegrator::Normals),
+        "uv" => Box::new(DebugIntegrator::Uv),
+        "depth" => Box::new(DebugIntegrator::Depth(p.float_or("far", 20.0)?)),
+        t => return Err(format!("unknown integrator '{}'", t)),
+    })
+}
+
+fn parse_material(p: &Params) -> Result<(String, Arc<dyn Material>), String> {
+    let name = p.args.first().ok_or("missing material name")?.clone();
+    let albedo = p.vec3_or("albedo", Vec3::new(0.5, 0.5, 0.5))?;
+    let mat: Arc<dyn Material> = match p.str_or("type", "lambertian") {
+        "lambertian" => Arc::new(Lambertian::new(albedo)),
+        "light" => Arc::new(DiffuseLight::new(
+            p.vec3_or("emission", Vec3::new(1.0, 1.0, 1.0))?,
+        )),
+        "metal" => Arc::new(Metal::new(albedo, p.float_or("fuzz", 0.0)?)),
+        "dielectric" => {
+            let ior = match p.str_or("ior", "1.5") {
+                "bk7" => Ior::bk7(),
+                "diamond" => Ior::diamond(),
+                n => Ior::Constant(n.parse().map_err(|_| format!("invalid ior '{}'", n))?),
+            };
+            let mut d = Dielectric::with_ior(ior).with_priority(p.usize_or("priority", 0)? as u32);
+            if let Some(sigma_a) = p.vec3("absorption")? {
+                d = d.with_absorption(sigma_a);
+            }
+            if let Some(color) = p.vec3("transmittance")? {
+                d = d.with_transmittance(color, p.float_or("distance", 1.0)?);
+            }
+            Arc::new(d)
+        }
+        t => return Err(format!("unknown material type '{}'", t)),
+    };
+    let texture = |path: &str| Image::read_pnm(path).map(|i| Texture::Image(Arc::new(i)));
+    let map = match (p.values.get("bump"), p.values.get("normal_map")) {
+        (Some(path), _) => NormalMap::Bump(texture(path)?, p.float_or("bump_scale", 0.01)?),
+        (None, Some(path)) => NormalMap::Tangent(texture(path)?),
+        (None, None) => return Ok((name, mat)),
+    };
+    Ok((name, Arc::new(NormalMapped::new(mat, map))))
+}
+
+fn parse_object_material(
+    p: &Params,
+    materials: &HashMap<String, Arc<dyn Material>>,
+) -> Result<Option<Arc<dyn Material>>, String> {
+    match p.values.get("material") {
+        Some(name) => Ok(Some(
+            materials
+                .get(name)
+                .ok_or(format!("unknown material '{}'", name))?
+                .clone(),
+        )),
+        None => Ok(None),
+    }
+}
+
+fn parse_sphere(
+    p: &Params,
+    materials: &HashMap<String, Arc<dyn Material>>,
+) -> Result<SphereObject, String> {
+    Ok(SphereObject {
+        center: p.vec3_or("center", Vec3::new(0.0, 0.0, 0.0))?,
+        radius: p.float_or("radius", 1.0)?,
+        mat: parse_object_material(p, materials)?,
+    })
+}
+
+fn parse_mesh(p: &Params, materials: &HashMap<String, Arc<dyn Material>>) -> Result<Mesh, String> {
+    let mut mesh = TriangleMesh::load_obj(p.values.get("file").ok_or("missing file")?)?;
+    mesh.transform(&parse_transform(p)?);
+    Ok(Mesh::new(mesh, parse_object_material(p, materials)?))
+}
+
+fn parse_volume(
+    p: &Params,
+    named: &mut HashMap<String, Box<dyn Hitable>>,
+) -> Result<GridMedium, String> {
+    let bounds = Aabb::new(
+        p.vec3_or("min", Vec3::new(-1.0, -1.0, -1.0))?,
+        p.vec3_or("max", Vec3::new(1.0, 1.0, 1.0))?,
+    );
+    let mut volume = GridMedium::new(
+        DensityGrid::load(p.values.get("file").ok_or("missing file")?)?,
+        bounds,
+        p.float_or("sigma_t", 1.0)?,
+        p.vec3_or("albedo", Vec3::new(1.0, 1.0, 1.0))?,
+    );
+    if let Some(name) = p.values.get("boundary") {
+        let boundary = named
+            .remove(name)
+            .ok_or(format!("unknown object '{}'", name))?;
+        volume = volume.with_boundary(boundary);
+    }
+    if let Some(color) = p.vec3("emission")? {
+        let grid = match p.values.get("emission_file") {
+            Some(path) => Some(DensityGrid::load(path)?),
+            None => None,
+        };
+        volume = volume.with_emission(color, grid);
+    }
+    Ok(volume)
+}
+
+fn add_object(
+    p: &Params,
+    object: Box<dyn Hitable>,
+    world: &mut HitList,
+    named: &mut HashMap<String, Box<dyn Hitable>>,
+) {
+    match p.values.get("name") {
+        Some(name) => {
+            named.insert(name.clone(), object);
+        }
+        None => world.list.push(object),
+    }
+}
+
+fn parse_csg(
+    p: &Params,
+    named: &mut HashMap<String, Box<dyn Hitable>>,
+) -> Result<Box<dyn Hitable>, String> {
+    // whether an object is a solid does not depend on the ray
+    let probe = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+    let mut take = |key: &str| {
+        let name = p.values.get(key).ok_or(format!("missing {}", key))?;
+        let object = named
+            .remove(name)
+            .ok_or(format!("unknown object '{}'", name))?;
+        match object.spans(&probe) {
+            Some(_) => Ok(object),
+            None => Err(format!("'{}' is not a closed solid", name)),
+        }
+    };
+    let (a, b) = (take("a")?, take("b")?);
+    Ok(match p.directive.as_str() {
+        "union" => Box::new(Union::new(a, b)),
+        "intersection" => Box::new(Intersection::new(a, b)),
+        _ => Box::new(Difference::new(a, b)),
+    })
+}
+
+fn parse_group(
+    p: &Params,
+    named: &mut HashMap<String, Box<dyn Hitable>>,
+) -> Result<Bvh<Box<dyn Hitable>>, String> {
+    let names = p.values.get("objects").ok_or("missing objects")?;
+    let objects = names
+        .split(',')
+        .map(|name| {
+            named
+                .remove(name)
+                .ok_or(format!("unknown object '{}'", name))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Bvh::new(objects))
+}
+
+// Named objects are shared by all their instances from the first one on.
+fn parse_instance(
+    p: &Params,
+    named: &mut HashMap<String, Box<dyn Hitable>>,
+    instanced: &mut HashMap<String, Arc<dyn Hitable>>,
+) -> Result<Instance, String> {
+    let name = p.values.get("object").ok_or("missing object")?;
+    let object = match (instanced.get(name), named.remove(name)) {
+        (Some(object), _) => object.clone(),
+        (None, Some(object)) => {
+            let object: Arc<dyn Hitable> = Arc::from(object);
+            instanced.insert(name.clone(), object.clone());
+            object
+        }
+        (None, None) => return Err(format!("unknown object '{}'", name)),
+    };
+    Ok(Instance::new(object, parse_transform(p)?))
+}
+
+fn parse_transform(p: &Params) -> Result<Transform, String> {
+    let rotate = p.vec3_or("rotate", Vec3::new(0.0, 0.0, 0.0))?;
+    Ok(
+        Transform::translate(p.vec3_or("translate", Vec3::new(0.0, 0.0, 0.0))?)
+            * Transform::rotate(rotate.z, Vec3::new(0.0, 0.0, 1.0))
+            * Transform::rotate(rotate.y, Vec3::new(0.0, 1.0, 0.0))
+            * Transform::rotate(rotate.x, Vec3::new(1.0, 0.0, 0.0))
+            * Transform::scale(p.vec3_or("scale", Vec3::new(1.0, 1.0, 1.0))?),
+    )
+}
+
+fn parse_shape(
+    p: &Params,
+    materials: &HashMap<String, Arc<dyn Material>>,
+) -> Result<Box<dyn Hitable>, String> {
+    fn place<S: Shape + 'static>(
+        shape: S,
+        mat: Option<Arc<dyn Material>>,
+        transform: Transform,
+    ) -> Box<dyn Hitable> {
+        Box::new(ShapeObject::new(shape, mat).with_transform(transform))
+    }
+    let mat = parse_object_material(p, materials)?;
+    let transform = parse_transform(p)?;
+    let phi_max = p.float_or("phi_max", 360.0)?;
+    let radius = p.float_or("radius", 1.0)?;
+    Ok(match p.directive.as_str() {
+        "cylinder" => place(
+            Cylinder::new(
+                radius,
+                p.float_or("z_min", -1.0)?,
+                p.float_or("z_max", 1.0)?,
+            )
+            .with_phi_max(phi_max),
+            mat,
+            transform,
+        ),
+        "cone" => place(
+            Cone::new(radius, p.float_or("height", 1.0)?).with_phi_max(phi_max),
+            mat,
+            transform,
+        ),
+        "disk" => place(
+            Disk::new(p.float_or("height", 0.0)?, radius)
+                .with_inner_radius(p.float_or("inner_radius", 0.0)?)
+                .with_phi_max(phi_max),
+            mat,
+            transform,
+        ),
+        "paraboloid" => place(
+            Paraboloid::new(radius, p.float_or("z_min", 0.0)?, p.float_or("z_max", 1.0)?)
+                .with_phi_max(phi_max),
+            mat,
+            transform,
+        ),
+        "hyperboloid" => place(
+            Hyperboloid::new(
+                p.vec3_or("p1", Vec3::new(1.0, 0.0, -1.0))?,
+                p.vec3_or("p2", Vec3::new(0.0, 1.0, 1.0))?,
+            )
+            .with_phi_max(phi_max),
+            mat,
+            transform,
+        ),
+        _ => place(
+            Torus::new(p.float_or("major", 1.0)?, p.float_or("minor", 0.25)?).with_phi_max(phi_max),
+            mat,
+            transform,
+        ),
+    })
+}
+
+fn parse_sdf(
+    p: &Params,
+    materials: &HashMap<String, Arc<dyn Material>>,
+) -> Result<SdfObject, String> {
+    let (mut sdf, radius) = match p.args.first().map(|s| s.as_str()) {
+        Some("sphere") => {
+            let r = p.float_or("radius", 1.0)?;
+            (Sdf::Sphere(r), r)
+        }
+        Some("cuboid") => {
+            let size = p.vec3_or("size", Vec3::new(1.0, 1.0, 1.0))?;
+            (Sdf::Cuboid(size), size.length())
+        }
+        Some("torus") => {
+            let (major, minor) = (p.float_or("major", 1.0)?, p.float_or("minor", 0.25)?);
+            (Sdf::Torus(major, minor), major + minor)
+        }
+        Some("mandelbulb") => (
+            Sdf::Mandelbulb(p.float_or("power", 8.0)?, p.usize_or("iterations", 10)?),
+            1.2,
+        ),
+        _ => return Err("unknown sdf primitive".to_string()),
+    };
+    // twisting around y keeps the bounding sphere
+    let twist = p.float_or("twist", 0.0)?;
+    if twist != 0.0 {
+        sdf = sdf.twist(twist);
+    }
+    let extent = match p.vec3("repeat")? {
+        Some(period) => {
+            sdf = sdf.repeat(period);
+            p.vec3("extent")?.ok_or("repeated sdf needs an extent")?
+        }
+        None => p.vec3_or("extent", Vec3::new(radius, radius, radius))?,
+    };
+    let center = p.vec3_or("center", Vec3::new(0.0, 0.0, 0.0))?;
+    Ok(SdfObject::new(
+        sdf.translate(center),
+        Aabb::new(center - extent, center + extent),
+        parse_object_material(p, materials)?,
+    )
+    .with_step_scale(p.float_or("step_scale", 1.0)?))
+}
+
+pub fn random_scene(world: &mut HitList) {
+    for s in random_spheres() {
+        world.list.push(Box::new(s));
+    }
+}
+
+// A sphere that is also a light of the scene.
+fn emissive_sphere(
+    center: Vec3,
+    radius: Float,
+    emission: Vec3,
+    lights: &mut Vec<Box<dyn Light>>,
+) -> SphereObject {
+    lights.push(Box::new(SphereLight::new(center, radius, emission)));
+    let mat = DiffuseLight::new(emission).with_light(lights.len() - 1);
+    SphereObject {
+        center,
+        radius,
+        mat: Some(Arc::new(mat)),
+    }
+}
+
+// The cover image with a quarter of the small spheres glowing in random
+// colors, to be seen with a dim sky.
+pub fn random_lit_spheres(lights: &mut Vec<Box<dyn Light>>) -> Vec<SphereObject> {
+    let mut spheres = random_spheres();
+    for s in spheres.iter_mut().skip(4) {
+        if random() < 0.25 {
+            let emission = 4.0 * Vec3::new(random(), random(), random());
+            *s = emissive_sphere(s.center, s.radius, emission, lights);
+        }
+    }
+    spheres
+}
+
+// The spheres of the cover image of "Ray Tracing in One Weekend".
+pub fn random_spheres() -> Vec<SphereObject> {
+    let mut spheres = vec![
+        SphereObject {
+            center: Vec3::new(0.0, -1000.0, 0.0),
+            radius: 1000.0,
+            mat: Some(Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)))),
+        },
+        SphereObject {
+            center: Vec3::new(0.0, 1.0, 0.0),
+            radius: 1.0,
+            mat: Some(Arc::new(Dielectric::with_ior(Ior::bk7()))),
+        },
+        SphereObject {
+            center: Vec3::new(-4.0, 1.0, 0.0),
+            radius: 1.0,
+            mat: Some(Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)))),
+        },
+        SphereObject {
+            center: Vec3::new(4.0, 1.0, 0.0),
+            radius: 1.0,
+            mat: Some(Arc::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0))),
+        },
+    ];
+    for a in -11..12 {
+        for b in -11..12 {
+            let choose_mat = random();
+            let center = Vec3::new(
+                a as Float + 0.9 * random(),
+                0.2,
+                b as Float + 0.9 * random(),
+            );
+            if (center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                let mat: Arc<dyn Material> = if choose_mat < 0.8 {
+                    Arc::new(Lambertian::new(Vec3::new(
+                        random() * random(),
+                        random() * random(),
+                        random() * random(),
+                    )))
+                } else if choose_mat < 0.95 {
+                    Arc::new(Metal::new(
+                        Vec3::new(
+                            0.5 * (1.0 + random()),
+                            0.5 * (1.0 + random()),
+                            0.5 * (1.0 + random()),
+                        ),
+                        0.5 * random(),
+                    ))
+                } else {
+                    Arc::new(Dielectric::new(1.5))
+                };
+                spheres.push(SphereObject {
+                    center,
+                    radius: 0.2,
+                    mat: Some(mat),
+                });
+            }
+        }
+    }
+    spheres
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{HitRecord, Hitable, Scene};
+    use crate::geometry::{Float, Ray, Vec3};
+    use std::{env, fs};
+    #[test]
+    fn parse_scene() {
+        let scene = Scene::parse(
+            "# filled glass\n\
+             image width=64 height=32 spp=4\n\
+             camera type=fisheye mapping=equisolid fov=170 look_from=0,0,5\n\
+             material glass type=dielectric ior=1.5 priority=2\n\
+             material wine type=dielectric ior=1.33 transmittance=0.6,0.1,0.2 distance=0.5 priority=1\n\
+             sphere center=0,0,0 radius=1 material=glass\n\
+             sphere center=0,0,0 radius=0.9 material=wine\n",
+        )
+        .unwrap();
+        assert_eq!((scene.width, scene.height, scene.spp), (64, 32, 4));
+        let mut rec = HitRecord::new();
+        let r = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(scene.world.hit(&r, 0.001, Float::INFINITY, &mut rec));
+        assert_eq!(rec.t, 4.0);
+        assert!(scene.camera.ray(0.5, 0.5).is_some());
+        assert!(scene.camera.ray(0.0, 0.0).is_none());
+    }
+    #[test]
+    fn parse_shapes() {
+        let scene = Scene::parse(
+            "cylinder radius=0.5 z_min=0 z_max=2 rotate=-90,0,0\n\
+             disk radius=0.5 height=2 rotate=-90,0,0\n\
+             torus major=2 minor=0.5 translate=10,0,0 phi_max=180\n",
+        )
+        .unwrap();
+        // the cylinder stands on the ground, capped at y = 2
+        let mut rec = HitRecord::new();
+        let down = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(scene.world.hit(&down, 0.0, Float::INFINITY, &mut rec));
+        assert!((rec.t - 3.0).abs() < 1e-5 && (rec.normal.y - 1.0).abs() < 1e-5);
+        let side = Ray::new(Vec3::new(-5.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(scene.world.hit(&side, 0.0, Float::INFINITY, &mut rec));
+        assert!((rec.t - 4.5).abs() < 1e-5);
+        // only the half of the torus with y >= 0 is there
+        let up = Ray::new(Vec3::new(10.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(scene.world.hit(&up, 0.0, Float::INFINITY, &mut rec));
+        assert!((rec.p.y - 1.5).abs() < 1e-4, "{:?}", rec.p);
+    }
+    #[test]
+    fn parse_csg() {
+        let scene = Scene::parse(
+            "sphere radius=1 name=ball\n\
+             cylinder radius=0.3 z_min=-2 z_max=2 name=hole\n\
+             difference a=ball b=hole name=bead\n\
+             sphere center=0,0,1 radius=0.5 name=bite\n\
+             difference a=bead b=bite\n",
+        )
+        .unwrap();
+        assert_eq!(scene.world.list.len(), 1);
+        let mut rec = HitRecord::new();
+        let down = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(!scene.world.hit(&down, 0.0, Float::INFINITY, &mut rec));
+        // the bottom of the bite, facing out of the bead
+        let side = Ray::new(Vec3::new(0.4, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(scene.world.hit(&side, 0.0, Float::INFINITY, &mut rec));
+        assert!(
+            (rec.p.z - 0.7).abs() < 1e-5 && rec.normal.z > 0.0,
+            "{:?}",
+            rec.p
+        );
+        let err = Scene::parse("sphere name=a\nunion a=a b=c").err().unwrap();
+        assert!(err.starts_with("line 2") && err.contains("'c'"), "{}", err);
+        let err = Scene::parse("sphere name=a\ntorus name=t\nunion a=a b=t")
+            .err()
+            .unwrap();
+        assert!(err.starts_with("line 3") && err.contains("'t'"), "{}", err);
+    }
+    #[test]
+    fn parse_instances() {
+        let scene = Scene::parse(
+            "sphere center=0,0,0 radius=1 name=ball\n\
+             torus major=1 minor=0.25 name=ring\n\
+             group objects=ball,ring name=planet\n\
+             instance object=planet translate=-5,0,0\n\
+             instance object=planet translate=5,0,0 scale=2,2,2\n\
+             instance object=planet rotate=90,0,0 name=turned\n\
+             instance object=turned translate=0,5,0\n",
+        )
+        .unwrap();
+        assert_eq!(scene.world.list.len(), 1);
+        let mut rec = HitRecord::new();
+        let down = |x: Float, y: Float| Ray::new(Vec3::new(x, y, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(scene
+            .world
+            .hit(&down(-5.0, 0.0), 0.0, Float::INFINITY, &mut rec));
+        assert!((rec.t - 9.0).abs() < 1e-4 && rec.normal.z > 0.99);
+        // the ring of the scaled planet, past its ball
+        assert!(scene
+            .world
+            .hit(&down(7.0, 0.0), 0.0, Float::INFINITY, &mut rec));
+        assert!((rec.t - 9.5).abs() < 1e-4, "{}", rec.t);
+        assert!(!scene
+            .world
+            .hit(&down(-7.5, 1.0), 0.0, Float::INFINITY, &mut rec));
+        // the turned ring lies in front of its ball
+        assert!(scene
+            .world
+            .hit(&down(0.0, 5.0), 0.0, Float::INFINITY, &mut rec));
+        assert!((rec.t - 8.75).abs() < 1e-4, "{}", rec.t);
+        let err = Scene::parse("sphere name=a\ngroup objects=a,b")
+            .err()
+            .unwrap();
+        assert!(err.starts_with("line 2") && err.contains("'b'"), "{}", err);
+        let err = Scene::parse("instance object=a").err().unwrap();
+        assert!(err.starts_with("line 1") && err.contains("'a'"), "{}", err);
+    }
+    #[test]
+    fn parse_sdf() {
+        let scene = Scene::parse(
+            "sdf sphere radius=0.5 repeat=2,0,2 extent=5,0.5,5\n\
+             sdf mandelbulb center=0,5,0 step_scale=0.5\n",
+        )
+        .unwrap();
+        let mut rec = HitRecord::new();
+        let down = Ray::new(Vec3::new(4.0, 5.0, -2.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(scene.world.hit(&down, 0.0, Float::INFINITY, &mut rec));
+        assert!((rec.p.y - 0.5).abs() < 1e-3 && rec.normal.y > 0.99);
+        let down = Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(scene.world.hit(&down, 0.0, Float::INFINITY, &mut rec));
+        assert!(rec.p.y > 5.0);
+        let err = Scene::parse("sdf cuboid repeat=1,1,1").err().unwrap();
+        assert!(err.contains("extent"), "{}", err);
+    }
+    #[test]
+    fn parse_mesh() {
+        let dir = env::temp_dir();
+        let path = |name: &str| {
+            let p = dir.join(format!("ray_tracer_{}_{}", std::process::id(), name));
+            p.to_str().unwrap().to_string()
+        };
+        let (obj, bump) = (path("quad.obj"), path("bump.pgm"));
+        fs::write(&obj, "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+        fs::write(&bump, "P2 2 1 255 0 255").unwrap();
+        let scene = Scene::parse(&format!(
+            "material bumpy bump={} bump_scale=0.1\n\
+             mesh file={} scale=2,2,2 rotate=-90,0,0 material=bumpy\n",
+            bump, obj
+        ));
+        fs::remove_file(&obj).unwrap();
+        fs::remove_file(&bump).unwrap();
+        let scene = scene.unwrap();
+        // the quad lies on the ground, facing up
+        let mut rec = HitRecord::new();
+        let down = Ray::new(Vec3::new(1.5, 5.0, -0.5), Vec3::new(0.0, -1.0, 0.0));
+        assert!(scene.world.hit(&down, 0.0, Float::INFINITY, &mut rec));
+        assert!(rec.t == 5.0 && rec.normal.y > 0.999, "{:?}", rec.normal);
+        let err = Scene::parse("mesh file=missing.obj").err().unwrap();
+        assert!(
+            err.starts_with("line 1") && err.contains("missing.obj"),
+            "{}",
+            err
+        );
+    }
+    #[test]
+    fn parse_volume() {
+        let grid = env::temp_dir().join(format!("ray_tracer_{}.grid", std::process::id()));
+        let grid = grid.to_str().unwrap();
+        fs::write(grid, "1 1 1 1").unwrap();
+        let scene = Scene::parse(&format!(
+            "sphere center=0,1,0 radius=0.5 name=puff\n\
+             volume file={} min=-1,0,-1 max=1,2,1 sigma_t=1000 boundary=puff\n",
+            grid
+        ));
+        fs::remove_file(grid).unwrap();
+        let scene = scene.unwrap();
+        // dense enough to stop rays right at the boundary
+        let mut rec = HitRecord::new();
+        let down = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(scene.world.hit(&down, 0.0, Float::INFINITY, &mut rec));
+        assert!((rec.p.y - 1.5).abs() < 0.05, "{:?}", rec.p);
+        let past = Ray::new(Vec3::new(0.8, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(!scene.world.hit(&past, 0.0, Float::INFINITY, &mut rec));
+        let err = Scene::parse("volume file=missing.grid").err().unwrap();
+        assert!(
+            err.starts_with("line 1") && err.contains("missing.grid"),
+            "{}",
+            err
+        );
+    }
+    #[test]
+    fn parse_lights() {
+        let scene = Scene::parse(
+            "sky intensity=0\n\
+             light point position=0,2,0 intensity=4,4,4\n\
+             light spot position=0,4,0 look_at=0,0,0 cone=20\n\
+             light distant direction=0,-1,0\n",
+        )
+        .unwrap();
+        assert_eq!((scene.lights.len(), scene.sky), (3, 0.0));
+        let s = scene.lights[0].sample(&Vec3::new(0.0, 0.0, 0.0)).unwrap();
+        assert_eq!(s.radiance, Vec3::new(1.0, 1.0, 1.0));
+        let err = Scene::parse("light area").err().unwrap();
+        assert!(err.starts_with("line 1"), "{}", err);
+        for light in [
+            "light spot position=0,4,0 look_at=0,4,0",
+            "light distant direction=0,0,0",
+        ] {
+            let err = Scene::parse(light).err().unwrap();
+            assert!(err.ends_with("light without a direction"), "{}", err);
+        }
+    }
+    #[test]
+    fn parse_area_lights() {
+        let scene = Scene::parse(
+            "material lamp type=light emission=2,2,2\n\
+             sphere center=0,3,0 radius=0.5 material=lamp\n\
+             light point position=0,5,0\n\
+             world random_lit seed=3\n",
+        )
+        .unwrap();
+        assert!(scene.lights.len() > 50, "{}", scene.lights.len());
+        // the lamp is both a light and an emitting surface that knows it
+        let mut rec = HitRecord::new();
+        let down = Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(scene.world.hit(&down, 0.0, Float::INFINITY, &mut rec));
+        let lamp = rec.mat.clone().unwrap();
+        assert_eq!(lamp.emitted(&down, &rec), Vec3::new(2.0, 2.0, 2.0));
+        assert_eq!(lamp.light_index(), Some(0));
+        assert!(scene.lights[0].pdf(&down.o, &down.d) > 0.0);
+        let p = Vec3::new(0.0, 1.0, 0.0);
+        let total: Float = (0..scene.lights.len())
+            .map(|i| scene.light_sampler.pmf(&p, i))
+            .sum();
+        assert!((total - 1.0).abs() < 1e-4);
+    }
+    #[test]
+    fn report_errors_with_line() {
+        let err = Scene::parse("image width=10\ncamera type=pinhole")
+            .err()
+            .unwrap();
+        assert!(err.starts_with("line 2"), "{}", err);
+        let err = Scene::parse("sphere material=gold").err().unwrap();
+        assert!(err.contains("gold"), "{}", err);
+    }
+    #[test]
+    fn parse_animation() {
+        let text = "camera look_from=0,0,5 look_at=0,0,0\n\
+                    sphere center=0,0,0 radius=1 id=ball\n\
+                    animate target=ball frame=0 center=0,0,0 radius=1\n\
+                    animate target=ball frame=10 center=0,4,0 radius=0.5\n\
+                    animate target=camera frame=10 look_from=0,2,5\n";
+        let mut rec = HitRecord::new();
+        let top = |scene: &Scene, rec: &mut HitRecord| {
+            let down = Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+            assert!(scene.world.hit(&down, 0.0, Float::INFINITY, rec));
+            rec.p.y
+        };
+        let scene = Scene::parse(text).unwrap();
+        assert!((top(&scene, &mut rec) - 1.0).abs() < 1e-4);
+        let scene = Scene::parse_frame(text, 5.0).unwrap();
+        assert!((top(&scene, &mut rec) - 2.75).abs() < 1e-4);
+        let r = scene.camera.ray(0.5, 0.5).unwrap();
+        assert_eq!(r.o, Vec3::new(0.0, 2.0, 5.0));
+        let scene = Scene::parse_frame(text, 20.0).unwrap();
+        assert!((top(&scene, &mut rec) - 4.5).abs() < 1e-4);
+        let err = Scene::parse("animate target=lamp frame=0 radius=1")
+            .err()
+            .unwrap();
+        assert!(err.starts_with("line 1") && err.contains("lamp"), "{}", err);
+        let err = Scene::parse("animate target=camera fov=30").err().unwrap();
+        assert!(err.contains("frame"), "{}", err);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::core::stats::{count, Counter};
+use crate::core::{HitRecord, Hitable, Material};
+use crate::geometry::{normalize, Aabb, Float, Ray, Vec3};
+use std::sync::Arc;
+
+// Signed distance functions, negative inside. Operations that bend space
+// (smooth unions, twists, fractals) only bound the distance, see
+// SdfObject::with_step_scale.
+#[derive(Clone)]
+pub enum Sdf {
+    Sphere(Float),
+    // half extents
+    Cuboid(Vec3),
+    // major radius around the y axis, minor radius
+    Torus(Float, Float),
+    // power and iterations of the distance estimator
+    Mandelbulb(Float, usize),
+    Custom(Arc<dyn Fn(&Vec3) -> Float + Send + Sync>),
+    Translate(Box<Sdf>, Vec3),
+    Union(Box<Sdf>, Box<Sdf>),
+    // polynomial smooth min with blend radius k
+    SmoothUnion(Box<Sdf>, Box<Sdf>, Float),
+    // rotation around the y axis of `rate` radians per unit of height
+    Twist(Box<Sdf>, Float),
+    // infinite copies along the axes with a non zero period
+    Repeat(Box<Sdf>, Vec3),
+}
+impl Sdf {
+    pub fn from_fn<F>(f: F) -> Self
+    where
+        F: Fn(&Vec3) -> Float + Send + Sync + 'static,
+    {
+        Sdf::Custom(Arc::new(f))
+    }
+    pub fn translate(self, t: Vec3) -> Self {
+        Sdf::Translate(Box::new(self), t)
+    }
+    pub fn union(self, other: Sdf) -> Self {
+        Sdf::Union(Box::new(self), Box::new(other))
+    }
+    pub fn smooth_union(self, other: Sdf, k: Float) -> Self {
+        Sdf::SmoothUnion(Box::new(self), Box::new(other), k)
+    }
+    pub fn twist(self, rate: Float) -> Self {
+        Sdf::Twist(Box::new(self), rate)
+    }
+    pub fn repeat(self, period: Vec3) -> Self {
+        Sdf::Repeat(Box::new(self), period)
+    }
+    pub fn distance(&self, p: &Vec3) -> Float {
+        match self {
+            Sdf::Sphere(r) => p.length() - r,
+            Sdf::Cuboid(b) => {
+                let q = Vec3::new(p.x.abs() - b.x, p.y.abs() - b.y, p.z.abs() - b.z);
+                let outside = Vec3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0));
+                outside.length() + q.x.max(q.y).max(q.z).min(0.0)
+            }
+            Sdf::Torus(major, minor) => {
+                let rho = (p.x * p.x + p.z * p.z).sqrt() - major;
+                (rho * rho + p.y * p.y).sqrt() - minor
+            }
+            Sdf::Mandelbulb(power, iterations) => mandelbulb(p, *power, *iterations),
+            Sdf::Custom(f) => f(p),
+            Sdf::Translate(a, t) => a.distance(&(*p - *t)),
+            Sdf::Union(a, b) => a.distance(p).min(b.distance(p)),
+            Sdf::SmoothUnion(a, b, k) => {
+                let (da, db) = (a.distance(p), b.distance(p));
+                let h = (k - (da - db).abs()).max(0.0) / k;
+                da.min(db) - h * h * k * 0.25
+            }
+            Sdf::Twist(a, rate) => {
+                let (s, c) = (rate * p.y).sin_cos();
+                a.distance(&Vec3::new(c * p.x - s * p.z, p.y, s * p.x + c * p.z))
+            }
+            Sdf::Repeat(a, period) => {
+                let mut q = *p;
+                for i in 0..3 {
+                    if period[i] != 0.0 {
+                        q[i] -= period[i] * (p[i] / period[i]).round();
+                    }
+                }
+                a.distance(&q)
+            }
+        }
+    }
+    // Central differences of the distance.
+    pub fn gradient(&self, p: &Vec3, h: Float) -> Vec3 {
+        let mut g = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..3 {
+            let (mut a, mut b) = (*p, *p);
+            a[i] += h;
+            b[i] -= h;
+            g[i] = (self.distance(&a) - self.distance(&b)) / (2.0 * h);
+        }
+        g
+    }
+}
+
+// Distance estimator of the power n mandelbulb, see Hart, Sandin and
+// Kauffman, "Ray tracing deterministic 3-D fractals".
+fn mandelbulb(p: &Vec3, power: Float, iterations: usize) -> Float {
+    let mut z = *p;
+    let mut dr = 1.0;
+    let mut r = 0.0;
+    for _ in 0..iterations {
+        r = z.length();
+        if r > 2.0 {
+            break;
+        }
+        if r == 0.0 {
+            // 0 maps to p whatever its angles, which are undefined
+            dr = 1.0;
+            z = *p;
+            continue;
+        }
+        let theta = (z.z / r).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        let zr = r.powf(power);
+        z =
+            zr * Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            ) + *p;
+    }
+    if r == 0.0 {
+        // the limit of r ln r, the orbit of the origin never leaving it
+        return 0.0;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+// Surface of an Sdf found by sphere tracing inside `bounds`.
+pub struct SdfObject {
+    pub sdf: Sdf,
+    pub bounds: Aabb,
+    pub mat: Option<Arc<dyn Material>>,
+    epsilon: Float,
+    step_scale: Float,
+    max_steps: usize,
+}
+impl SdfObject {
+    pub fn new(sdf: Sdf, bounds: Aabb, mat: Option<Arc<dyn Material>>) -> Self {
+        Self {
+            sdf,
+            bounds,
+            mat,
+            epsilon: 1e-4,
+            step_scale: 1.0,
+            max_steps: 512,
+        }
+    }
+    // Distance below which the surface is hit.
+    pub fn with_epsilon(mut self, epsilon: Float) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+    // Fraction of the distance to step, below 1 for functions that
+    // overestimate it.
+    pub fn with_step_scale(mut self, step_scale: Float) -> Self {
+        self.step_scale = step_scale;
+        self
+    }
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+}
+impl Hitable for SdfObject {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        count(Counter::IntersectionTests, 1);
+        let (mut t, t1) = match self.bounds.intersect(ray, t_min, t_max) {
+            Some(range) => range,
+            None => return false,
+        };
+        let length = ray.d.length();
+        for _ in 0..self.max_steps {
+            let p = ray.point_at_parameter(t);
+            // the absolute distance also traces from inside, for dielectrics
+            let d = self.sdf.distance(&p).abs();
+            if d < self.epsilon {
+                if t <= t_min {
+                    return false;
+                }
+                rec.t = t;
+                rec.p = p;
+                // spawned rays start clear of the epsilon shell
+                let e = 2.0 * self.epsilon;
+                rec.p_error = Vec3::new(e, e, e);
+                rec.normal = normalize(self.sdf.gradient(&p, self.epsilon));
+                rec.shading_normal = rec.normal;
+                rec.u = 0.0;
+                rec.v = 0.0;
+                rec.dpdu = Vec3::new(0.0, 0.0, 0.0);
+                rec.dpdv = Vec3::new(0.0, 0.0, 0.0);
+                rec.mat = self.mat.clone();
+                return true;
+            }
+            t += self.step_scale * d / length;
+            if t >= t1 {
+                return false;
+            }
+        }
+        false
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{HitRecord, Hitable, Sdf, SdfObject, SphereObject};
+    use crate::geometry::{consts, dot, normalize, random_in_unit_sphere, seed_random};
+    use crate::geometry::{Aabb, Float, Ray, Vec3};
+    fn unit_box() -> Aabb {
+        Aabb::new(Vec3::new(-2.0, -2.0, -2.0), Vec3::new(2.0, 2.0, 2.0))
+    }
+    #[test]
+    fn traces_like_the_analytic_sphere() {
+        seed_random(4);
+        let c = Vec3::new(0.2, -0.1, 0.3);
+        let sdf = SdfObject::new(Sdf::Sphere(1.0).translate(c), unit_box(), None);
+        let sphere = SphereObject {
+            center: c,
+            radius: 1.0,
+            mat: None,
+        };
+        for _ in 0..1000 {
+            let o = 5.0 * normalize(random_in_unit_sphere());
+            let r = Ray::new(o, 0.5 * random_in_unit_sphere() - o);
+            let (mut a, mut b) = (HitRecord::new(), HitRecord::new());
+            let hit = sphere.hit(&r, 0.0, Float::INFINITY, &mut b);
+            // grazing rays may go either way
+            if sdf.hit(&r, 0.0, Float::INFINITY, &mut a) != hit {
+                assert!(dot(&normalize(r.d), &b.normal).abs() < 0.05);
+                continue;
+            }
+            if hit {
+                assert!(((a.p - c).length() - 1.0).abs() < 2e-4, "{:?}", a.p);
+                assert!(dot(&a.normal, &b.normal) > 0.999);
+                // leaving and entering the surface
+                let mut next = HitRecord::new();
+                assert!(!sdf.hit(&a.spawn_ray(a.normal), 0.0, Float::INFINITY, &mut next));
+                assert!(sdf.hit(&a.spawn_ray(r.d), 0.0, Float::INFINITY, &mut next));
+                assert!(next.t * r.d.length() > 1e-2);
+            }
+        }
+    }
+    #[test]
+    fn operations() {
+        let p = Vec3::new(0.0, 0.0, 0.0);
+        let a = Sdf::Sphere(1.0).translate(Vec3::new(-1.0, 0.0, 0.0));
+        let b = Sdf::Sphere(1.0).translate(Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(a.clone().union(b.clone()).distance(&p), 0.0);
+        // blending fills the gap between the spheres
+        assert!(a.smooth_union(b, 0.5).distance(&p) < -0.1);
+        let cells = Sdf::Sphere(0.5).repeat(Vec3::new(3.0, 0.0, 3.0));
+        assert!((cells.distance(&Vec3::new(30.0, 0.0, -6.0)) + 0.5).abs() < 1e-4);
+        assert!((cells.distance(&Vec3::new(30.0, 3.0, -6.0)) - 2.5).abs() < 1e-4);
+        // a quarter turn per unit of height swaps x and z
+        let twisted = Sdf::Cuboid(Vec3::new(1.0, 2.0, 0.1)).twist(consts::FRAC_PI_2);
+        assert!(twisted.distance(&Vec3::new(0.0, 0.0, 0.9)) > 0.5);
+        assert!(twisted.distance(&Vec3::new(0.0, 1.0, 0.9)) < 0.0);
+        assert!(Sdf::Mandelbulb(8.0, 8).distance(&Vec3::new(0.1, 0.1, 0.1)) < 0.0);
+        assert!(Sdf::Mandelbulb(8.0, 8).distance(&Vec3::new(0.0, 0.0, 3.0)) > 1.0);
+        assert_eq!(
+            Sdf::Mandelbulb(8.0, 8).distance(&Vec3::new(0.0, 0.0, 0.0)),
+            0.0
+        );
+        assert!(Sdf::Mandelbulb(8.0, 8).distance(&Vec3::new(1e-3, 0.0, 0.0)) <= 0.0);
+    }
+}
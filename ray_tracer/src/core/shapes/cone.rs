@@ -0,0 +1,63 @@
+use crate::core::shapes::{efloat_ray, first_root, phi, phi_max, point_error, Shape, SurfaceHit};
+use crate::geometry::{quadratic, Aabb, EFloat, Float, Ray, Vec3};
+
+// Cone with its base of the given radius at z = 0 and its apex at z = height.
+pub struct Cone {
+    radius: Float,
+    height: Float,
+    phi_max: Float,
+}
+impl Cone {
+    pub fn new(radius: Float, height: Float) -> Self {
+        Self {
+            radius,
+            height,
+            phi_max: phi_max(360.0),
+        }
+    }
+    // Sweep angle in degrees.
+    pub fn with_phi_max(mut self, degrees: Float) -> Self {
+        self.phi_max = phi_max(degrees);
+        self
+    }
+}
+impl Shape for Cone {
+    fn intersect(
+        &self,
+        ray: &Ray,
+        o_error: &Vec3,
+        d_error: &Vec3,
+        t_min: Float,
+        t_max: Float,
+    ) -> Option<SurfaceHit> {
+        let (o, d) = efloat_ray(ray, o_error, d_error);
+        let k = EFloat::from(self.radius) / EFloat::from(self.height);
+        let k = k * k;
+        let h = EFloat::from(self.height);
+        let a = d[0] * d[0] + d[1] * d[1] - k * d[2] * d[2];
+        let b = EFloat::from(2.0) * (d[0] * o[0] + d[1] * o[1] - k * d[2] * (o[2] - h));
+        let c = o[0] * o[0] + o[1] * o[1] - k * (o[2] - h) * (o[2] - h);
+        first_root(quadratic(a, b, c), t_min, t_max, |t| {
+            let p = ray.point_at_parameter(t.v);
+            let phi = phi(p.x, p.y);
+            if p.z < 0.0 || p.z > self.height || phi > self.phi_max {
+                return None;
+            }
+            let rho = (p.x * p.x + p.y * p.y).sqrt();
+            Some(SurfaceHit {
+                t: t.v,
+                p,
+                p_error: point_error(&o, &d, t),
+                normal: Vec3::new(self.height * p.x, self.height * p.y, self.radius * rho),
+                u: phi / self.phi_max,
+                v: p.z / self.height,
+            })
+        })
+    }
+    fn bounds(&self) -> Aabb {
+        Aabb::new(
+            Vec3::new(-self.radius, -self.radius, 0.0),
+            Vec3::new(self.radius, self.radius, self.height),
+        )
+    }
+}
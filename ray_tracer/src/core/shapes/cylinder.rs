@@ -0,0 +1,151 @@
+use crate::core::shapes::{efloat_ray, first_root, phi, phi_max, point_error, Shape, SurfaceHit};
+use crate::geometry::{consts, gamma, quadratic, Aabb, EFloat, Float, Ray, Vec3};
+
+// Open cylinder around the z axis between z_min and z_max.
+pub struct Cylinder {
+    radius: Float,
+    z_min: Float,
+    z_max: Float,
+    phi_max: Float,
+}
+impl Cylinder {
+    pub fn new(radius: Float, z_min: Float, z_max: Float) -> Self {
+        Self {
+            radius,
+            z_min: z_min.min(z_max),
+            z_max: z_min.max(z_max),
+            phi_max: phi_max(360.0),
+        }
+    }
+    // Sweep angle in degrees.
+    pub fn with_phi_max(mut self, degrees: Float) -> Self {
+        self.phi_max = phi_max(degrees);
+        self
+    }
+}
+impl Shape for Cylinder {
+    fn intersect(
+        &self,
+        ray: &Ray,
+        o_error: &Vec3,
+        d_error: &Vec3,
+        t_min: Float,
+        t_max: Float,
+    ) -> Option<SurfaceHit> {
+        let (o, d) = efloat_ray(ray, o_error, d_error);
+        let r = EFloat::from(self.radius);
+        let a = d[0] * d[0] + d[1] * d[1];
+        let b = EFloat::from(2.0) * (d[0] * o[0] + d[1] * o[1]);
+        let c = o[0] * o[0] + o[1] * o[1] - r * r;
+        first_root(quadratic(a, b, c), t_min, t_max, |t| {
+            let mut p = ray.point_at_parameter(t.v);
+            // reprojects onto the surface
+            let rho = (p.x * p.x + p.y * p.y).sqrt();
+            p.x *= self.radius / rho;
+            p.y *= self.radius / rho;
+            let phi = phi(p.x, p.y);
+            if p.z < self.z_min || p.z > self.z_max || phi > self.phi_max {
+                return None;
+            }
+            Some(SurfaceHit {
+                t: t.v,
+                p,
+                p_error: gamma(3) * Vec3::new(p.x.abs(), p.y.abs(), 0.0),
+                normal: Vec3::new(p.x, p.y, 0.0),
+                u: phi / self.phi_max,
+                v: (p.z - self.z_min) / (self.z_max - self.z_min),
+            })
+        })
+    }
+    // The solid capped at z_min and z_max, when the sweep is complete.
+    fn spans(
+        &self,
+        ray: &Ray,
+        o_error: &Vec3,
+        d_error: &Vec3,
+    ) -> Option<Vec<(SurfaceHit, SurfaceHit)>> {
+        if self.phi_max < 2.0 * consts::PI {
+            return None;
+        }
+        let (o, d) = efloat_ray(ray, o_error, d_error);
+        // the line inside the slab between the caps
+        let (mut near, mut far) = (Float::NEG_INFINITY, Float::INFINITY);
+        let (mut near_cap, mut far_cap) = (None, None);
+        if ray.d.z != 0.0 {
+            let t_min = (EFloat::from(self.z_min) - o[2]) / d[2];
+            let t_max = (EFloat::from(self.z_max) - o[2]) / d[2];
+            let (t0, t1) = if t_min.v < t_max.v {
+                ((t_min, self.z_min), (t_max, self.z_max))
+            } else {
+                ((t_max, self.z_max), (t_min, self.z_min))
+            };
+            near = t0.0.v;
+            far = t1.0.v;
+            near_cap = Some(t0);
+            far_cap = Some(t1);
+        } else if ray.o.z < self.z_min || ray.o.z > self.z_max {
+            return Some(vec![]);
+        }
+        // and inside the infinite cylinder
+        let r = EFloat::from(self.radius);
+        let a = d[0] * d[0] + d[1] * d[1];
+        let b = EFloat::from(2.0) * (d[0] * o[0] + d[1] * o[1]);
+        let c = o[0] * o[0] + o[1] * o[1] - r * r;
+        let side = if a.v == 0.0 {
+            if c.v > 0.0 {
+                return Some(vec![]);
+            }
+            None
+        } else {
+            match quadratic(a, b, c) {
+                Some((t0, t1)) => Some((t0.v, t1.v)),
+                None => return Some(vec![]),
+            }
+        };
+        let side_hit = |t: Float| {
+            let mut p = ray.point_at_parameter(t);
+            let rho = (p.x * p.x + p.y * p.y).sqrt();
+            p.x *= self.radius / rho;
+            p.y *= self.radius / rho;
+            SurfaceHit {
+                t,
+                p,
+                p_error: gamma(3) * Vec3::new(p.x.abs(), p.y.abs(), 0.0),
+                normal: Vec3::new(p.x, p.y, 0.0),
+                u: phi(p.x, p.y) / self.phi_max,
+                v: (p.z - self.z_min) / (self.z_max - self.z_min),
+            }
+        };
+        let cap_hit = |(t, z): (EFloat, Float)| {
+            let mut p = ray.point_at_parameter(t.v);
+            p.z = z;
+            let e = point_error(&o, &d, t);
+            SurfaceHit {
+                t: t.v,
+                p,
+                p_error: Vec3::new(e.x, e.y, 0.0),
+                normal: Vec3::new(0.0, 0.0, if z == self.z_min { -1.0 } else { 1.0 }),
+                u: phi(p.x, p.y) / self.phi_max,
+                v: (p.x * p.x + p.y * p.y).sqrt() / self.radius,
+            }
+        };
+        let enter = match side {
+            Some((t0, _)) if t0 > near => side_hit(t0),
+            _ => cap_hit(near_cap?),
+        };
+        let exit = match side {
+            Some((_, t1)) if t1 < far => side_hit(t1),
+            _ => cap_hit(far_cap?),
+        };
+        if enter.t >= exit.t {
+            return Some(vec![]);
+        }
+        Some(vec![(enter, exit)])
+    }
+    fn bounds(&self) -> Aabb {
+        Aabb::new(
+            Vec3::new(-self.radius, -self.radius, self.z_min),
+            Vec3::new(self.radius, self.radius, self.z_max),
+        )
+    }
+}
@@ -0,0 +1,72 @@
+use crate::core::shapes::{efloat_ray, phi, phi_max, Shape, SurfaceHit};
+use crate::geometry::{Aabb, EFloat, Float, Ray, Vec3};
+
+// Disk, or annulus, in the plane z = height, facing +z.
+pub struct Disk {
+    height: Float,
+    radius: Float,
+    inner_radius: Float,
+    phi_max: Float,
+}
+impl Disk {
+    pub fn new(height: Float, radius: Float) -> Self {
+        Self {
+            height,
+            radius,
+            inner_radius: 0.0,
+            phi_max: phi_max(360.0),
+        }
+    }
+    pub fn with_inner_radius(mut self, inner_radius: Float) -> Self {
+        self.inner_radius = inner_radius.clamp(0.0, self.radius);
+        self
+    }
+    // Sweep angle in degrees.
+    pub fn with_phi_max(mut self, degrees: Float) -> Self {
+        self.phi_max = phi_max(degrees);
+        self
+    }
+}
+impl Shape for Disk {
+    fn intersect(
+        &self,
+        ray: &Ray,
+        o_error: &Vec3,
+        d_error: &Vec3,
+        t_min: Float,
+        t_max: Float,
+    ) -> Option<SurfaceHit> {
+        if ray.d.z == 0.0 {
+            return None;
+        }
+        let (o, d) = efloat_ray(ray, o_error, d_error);
+        let t = (EFloat::from(self.height) - o[2]) / d[2];
+        if t.lower_bound() <= t_min || t.upper_bound() >= t_max {
+            return None;
+        }
+        let mut p = ray.point_at_parameter(t.v);
+        p.z = self.height;
+        let rho2 = p.x * p.x + p.y * p.y;
+        if rho2 > self.radius * self.radius || rho2 < self.inner_radius * self.inner_radius {
+            return None;
+        }
+        let phi = phi(p.x, p.y);
+        if phi > self.phi_max {
+            return None;
+        }
+        Some(SurfaceHit {
+            t: t.v,
+            p,
+            p_error: Vec3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            u: phi / self.phi_max,
+            v: (self.radius - rho2.sqrt()) / (self.radius - self.inner_radius),
+        })
+    }
+    fn bounds(&self) -> Aabb {
+        Aabb::new(
+            Vec3::new(-self.radius, -self.radius, self.height),
+            Vec3::new(self.radius, self.radius, self.height),
+        )
+    }
+}
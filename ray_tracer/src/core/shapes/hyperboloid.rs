@@ -0,0 +1,101 @@
+use crate::core::shapes::{efloat_ray, first_root, phi_max, point_error, Shape, SurfaceHit};
+use crate::geometry::{consts, quadratic, Aabb, EFloat, Float, Ray, Vec3};
+
+// Surface swept by the segment p1 p2 around the z axis, see pbrt 3.5.
+pub struct Hyperboloid {
+    p1: Vec3,
+    p2: Vec3,
+    z_min: Float,
+    z_max: Float,
+    r_max: Float,
+    // implicit form ah (x^2 + y^2) - ch z^2 = 1
+    ah: Float,
+    ch: Float,
+    phi_max: Float,
+}
+impl Hyperboloid {
+    pub fn new(p1: Vec3, p2: Vec3) -> Self {
+        let (p1, p2) = if p2.z == 0.0 { (p2, p1) } else { (p1, p2) };
+        let radius = |p: &Vec3| (p.x * p.x + p.y * p.y).sqrt();
+        // slides a point along the line until the coefficients are finite
+        let mut pp = p1;
+        let (mut ah, mut ch);
+        loop {
+            pp += 2.0 * (p2 - p1);
+            let xy1 = pp.x * pp.x + pp.y * pp.y;
+            let xy2 = p2.x * p2.x + p2.y * p2.y;
+            ah = (1.0 / xy1 - (pp.z * pp.z) / (xy1 * p2.z * p2.z))
+                / (1.0 - (xy2 * pp.z * pp.z) / (xy1 * p2.z * p2.z));
+            ch = (ah * xy2 - 1.0) / (p2.z * p2.z);
+            if ah.is_finite() && ch.is_finite() {
+                break;
+            }
+        }
+        Self {
+            p1,
+            p2,
+            z_min: p1.z.min(p2.z),
+            z_max: p1.z.max(p2.z),
+            r_max: radius(&p1).max(radius(&p2)),
+            ah,
+            ch,
+            phi_max: phi_max(360.0),
+        }
+    }
+    // Sweep angle in degrees.
+    pub fn with_phi_max(mut self, degrees: Float) -> Self {
+        self.phi_max = phi_max(degrees);
+        self
+    }
+    pub fn coefficients(&self) -> (Float, Float) {
+        (self.ah, self.ch)
+    }
+}
+impl Shape for Hyperboloid {
+    fn intersect(
+        &self,
+        ray: &Ray,
+        o_error: &Vec3,
+        d_error: &Vec3,
+        t_min: Float,
+        t_max: Float,
+    ) -> Option<SurfaceHit> {
+        let (o, d) = efloat_ray(ray, o_error, d_error);
+        let (ah, ch) = (EFloat::from(self.ah), EFloat::from(self.ch));
+        let a = ah * (d[0] * d[0] + d[1] * d[1]) - ch * d[2] * d[2];
+        let b = EFloat::from(2.0) * (ah * (d[0] * o[0] + d[1] * o[1]) - ch * d[2] * o[2]);
+        let c = ah * (o[0] * o[0] + o[1] * o[1]) - ch * o[2] * o[2] - EFloat::from(1.0);
+        first_root(quadratic(a, b, c), t_min, t_max, |t| {
+            let p = ray.point_at_parameter(t.v);
+            if p.z < self.z_min || p.z > self.z_max {
+                return None;
+            }
+            // angle from the swept segment at that height
+            let v = (p.z - self.p1.z) / (self.p2.z - self.p1.z);
+            let pr = (1.0 - v) * self.p1 + v * self.p2;
+            let mut phi = (pr.x * p.y - p.x * pr.y).atan2(p.x * pr.x + p.y * pr.y);
+            if phi < 0.0 {
+                phi += 2.0 * consts::PI;
+            }
+            if phi > self.phi_max {
+                return None;
+            }
+            // gradient of the implicit form, oriented away from the axis
+            let s = self.ah.signum();
+            Some(SurfaceHit {
+                t: t.v,
+                p,
+                p_error: point_error(&o, &d, t),
+                normal: s * Vec3::new(self.ah * p.x, self.ah * p.y, -self.ch * p.z),
+                u: phi / self.phi_max,
+                v,
+            })
+        })
+    }
+    fn bounds(&self) -> Aabb {
+        Aabb::new(
+            Vec3::new(-self.r_max, -self.r_max, self.z_min),
+            Vec3::new(self.r_max, self.r_max, self.z_max),
+        )
+    }
+}
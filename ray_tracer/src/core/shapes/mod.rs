@@ -0,0 +1,280 @@
+mod cone;
+mod cylinder;
+mod disk;
+mod hyperboloid;
+mod paraboloid;
+mod torus;
+
+pub use self::cone::Cone;
+pub use self::cylinder::Cylinder;
+pub use self::disk::Disk;
+pub use self::hyperboloid::Hyperboloid;
+pub use self::paraboloid::Paraboloid;
+pub use self::torus::Torus;
+
+use crate::core::stats::{count, Counter};
+use crate::core::{HitRecord, Hitable, Material, Span};
+use crate::geometry::{consts, normalize, Aabb, EFloat, Float, Ray, Transform, Vec3};
+use std::sync::Arc;
+
+// Intersection with a shape, in its object space.
+pub struct SurfaceHit {
+    pub t: Float,
+    pub p: Vec3,
+    pub p_error: Vec3,
+    // outward, not normalized
+    pub normal: Vec3,
+    pub u: Float,
+    pub v: Float,
+}
+
+// Shapes are defined in object space around the z axis. Rays come with the
+// error bounds of their origin and direction.
+pub trait Shape: Send + Sync {
+    fn intersect(
+        &self,
+        ray: &Ray,
+        o_error: &Vec3,
+        d_error: &Vec3,
+        t_min: Float,
+        t_max: Float,
+    ) -> Option<SurfaceHit>;
+    fn bounds(&self) -> Aabb;
+    // Entry and exit hits of the whole line of the ray, for shapes that can
+    // be closed into solids.
+    fn spans(
+        &self,
+        _ray: &Ray,
+        _o_error: &Vec3,
+        _d_error: &Vec3,
+    ) -> Option<Vec<(SurfaceHit, SurfaceHit)>> {
+        None
+    }
+}
+
+// A shape placed in the world, with its material.
+pub struct ShapeObject<S: Shape> {
+    pub shape: S,
+    pub transform: Transform,
+    pub mat: Option<Arc<dyn Material>>,
+}
+impl<S: Shape> ShapeObject<S> {
+    pub fn new(shape: S, mat: Option<Arc<dyn Material>>) -> Self {
+        Self {
+            shape,
+            transform: Transform::identity(),
+            mat,
+        }
+    }
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+}
+impl<S: Shape> Hitable for ShapeObject<S> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        count(Counter::IntersectionTests, 1);
+        let (r, o_error, d_error) = self.transform.inverse().ray_with_error(ray);
+        match self.shape.intersect(&r, &o_error, &d_error, t_min, t_max) {
+            Some(h) => {
+                *rec = self.record(&h);
+                true
+            }
+            None => false,
+        }
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.transform.bounds(&self.shape.bounds()))
+    }
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span>> {
+        let (r, o_error, d_error) = self.transform.inverse().ray_with_error(ray);
+        let spans = self.shape.spans(&r, &o_error, &d_error)?;
+        Some(
+            spans
+                .iter()
+                .map(|(enter, exit)| Span {
+                    enter: self.record(enter),
+                    exit: self.record(exit),
+                })
+                .collect(),
+        )
+    }
+}
+impl<S: Shape> ShapeObject<S> {
+    fn record(&self, h: &SurfaceHit) -> HitRecord {
+        let (p, p_error) = self.transform.point_with_error(&h.p, &h.p_error);
+        let normal = normalize(self.transform.normal(&h.normal));
+        HitRecord {
+            t: h.t,
+            p,
+            p_error,
+            normal,
+            shading_normal: normal,
+            u: h.u,
+            v: h.v,
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
+            mat: self.mat.clone(),
+            outer_ior: 1.0,
+        }
+    }
+}
+
+// Angle of (x, y) around the z axis, in [0, 2pi).
+fn phi(x: Float, y: Float) -> Float {
+    let phi = y.atan2(x);
+    if phi < 0.0 {
+        phi + 2.0 * consts::PI
+    } else {
+        phi
+    }
+}
+
+fn phi_max(degrees: Float) -> Float {
+    degrees.clamp(0.0, 360.0).to_radians()
+}
+
+fn efloat_ray(ray: &Ray, o_error: &Vec3, d_error: &Vec3) -> ([EFloat; 3], [EFloat; 3]) {
+    (
+        [0, 1, 2].map(|i| EFloat::new(ray.o[i], o_error[i])),
+        [0, 1, 2].map(|i| EFloat::new(ray.d[i], d_error[i])),
+    )
+}
+
+// Error bound of the point o + t d, for hits that are not reprojected.
+fn point_error(o: &[EFloat; 3], d: &[EFloat; 3], t: EFloat) -> Vec3 {
+    let e = [0, 1, 2].map(|i| (o[i] + t * d[i]).absolute_error());
+    Vec3::new(e[0], e[1], e[2])
+}
+
+// Tries the roots of a quadric in order, keeping those certainly inside
+// (t_min, t_max), and returns the first hit accepted by `surface`.
+fn first_root<F>(
+    roots: Option<(EFloat, EFloat)>,
+    t_min: Float,
+    t_max: Float,
+    surface: F,
+) -> Option<SurfaceHit>
+where
+    F: Fn(EFloat) -> Option<SurfaceHit>,
+{
+    let (t0, t1) = roots?;
+    if t0.upper_bound() >= t_max || t1.lower_bound() <= t_min {
+        return None;
+    }
+    if t0.lower_bound() > t_min {
+        if let Some(h) = surface(t0) {
+            return Some(h);
+        }
+    }
+    if t1.upper_bound() < t_max {
+        return surface(t1);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::assert_no_self_intersection;
+    use crate::core::shapes::*;
+    use crate::geometry::{dot, random_in_unit_sphere, seed_random};
+    // Shoots rays from around the shape and checks the hits: on the surface
+    // according to `f`, with normals along its gradient, uvs in [0, 1], inside
+    // the bounds, and no self intersections of rays leaving them.
+    fn check<S: Shape>(shape: S, f: impl Fn(&Vec3) -> Float, grad: impl Fn(&Vec3) -> Vec3) {
+        seed_random(3);
+        let transform = Transform::translate(Vec3::new(1.0, -2.0, 3.0))
+            * Transform::rotate(30.0, Vec3::new(1.0, 1.0, 0.0));
+        let object = ShapeObject::new(shape, None).with_transform(transform);
+        let bounds = object.bounding_box().unwrap();
+        let center = bounds.centroid();
+        let size = (bounds.max - bounds.min).length();
+        let mut hits = 0;
+        for _ in 0..5000 {
+            let o = center + 2.0 * size * normalize(random_in_unit_sphere());
+            let target = center + 0.5 * size * random_in_unit_sphere();
+            let r = Ray::new(o, target - o);
+            let mut rec = HitRecord::new();
+            if !object.hit(&r, 0.0, Float::INFINITY, &mut rec) {
+                continue;
+            }
+            hits += 1;
+            let p = transform.inverse().point(&rec.p);
+            assert!(f(&p).abs() < 1e-3, "{:?} {}", p, f(&p));
+            let n = normalize(transform.inverse().normal(&rec.normal));
+            assert!(
+                dot(&n, &normalize(grad(&p))) > 0.999,
+                "{:?} {:?}",
+                n,
+                grad(&p)
+            );
+            assert!((0.0..=1.0).contains(&rec.u) && (0.0..=1.0).contains(&rec.v));
+            let grown = Vec3::new(1e-3, 1e-3, 1e-3);
+            assert!(Aabb::new(bounds.min - grown, bounds.max + grown).contains(&rec.p));
+            assert_no_self_intersection(&object, &rec, 0.5, 1e-3 * size);
+        }
+        assert!(hits > 200, "{} hits", hits);
+    }
+    #[test]
+    fn cylinder() {
+        check(
+            Cylinder::new(1.0, -1.0, 2.0).with_phi_max(270.0),
+            |p| p.x * p.x + p.y * p.y - 1.0,
+            |p| Vec3::new(p.x, p.y, 0.0),
+        );
+    }
+    #[test]
+    fn cone() {
+        check(
+            Cone::new(1.0, 2.0).with_phi_max(300.0),
+            |p| p.x * p.x + p.y * p.y - (1.0 - p.z / 2.0).powi(2),
+            |p| Vec3::new(p.x, p.y, (1.0 - p.z / 2.0) / 2.0),
+        );
+    }
+    #[test]
+    fn disk() {
+        check(
+            Disk::new(0.5, 1.0)
+                .with_inner_radius(0.3)
+                .with_phi_max(200.0),
+            |p| p.z - 0.5,
+            |_| Vec3::new(0.0, 0.0, 1.0),
+        );
+    }
+    #[test]
+    fn paraboloid() {
+        check(
+            Paraboloid::new(1.0, 0.2, 2.0).with_phi_max(270.0),
+            |p| p.x * p.x + p.y * p.y - p.z / 2.0,
+            |p| Vec3::new(p.x, p.y, -0.25),
+        );
+    }
+    #[test]
+    fn hyperboloid() {
+        let h = Hyperboloid::new(Vec3::new(1.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 1.0));
+        let (ah, ch) = h.coefficients();
+        check(
+            h.with_phi_max(320.0),
+            move |p| ah * (p.x * p.x + p.y * p.y) - ch * p.z * p.z - 1.0,
+            move |p| Vec3::new(ah * p.x, ah * p.y, -ch * p.z),
+        );
+    }
+    #[test]
+    fn torus() {
+        let f = |p: &Vec3| {
+            let rho = (p.x * p.x + p.y * p.y).sqrt();
+            ((rho - 1.0).powi(2) + p.z * p.z).sqrt() - 0.25
+        };
+        let grad = |p: &Vec3| {
+            let rho = (p.x * p.x + p.y * p.y).sqrt();
+            *p - Vec3::new(p.x / rho, p.y / rho, 0.0)
+        };
+        check(Torus::new(1.0, 0.25).with_phi_max(300.0), f, grad);
+        // thin and far away tori need a robust quartic solver
+        let thin = ShapeObject::new(Torus::new(1.0, 0.01), None);
+        let r = Ray::new(Vec3::new(-1e3, 0.0, 0.005), Vec3::new(1.0, 0.0, 0.0));
+        let mut rec = HitRecord::new();
+        assert!(thin.hit(&r, 0.0, Float::INFINITY, &mut rec));
+        assert!((rec.p.x + 1.0 + (0.01 as Float * 0.01 - 0.005 * 0.005).sqrt()).abs() < 1e-4);
+    }
+}
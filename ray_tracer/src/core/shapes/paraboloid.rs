@@ -0,0 +1,64 @@
+use crate::core::shapes::{efloat_ray, first_root, phi, phi_max, point_error, Shape, SurfaceHit};
+use crate::geometry::{quadratic, Aabb, EFloat, Float, Ray, Vec3};
+
+// Paraboloid x^2 + y^2 = radius^2 z / z_max, cut between z_min and z_max.
+pub struct Paraboloid {
+    radius: Float,
+    z_min: Float,
+    z_max: Float,
+    phi_max: Float,
+}
+impl Paraboloid {
+    pub fn new(radius: Float, z_min: Float, z_max: Float) -> Self {
+        Self {
+            radius,
+            z_min: z_min.min(z_max).max(0.0),
+            z_max: z_min.max(z_max),
+            phi_max: phi_max(360.0),
+        }
+    }
+    // Sweep angle in degrees.
+    pub fn with_phi_max(mut self, degrees: Float) -> Self {
+        self.phi_max = phi_max(degrees);
+        self
+    }
+}
+impl Shape for Paraboloid {
+    fn intersect(
+        &self,
+        ray: &Ray,
+        o_error: &Vec3,
+        d_error: &Vec3,
+        t_min: Float,
+        t_max: Float,
+    ) -> Option<SurfaceHit> {
+        let (o, d) = efloat_ray(ray, o_error, d_error);
+        let r = EFloat::from(self.radius);
+        let k = EFloat::from(self.z_max) / (r * r);
+        let a = k * (d[0] * d[0] + d[1] * d[1]);
+        let b = EFloat::from(2.0) * k * (d[0] * o[0] + d[1] * o[1]) - d[2];
+        let c = k * (o[0] * o[0] + o[1] * o[1]) - o[2];
+        let k = k.v;
+        first_root(quadratic(a, b, c), t_min, t_max, |t| {
+            let p = ray.point_at_parameter(t.v);
+            let phi = phi(p.x, p.y);
+            if p.z < self.z_min || p.z > self.z_max || phi > self.phi_max {
+                return None;
+            }
+            Some(SurfaceHit {
+                t: t.v,
+                p,
+                p_error: point_error(&o, &d, t),
+                normal: Vec3::new(2.0 * k * p.x, 2.0 * k * p.y, -1.0),
+                u: phi / self.phi_max,
+                v: (p.z - self.z_min) / (self.z_max - self.z_min),
+            })
+        })
+    }
+    fn bounds(&self) -> Aabb {
+        Aabb::new(
+            Vec3::new(-self.radius, -self.radius, self.z_min),
+            Vec3::new(self.radius, self.radius, self.z_max),
+        )
+    }
+}
@@ -0,0 +1,105 @@
+use crate::core::shapes::{efloat_ray, phi, phi_max, Shape, SurfaceHit};
+use crate::geometry::{consts, gamma, solve_quartic, Aabb, EFloat, Float, Ray, Vec3};
+
+// Torus around the z axis: a tube of radius `minor` whose center is a circle
+// of radius `major` in the plane z = 0.
+pub struct Torus {
+    major: Float,
+    minor: Float,
+    phi_max: Float,
+}
+impl Torus {
+    pub fn new(major: Float, minor: Float) -> Self {
+        Self {
+            major,
+            minor,
+            phi_max: phi_max(360.0),
+        }
+    }
+    // Sweep angle in degrees.
+    pub fn with_phi_max(mut self, degrees: Float) -> Self {
+        self.phi_max = phi_max(degrees);
+        self
+    }
+    // Distances along the unit direction d from o to the surface, solving
+    // (|p|^2 - R^2 - r^2)^2 = 4 R^2 (r^2 - z^2) in double precision.
+    #[allow(clippy::unnecessary_cast)]
+    fn distances(&self, o: [f64; 3], d: [f64; 3]) -> Vec<f64> {
+        // starts from the point of the line closest to the center, which
+        // keeps the coefficients small for far away rays
+        let s0 = -(o[0] * d[0] + o[1] * d[1] + o[2] * d[2]);
+        let o = [0, 1, 2].map(|i| o[i] + s0 * d[i]);
+        let (big, small) = (self.major as f64, self.minor as f64);
+        let e = o[0] * o[0] + o[1] * o[1] + o[2] * o[2] - big * big - small * small;
+        let f = o[0] * d[0] + o[1] * d[1] + o[2] * d[2];
+        let four_r2 = 4.0 * big * big;
+        solve_quartic([
+            1.0,
+            4.0 * f,
+            2.0 * e + 4.0 * f * f + four_r2 * d[2] * d[2],
+            4.0 * f * e + 2.0 * four_r2 * o[2] * d[2],
+            e * e - four_r2 * (small * small - o[2] * o[2]),
+        ])
+        .into_iter()
+        .map(|s| s + s0)
+        .collect()
+    }
+}
+// the casts are no-ops in f64 builds
+#[allow(clippy::unnecessary_cast)]
+impl Shape for Torus {
+    fn intersect(
+        &self,
+        ray: &Ray,
+        o_error: &Vec3,
+        d_error: &Vec3,
+        t_min: Float,
+        t_max: Float,
+    ) -> Option<SurfaceHit> {
+        let length = ray.d.length() as f64;
+        let o = [0, 1, 2].map(|i| ray.o[i] as f64);
+        let d = [0, 1, 2].map(|i| ray.d[i] as f64 / length);
+        let (oe, de) = efloat_ray(ray, o_error, d_error);
+        for s in self.distances(o, d) {
+            let t = (s / length) as Float;
+            if t <= t_min || t >= t_max {
+                continue;
+            }
+            // the root in double precision is exact up to rounding it to t
+            let t = EFloat::new(t, gamma(1) * t.abs());
+            let p = [0, 1, 2].map(|i| oe[i] + t * de[i]);
+            let phi = phi(p[0].v, p[1].v);
+            if phi > self.phi_max {
+                continue;
+            }
+            // reprojects onto the tube around the nearest point of the circle
+            let rho = (p[0] * p[0] + p[1] * p[1]).sqrt();
+            let major = EFloat::from(self.major);
+            let c = [major * p[0] / rho, major * p[1] / rho, EFloat::from(0.0)];
+            let q = [0, 1, 2].map(|i| p[i] - c[i]);
+            let scale = EFloat::from(self.minor) / (q[0] * q[0] + q[1] * q[1] + q[2] * q[2]).sqrt();
+            let p = [0, 1, 2].map(|i| c[i] + q[i] * scale);
+            let mut theta = p[2].v.atan2(rho.v - self.major);
+            if theta < 0.0 {
+                theta += 2.0 * consts::PI;
+            }
+            return Some(SurfaceHit {
+                t: t.v,
+                p: Vec3::new(p[0].v, p[1].v, p[2].v),
+                p_error: Vec3::new(
+                    p[0].absolute_error(),
+                    p[1].absolute_error(),
+                    p[2].absolute_error(),
+                ),
+                normal: Vec3::new(q[0].v, q[1].v, q[2].v),
+                u: phi / self.phi_max,
+                v: theta / (2.0 * consts::PI),
+            });
+        }
+        None
+    }
+    fn bounds(&self) -> Aabb {
+        let r = self.major + self.minor;
+        Aabb::new(Vec3::new(-r, -r, -self.minor), Vec3::new(r, r, self.minor))
+    }
+}
@@ -0,0 +1,271 @@
+use crate::geometry::{Float, Vec3};
+use std::ops::{Add, AddAssign, Div, Mul};
+use std::sync::OnceLock;
+
+pub const LAMBDA_MIN: Float = 380.0;
+pub const LAMBDA_MAX: Float = 720.0;
+pub const N_SPECTRUM_SAMPLES: usize = 4;
+
+// Hero wavelength sampling (Wilkie et al. 2014): one uniformly sampled hero
+// wavelength plus N - 1 equally spaced rotations of it over the visible range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampledWavelengths {
+    pub lambda: [Float; N_SPECTRUM_SAMPLES],
+    pub pdf: [Float; N_SPECTRUM_SAMPLES],
+}
+impl SampledWavelengths {
+    pub fn sample_uniform(u: Float) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let delta = range / N_SPECTRUM_SAMPLES as Float;
+        let mut lambda = [0.0; N_SPECTRUM_SAMPLES];
+        lambda[0] = LAMBDA_MIN + u * range;
+        for i in 1..N_SPECTRUM_SAMPLES {
+            lambda[i] = lambda[i - 1] + delta;
+            if lambda[i] > LAMBDA_MAX {
+                lambda[i] -= range;
+            }
+        }
+        Self {
+            lambda,
+            pdf: [1.0 / range; N_SPECTRUM_SAMPLES],
+        }
+    }
+    pub fn hero(&self) -> Float {
+        self.lambda[0]
+    }
+    // Called when a wavelength dependent event (e.g. dispersion) makes the
+    // path only valid for the hero wavelength.
+    pub fn terminate_secondary(&mut self) {
+        if self.secondary_terminated() {
+            return;
+        }
+        for i in 1..N_SPECTRUM_SAMPLES {
+            self.pdf[i] = 0.0;
+        }
+        self.pdf[0] /= N_SPECTRUM_SAMPLES as Float;
+    }
+    pub fn secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|p| *p == 0.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampledSpectrum {
+    pub values: [Float; N_SPECTRUM_SAMPLES],
+}
+impl SampledSpectrum {
+    pub fn new(v: Float) -> Self {
+        Self {
+            values: [v; N_SPECTRUM_SAMPLES],
+        }
+    }
+    pub fn from_rgb(rgb: &Vec3, wl: &SampledWavelengths) -> Self {
+        let mut values = [0.0; N_SPECTRUM_SAMPLES];
+        for (v, lambda) in values.iter_mut().zip(wl.lambda.iter()) {
+            *v = rgb_to_spectrum(rgb, *lambda);
+        }
+        Self { values }
+    }
+    pub fn exp(&self) -> Self {
+        let mut values = self.values;
+        for v in values.iter_mut() {
+            *v = v.exp();
+        }
+        Self { values }
+    }
+    pub fn to_rgb(self, wl: &SampledWavelengths) -> Vec3 {
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..N_SPECTRUM_SAMPLES {
+            if wl.pdf[i] > 0.0 {
+                xyz += cie_xyz(wl.lambda[i]) * (self.values[i] / wl.pdf[i]);
+            }
+        }
+        xyz /= N_SPECTRUM_SAMPLES as Float;
+        let white = white_rgb();
+        let rgb = xyz_to_linear_srgb(&xyz);
+        Vec3::new(rgb.x / white.x, rgb.y / white.y, rgb.z / white.z)
+    }
+}
+impl Add for SampledSpectrum {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        let mut values = self.values;
+        for (v, o) in values.iter_mut().zip(other.values.iter()) {
+            *v += o;
+        }
+        Self { values }
+    }
+}
+impl AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+impl Mul for SampledSpectrum {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        let mut values = self.values;
+        for (v, o) in values.iter_mut().zip(other.values.iter()) {
+            *v *= o;
+        }
+        Self { values }
+    }
+}
+impl Mul<Float> for SampledSpectrum {
+    type Output = Self;
+    fn mul(self, f: Float) -> Self {
+        let mut values = self.values;
+        for v in values.iter_mut() {
+            *v *= f;
+        }
+        Self { values }
+    }
+}
+impl Div<Float> for SampledSpectrum {
+    type Output = Self;
+    fn div(self, f: Float) -> Self {
+        if f == 0.0 {
+            panic!("Cannot divide spectrum by zero!");
+        }
+        self * (1.0 / f)
+    }
+}
+
+// Smits 1999 basis spectra, 10 bins spanning [LAMBDA_MIN, LAMBDA_MAX].
+const SMITS_BINS: usize = 10;
+const SMITS_WHITE: [Float; SMITS_BINS] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [Float; SMITS_BINS] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [Float; SMITS_BINS] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [Float; SMITS_BINS] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [Float; SMITS_BINS] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [Float; SMITS_BINS] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [Float; SMITS_BINS] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+fn smits_basis(basis: &[Float; SMITS_BINS], lambda: Float) -> Float {
+    // linear interpolation between bin centers
+    let width = (LAMBDA_MAX - LAMBDA_MIN) / SMITS_BINS as Float;
+    let x = ((lambda - LAMBDA_MIN) / width - 0.5).max(0.0);
+    let i = (x as usize).min(SMITS_BINS - 1);
+    let j = (i + 1).min(SMITS_BINS - 1);
+    let t = (x - i as Float).min(1.0);
+    (1.0 - t) * basis[i] + t * basis[j]
+}
+
+pub fn rgb_to_spectrum(rgb: &Vec3, lambda: Float) -> Float {
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+    let s = |basis: &[Float; SMITS_BINS]| smits_basis(basis, lambda);
+    let value = if r <= g && r <= b {
+        if g <= b {
+            r * s(&SMITS_WHITE) + (g - r) * s(&SMITS_CYAN) + (b - g) * s(&SMITS_BLUE)
+        } else {
+            r * s(&SMITS_WHITE) + (b - r) * s(&SMITS_CYAN) + (g - b) * s(&SMITS_GREEN)
+        }
+    } else if g <= r && g <= b {
+        if r <= b {
+            g * s(&SMITS_WHITE) + (r - g) * s(&SMITS_MAGENTA) + (b - r) * s(&SMITS_BLUE)
+        } else {
+            g * s(&SMITS_WHITE) + (b - g) * s(&SMITS_MAGENTA) + (r - b) * s(&SMITS_RED)
+        }
+    } else if r <= g {
+        b * s(&SMITS_WHITE) + (r - b) * s(&SMITS_YELLOW) + (g - r) * s(&SMITS_GREEN)
+    } else {
+        b * s(&SMITS_WHITE) + (g - b) * s(&SMITS_YELLOW) + (r - g) * s(&SMITS_RED)
+    };
+    value.max(0.0)
+}
+
+// Multi-lobe fit of the CIE 1931 2-degree color matching functions
+// (Wyman, Sloan and Shirley 2013).
+pub fn cie_xyz(lambda: Float) -> Vec3 {
+    let g = |mu: Float, s1: Float, s2: Float| {
+        let t = (lambda - mu) / if lambda < mu { s1 } else { s2 };
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+pub fn xyz_to_linear_srgb(xyz: &Vec3) -> Vec3 {
+    Vec3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+// RGB of the constant unit spectrum. Dividing by it white balances the
+// conversion so that RGB (1,1,1) round trips to (1,1,1).
+fn white_rgb() -> Vec3 {
+    static WHITE: OnceLock<Vec3> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let steps = 1000;
+        let dl = (LAMBDA_MAX - LAMBDA_MIN) / steps as Float;
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..steps {
+            xyz += cie_xyz(LAMBDA_MIN + (i as Float + 0.5) * dl) * dl;
+        }
+        xyz_to_linear_srgb(&xyz)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::spectrum::*;
+    #[test]
+    fn white_round_trip() {
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        let n = 256;
+        for i in 0..n {
+            let wl = SampledWavelengths::sample_uniform((i as Float + 0.5) / n as Float);
+            sum += SampledSpectrum::from_rgb(&white, &wl).to_rgb(&wl);
+        }
+        sum /= n as Float;
+        for c in 0..3 {
+            assert!((sum[c] - 1.0).abs() < 0.02, "{:?}", sum);
+        }
+    }
+    #[test]
+    fn red_stays_red() {
+        let red = Vec3::new(1.0, 0.0, 0.0);
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        let n = 256;
+        for i in 0..n {
+            let wl = SampledWavelengths::sample_uniform((i as Float + 0.5) / n as Float);
+            sum += SampledSpectrum::from_rgb(&red, &wl).to_rgb(&wl);
+        }
+        sum /= n as Float;
+        assert!(
+            sum.x > 0.5 && sum.x > 4.0 * sum.y && sum.x > 4.0 * sum.z,
+            "{:?}",
+            sum
+        );
+    }
+    #[test]
+    fn terminate_secondary() {
+        let mut wl = SampledWavelengths::sample_uniform(0.3);
+        let pdf = wl.pdf[0];
+        wl.terminate_secondary();
+        wl.terminate_secondary();
+        assert!(wl.secondary_terminated());
+        assert_eq!(wl.pdf[0], pdf / N_SPECTRUM_SAMPLES as Float);
+        assert_eq!(&wl.pdf[1..], &[0.0; N_SPECTRUM_SAMPLES - 1]);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::core::stats::{count, Counter};
+use crate::core::{HitRecord, Hitable, SphereObject};
+use crate::geometry::{dot, Aabb, Float, Ray};
+use wide::{CmpGe, CmpGt, CmpLt};
+
+#[cfg(not(feature = "f64"))]
+type FloatN = wide::f32x8;
+#[cfg(feature = "f64")]
+type FloatN = wide::f64x4;
+const LANES: usize = std::mem::size_of::<FloatN>() / std::mem::size_of::<Float>();
+
+// Spheres stored as structure of arrays, so that a ray is tested against
+// a group of them at once with SIMD. The SIMD test only selects candidates,
+// which are confirmed by the exact test of SphereObject. Unused lanes of the last group hold NaN
+// centers, which never produce a hit.
+pub struct SphereSoA {
+    cx: Vec<FloatN>,
+    cy: Vec<FloatN>,
+    cz: Vec<FloatN>,
+    radius2: Vec<FloatN>,
+    spheres: Vec<SphereObject>,
+}
+
+impl SphereSoA {
+    pub fn new(spheres: &[SphereObject]) -> Self {
+        let groups = spheres.len().div_ceil(LANES);
+        let lane = |g: usize, f: &dyn Fn(&SphereObject) -> Float| {
+            let mut v = [Float::NAN; LANES];
+            for (i, s) in spheres[g * LANES..].iter().take(LANES).enumerate() {
+                v[i] = f(s);
+            }
+            FloatN::from(v)
+        };
+        Self {
+            cx: (0..groups).map(|g| lane(g, &|s| s.center.x)).collect(),
+            cy: (0..groups).map(|g| lane(g, &|s| s.center.y)).collect(),
+            cz: (0..groups).map(|g| lane(g, &|s| s.center.z)).collect(),
+            radius2: (0..groups)
+                .map(|g| lane(g, &|s| s.radius * s.radius))
+                .collect(),
+            spheres: spheres.to_vec(),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.spheres.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.spheres.is_empty()
+    }
+}
+
+impl Hitable for SphereSoA {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, rec: &mut HitRecord) -> bool {
+        count(Counter::IntersectionTests, self.spheres.len() as u64);
+        let (ox, oy, oz) = (
+            FloatN::splat(ray.o.x),
+            FloatN::splat(ray.o.y),
+            FloatN::splat(ray.o.z),
+        );
+        let (dx, dy, dz) = (
+            FloatN::splat(ray.d.x),
+            FloatN::splat(ray.d.y),
+            FloatN::splat(ray.d.z),
+        );
+        let a = FloatN::splat(dot(&ray.d, &ray.d));
+        let t_min8 = FloatN::splat(t_min);
+        let e = FloatN::splat(64.0 * Float::EPSILON);
+        let mut t_max = t_max;
+        let mut hit = false;
+        for g in 0..self.cx.len() {
+            let (ocx, ocy, ocz) = (ox - self.cx[g], oy - self.cy[g], oz - self.cz[g]);
+            let b = ocx * dx + ocy * dy + ocz * dz;
+            let oc2 = ocx * ocx + ocy * ocy + ocz * ocz;
+            let discriminant = b * b - a * (oc2 - self.radius2[g]);
+            // loose bounds, so that every sphere the exact test would accept
+            // is a candidate
+            let tol = e * (b * b + a * (oc2 + self.radius2[g]));
+            let valid = (discriminant + tol).cmp_ge(FloatN::splat(0.0));
+            let root = (discriminant + tol).max(FloatN::splat(0.0)).sqrt();
+            let err = e * (b.abs() + root) / a;
+            let t0 = (-b - root) / a - err;
+            let t1 = (-b + root) / a + err;
+            let candidates =
+                (valid & t1.cmp_gt(t_min8) & t0.cmp_lt(FloatN::splat(t_max))).move_mask();
+            for i in 0..LANES {
+                if candidates & (1 << i) != 0
+                    && self.spheres[g * LANES + i].intersect(ray, t_min, t_max, rec)
+                {
+                    t_max = rec.t;
+                    hit = true;
+                }
+            }
+        }
+        hit
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut boxes = self.spheres.iter().filter_map(|s| s.bounding_box());
+        let first = boxes.next()?;
+        Some(boxes.fold(first, |acc, b| acc.union(&b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{random_spheres, HitList, HitRecord, Hitable, SphereSoA};
+    use crate::geometry::{random, random_in_unit_sphere, seed_random, Float, Ray, Vec3};
+    #[test]
+    fn matches_scalar_hit_list() {
+        seed_random(1);
+        let spheres = random_spheres();
+        let soa = SphereSoA::new(&spheres);
+        let mut list = HitList::new();
+        for s in spheres.iter() {
+            list.list.push(Box::new(s.clone()));
+        }
+        for _ in 0..2000 {
+            let o = Vec3::new(
+                24.0 * random() - 12.0,
+                3.0 * random(),
+                24.0 * random() - 12.0,
+            );
+            let r = Ray::new(o, random_in_unit_sphere());
+            let mut a = HitRecord::new();
+            let mut b = HitRecord::new();
+            let hit = list.hit(&r, 0.0, Float::INFINITY, &mut a);
+            assert_eq!(hit, soa.hit(&r, 0.0, Float::INFINITY, &mut b));
+            if hit {
+                assert_eq!((a.t, a.p, a.normal), (b.t, b.p, b.normal));
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::cell::Cell;
+use std::fmt;
+use std::ops::AddAssign;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    CameraRays,
+    SecondaryRays,
+    ShadowRays,
+    IntersectionTests,
+    BvhNodeVisits,
+    // surface interactions along paths, for the average path length
+    PathVertices,
+}
+const N_COUNTERS: usize = 6;
+
+thread_local! {
+    static COUNTERS: Cell<[u64; N_COUNTERS]> = const { Cell::new([0; N_COUNTERS]) };
+}
+
+// Increments one of the calling thread's counters.
+pub fn count(counter: Counter, n: u64) {
+    COUNTERS.with(|c| {
+        let mut values = c.get();
+        values[counter as usize] += n;
+        c.set(values);
+    });
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    pub counters: [u64; N_COUNTERS],
+    pub time: Duration,
+}
+impl Stats {
+    // Returns and resets the calling thread's counters.
+    pub fn take() -> Self {
+        Self {
+            counters: COUNTERS.with(|c| c.replace([0; N_COUNTERS])),
+            time: Duration::default(),
+        }
+    }
+    // The calling thread's counters so far.
+    pub fn peek() -> Self {
+        Self {
+            counters: COUNTERS.with(|c| c.get()),
+            time: Duration::default(),
+        }
+    }
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize]
+    }
+    pub fn rays(&self) -> u64 {
+        self.get(Counter::CameraRays)
+            + self.get(Counter::SecondaryRays)
+            + self.get(Counter::ShadowRays)
+    }
+    // Traversal and intersection work, a machine independent render cost.
+    pub fn work(&self) -> u64 {
+        self.get(Counter::IntersectionTests) + self.get(Counter::BvhNodeVisits)
+    }
+    pub fn average_path_length(&self) -> f32 {
+        match self.get(Counter::CameraRays) {
+            0 => 0.0,
+            n => self.get(Counter::PathVertices) as f32 / n as f32,
+        }
+    }
+    pub fn rays_per_second(&self) -> f64 {
+        match self.time.as_secs_f64() {
+            t if t > 0.0 => self.rays() as f64 / t,
+            _ => 0.0,
+        }
+    }
+}
+impl AddAssign for Stats {
+    fn add_assign(&mut self, other: Self) {
+        for i in 0..N_COUNTERS {
+            self.counters[i] += other.counters[i];
+        }
+        self.time += other.time;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PassStats {
+    pub threads: Vec<Stats>,
+    pub time: Duration,
+}
+impl PassStats {
+    // Counters summed over threads, timed by the wall clock.
+    pub fn total(&self) -> Stats {
+        let mut total = Stats::default();
+        for t in self.threads.iter() {
+            total += *t;
+        }
+        total.time = self.time;
+        total
+    }
+}
+
+// Per pass and per thread table followed by the totals.
+pub struct Report<'a>(pub &'a [PassStats]);
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>5} {:>6} {:>9} {:>12} {:>12} {:>12} {:>14} {:>12} {:>9} {:>10}",
+            "pass",
+            "thread",
+            "time(s)",
+            "camera",
+            "secondary",
+            "shadow",
+            "isect tests",
+            "bvh visits",
+            "avg path",
+            "Mrays/s"
+        )?;
+        let row = |f: &mut fmt::Formatter, pass: &str, thread: &str, s: &Stats| {
+            writeln!(
+                f,
+                "{:>5} {:>6} {:>9.3} {:>12} {:>12} {:>12} {:>14} {:>12} {:>9.2} {:>10.3}",
+                pass,
+                thread,
+                s.time.as_secs_f64(),
+                s.get(Counter::CameraRays),
+                s.get(Counter::SecondaryRays),
+                s.get(Counter::ShadowRays),
+                s.get(Counter::IntersectionTests),
+                s.get(Counter::BvhNodeVisits),
+                s.average_path_length(),
+                s.rays_per_second() / 1e6
+            )
+        };
+        let mut total = Stats::default();
+        for (i, pass) in self.0.iter().enumerate() {
+            for (j, t) in pass.threads.iter().enumerate() {
+                row(f, &(i + 1).to_string(), &j.to_string(), t)?;
+            }
+            row(f, &(i + 1).to_string(), "all", &pass.total())?;
+            total += pass.total();
+        }
+        row(f, "total", "all", &total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::stats::*;
+    #[test]
+    fn counters_are_per_thread() {
+        Stats::take();
+        count(Counter::CameraRays, 2);
+        count(Counter::PathVertices, 5);
+        std::thread::spawn(|| count(Counter::CameraRays, 10))
+            .join()
+            .unwrap();
+        let s = Stats::take();
+        assert_eq!(s.get(Counter::CameraRays), 2);
+        assert_eq!(s.average_path_length(), 2.5);
+        assert_eq!(Stats::take(), Stats::default());
+    }
+}
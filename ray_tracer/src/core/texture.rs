@@ -0,0 +1,64 @@
+use crate::core::Image;
+use crate::geometry::{Float, Vec3};
+use std::sync::Arc;
+
+// Values over the (u, v) coordinates of a surface.
+#[derive(Clone)]
+pub enum Texture {
+    Constant(Vec3),
+    // repeated outside [0, 1], bilinearly filtered, v = 0 at the bottom row
+    Image(Arc<Image>),
+    Custom(Arc<dyn Fn(Float, Float) -> Vec3 + Send + Sync>),
+}
+impl Texture {
+    pub fn from_fn<F>(f: F) -> Self
+    where
+        F: Fn(Float, Float) -> Vec3 + Send + Sync + 'static,
+    {
+        Texture::Custom(Arc::new(f))
+    }
+    pub fn value(&self, u: Float, v: Float) -> Vec3 {
+        match self {
+            Texture::Constant(c) => *c,
+            Texture::Image(image) => bilinear(image, u, v),
+            Texture::Custom(f) => f(u, v),
+        }
+    }
+    // Mean of the channels, for height maps.
+    pub fn scalar(&self, u: Float, v: Float) -> Float {
+        let c = self.value(u, v);
+        (c.x + c.y + c.z) / 3.0
+    }
+}
+
+fn bilinear(image: &Image, u: Float, v: Float) -> Vec3 {
+    let (w, h) = (image.width as isize, image.height as isize);
+    let x = u * w as Float - 0.5;
+    let y = (1.0 - v) * h as Float - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let texel = |i: isize, j: isize| image.get(i.rem_euclid(w) as usize, j.rem_euclid(h) as usize);
+    let (i, j) = (x0 as isize, y0 as isize);
+    (1.0 - fy) * ((1.0 - fx) * texel(i, j) + fx * texel(i + 1, j))
+        + fy * ((1.0 - fx) * texel(i, j + 1) + fx * texel(i + 1, j + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{Image, Texture};
+    use crate::geometry::Vec3;
+    use std::sync::Arc;
+    #[test]
+    fn image_lookup() {
+        let mut image = Image::new(2, 2);
+        image.set(0, 1, Vec3::new(1.0, 1.0, 1.0));
+        let t = Texture::Image(Arc::new(image));
+        // texel centers, with the bottom row at v = 0
+        assert_eq!(t.scalar(0.25, 0.25), 1.0);
+        assert_eq!(t.scalar(0.75, 0.25), 0.0);
+        assert_eq!(t.scalar(0.25, 0.75), 0.0);
+        // halfway, and wrapping around
+        assert_eq!(t.scalar(0.5, 0.25), 0.5);
+        assert_eq!(t.scalar(1.25, -0.75), 1.0);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::geometry::{gamma, Float, Ray, Vec3};
+
+// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+impl Aabb {
+    pub fn new(a: Vec3, b: Vec3) -> Self {
+        Self {
+            min: Vec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+            max: Vec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+        }
+    }
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::new(
+            Vec3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            Vec3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        )
+    }
+    pub fn corner(&self, i: usize) -> Vec3 {
+        Vec3::new(
+            if i & 1 == 0 { self.min.x } else { self.max.x },
+            if i & 2 == 0 { self.min.y } else { self.max.y },
+            if i & 4 == 0 { self.min.z } else { self.max.z },
+        )
+    }
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+    pub fn surface_area(&self) -> Float {
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+    pub fn contains(&self, p: &Vec3) -> bool {
+        (0..3).all(|i| p[i] >= self.min[i] && p[i] <= self.max[i])
+    }
+    pub fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        self.intersect(ray, t_min, t_max).is_some()
+    }
+    // Slab test, with the far distances enlarged so that rounding can not
+    // miss boxes the ray grazes. Returns the part of (t_min, t_max) inside.
+    pub fn intersect(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<(Float, Float)> {
+        let (mut t0, mut t1) = (t_min, t_max);
+        for i in 0..3 {
+            let inv = 1.0 / ray.d[i];
+            let mut near = (self.min[i] - ray.o[i]) * inv;
+            let mut far = (self.max[i] - ray.o[i]) * inv;
+            if near > far {
+                std::mem::swap(&mut near, &mut far);
+            }
+            far *= 1.0 + 2.0 * gamma(3);
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
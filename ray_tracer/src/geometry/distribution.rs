@@ -0,0 +1,123 @@
+use crate::geometry::Float;
+// Piecewise constant distributions over [0,1) and [0,1)^2, sampled by
+// inverting their CDFs.
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    pub func: Vec<Float>,
+    cdf: Vec<Float>,
+    pub integral: Float,
+}
+impl Distribution1D {
+    pub fn new(func: &[Float]) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].abs() / n as Float;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as Float / n as Float
+            };
+        }
+        Self {
+            func: func.iter().map(|f| f.abs()).collect(),
+            cdf,
+            integral,
+        }
+    }
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+    // Returns the sampled point, its pdf and the index of its segment.
+    pub fn sample_continuous(&self, u: Float) -> (Float, Float, usize) {
+        let i = self
+            .cdf
+            .partition_point(|c| *c <= u)
+            .saturating_sub(1)
+            .min(self.count() - 1);
+        let mut du = u - self.cdf[i];
+        let width = self.cdf[i + 1] - self.cdf[i];
+        if width > 0.0 {
+            du /= width;
+        }
+        let pdf = if self.integral > 0.0 {
+            self.func[i] / self.integral
+        } else {
+            1.0
+        };
+        ((i as Float + du) / self.count() as Float, pdf, i)
+    }
+    // Returns the sampled segment index and its probability.
+    pub fn sample_discrete(&self, u: Float) -> (usize, Float) {
+        let (_, _, i) = self.sample_continuous(u);
+        (i, self.discrete_pdf(i))
+    }
+    pub fn discrete_pdf(&self, i: usize) -> Float {
+        self.cdf[i + 1] - self.cdf[i]
+    }
+}
+
+// Rows of `func` are indexed by v, columns by u.
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+impl Distribution2D {
+    pub fn new(func: &[Float], nu: usize, nv: usize) -> Self {
+        let conditional: Vec<Distribution1D> = (0..nv)
+            .map(|v| Distribution1D::new(&func[v * nu..(v + 1) * nu]))
+            .collect();
+        let marginal: Vec<Float> = conditional.iter().map(|c| c.integral).collect();
+        Self {
+            conditional,
+            marginal: Distribution1D::new(&marginal),
+        }
+    }
+    // Returns the sampled (u, v) point and its pdf.
+    pub fn sample_continuous(&self, u0: Float, u1: Float) -> ((Float, Float), Float) {
+        let (v, pdf_v, iv) = self.marginal.sample_continuous(u1);
+        let (u, pdf_u, _) = self.conditional[iv].sample_continuous(u0);
+        ((u, v), pdf_u * pdf_v)
+    }
+    pub fn pdf(&self, u: Float, v: Float) -> Float {
+        let c = &self.conditional[0];
+        let iu = ((u * c.count() as Float) as usize).min(c.count() - 1);
+        let iv = ((v * self.marginal.count() as Float) as usize).min(self.marginal.count() - 1);
+        if self.marginal.integral > 0.0 {
+            self.conditional[iv].func[iu] / self.marginal.integral
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::{Distribution1D, Distribution2D, Float};
+    #[test]
+    fn sample_1d() {
+        let d = Distribution1D::new(&[1.0, 3.0, 0.0, 4.0]);
+        assert_eq!(d.integral, 2.0);
+        let (x, pdf, i) = d.sample_continuous(0.0625);
+        assert_eq!(i, 0);
+        assert!((x - 0.125).abs() < 1e-6);
+        assert!((pdf - 0.5).abs() < 1e-6);
+        assert_eq!(d.sample_discrete(0.3), (1, 0.375));
+        // zero valued segments are never sampled
+        assert_eq!(d.sample_discrete(0.5).0, 3);
+    }
+    #[test]
+    fn sample_2d() {
+        let d = Distribution2D::new(&[0.0, 0.0, 0.0, 1.0], 2, 2);
+        for i in 0..10 {
+            let ((u, v), pdf) = d.sample_continuous(i as Float / 10.0, 1.0 - i as Float / 10.0);
+            assert!(u >= 0.5 && v >= 0.5);
+            assert!((pdf - 4.0).abs() < 1e-5);
+            assert_eq!(d.pdf(u, v), pdf);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use crate::geometry::{Float, Vec3};
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+// Bound on the relative error of n consecutive floating point operations.
+pub fn gamma(n: i32) -> Float {
+    let e = Float::EPSILON * 0.5 * n as Float;
+    e / (1.0 - e)
+}
+
+pub fn next_float_up(v: Float) -> Float {
+    if v.is_infinite() && v > 0.0 {
+        return v;
+    }
+    let v = if v == -0.0 { 0.0 } else { v };
+    let bits = v.to_bits();
+    Float::from_bits(if v >= 0.0 { bits + 1 } else { bits - 1 })
+}
+
+pub fn next_float_down(v: Float) -> Float {
+    if v.is_infinite() && v < 0.0 {
+        return v;
+    }
+    let v = if v == 0.0 { -0.0 } else { v };
+    let bits = v.to_bits();
+    Float::from_bits(if v > 0.0 { bits - 1 } else { bits + 1 })
+}
+
+// A float carried together with an interval that is guaranteed to contain the
+// exact result of the operations that produced it.
+#[derive(Debug, Clone, Copy)]
+pub struct EFloat {
+    pub v: Float,
+    low: Float,
+    high: Float,
+}
+impl EFloat {
+    pub fn new(v: Float, err: Float) -> Self {
+        if err == 0.0 {
+            return Self::from(v);
+        }
+        Self {
+            v,
+            low: next_float_down(v - err),
+            high: next_float_up(v + err),
+        }
+    }
+    pub fn lower_bound(&self) -> Float {
+        self.low
+    }
+    pub fn upper_bound(&self) -> Float {
+        self.high
+    }
+    pub fn absolute_error(&self) -> Float {
+        next_float_up((self.high - self.v).max(self.v - self.low))
+    }
+    pub fn sqrt(self) -> Self {
+        Self {
+            v: self.v.sqrt(),
+            low: next_float_down(self.low.max(0.0).sqrt()),
+            high: next_float_up(self.high.sqrt()),
+        }
+    }
+}
+impl From<Float> for EFloat {
+    fn from(v: Float) -> Self {
+        Self { v, low: v, high: v }
+    }
+}
+impl Add for EFloat {
+    type Output = Self;
+    fn add(self, o: Self) -> Self {
+        Self {
+            v: self.v + o.v,
+            low: next_float_down(self.low + o.low),
+            high: next_float_up(self.high + o.high),
+        }
+    }
+}
+impl Sub for EFloat {
+    type Output = Self;
+    fn sub(self, o: Self) -> Self {
+        Self {
+            v: self.v - o.v,
+            low: next_float_down(self.low - o.high),
+            high: next_float_up(self.high - o.low),
+        }
+    }
+}
+impl Mul for EFloat {
+    type Output = Self;
+    fn mul(self, o: Self) -> Self {
+        let p = [
+            self.low * o.low,
+            self.high * o.low,
+            self.low * o.high,
+            self.high * o.high,
+        ];
+        Self {
+            v: self.v * o.v,
+            low: next_float_down(p.iter().cloned().fold(Float::INFINITY, Float::min)),
+            high: next_float_up(p.iter().cloned().fold(Float::NEG_INFINITY, Float::max)),
+        }
+    }
+}
+impl Div for EFloat {
+    type Output = Self;
+    fn div(self, o: Self) -> Self {
+        if o.low < 0.0 && o.high > 0.0 {
+            // the divisor interval contains zero
+            return Self {
+                v: self.v / o.v,
+                low: Float::NEG_INFINITY,
+                high: Float::INFINITY,
+            };
+        }
+        let q = [
+            self.low / o.low,
+            self.high / o.low,
+            self.low / o.high,
+            self.high / o.high,
+        ];
+        Self {
+            v: self.v / o.v,
+            low: next_float_down(q.iter().cloned().fold(Float::INFINITY, Float::min)),
+            high: next_float_up(q.iter().cloned().fold(Float::NEG_INFINITY, Float::max)),
+        }
+    }
+}
+impl Neg for EFloat {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self {
+            v: -self.v,
+            low: -self.high,
+            high: -self.low,
+        }
+    }
+}
+
+// Roots of a*t^2 + b*t + c, in increasing order.
+pub fn quadratic(a: EFloat, b: EFloat, c: EFloat) -> Option<(EFloat, EFloat)> {
+    if a.v == 0.0 {
+        // linear, e.g. a ray along the axis of a paraboloid
+        if b.v == 0.0 {
+            return None;
+        }
+        let t = -c / b;
+        return Some((t, t));
+    }
+    // the discriminant keeps its own bounds, since b^2 - 4ac cancels badly
+    // for grazing rays
+    let discriminant = b * b - EFloat::from(4.0) * a * c;
+    if discriminant.v < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    // avoids the cancellation of -b + root
+    let q = if b.v < 0.0 {
+        EFloat::from(-0.5) * (b - root)
+    } else {
+        EFloat::from(-0.5) * (b + root)
+    };
+    if q.v == 0.0 {
+        // b and c are 0, a double root at 0 where c / q would be 0 / 0
+        let t = q / a;
+        return Some((t, t));
+    }
+    let (t0, t1) = (q / a, c / q);
+    if t0.v > t1.v {
+        Some((t1, t0))
+    } else {
+        Some((t0, t1))
+    }
+}
+
+// Moves a point, known up to `p_error`, just past the error box along the
+// normal, on the side the direction `w` leaves to.
+pub fn offset_ray_origin(p: &Vec3, p_error: &Vec3, n: &Vec3, w: &Vec3) -> Vec3 {
+    let d = n.x.abs() * p_error.x + n.y.abs() * p_error.y + n.z.abs() * p_error.z;
+    let mut offset = d * *n;
+    if w.x * n.x + w.y * n.y + w.z * n.z < 0.0 {
+        offset = -offset;
+    }
+    let mut po = *p + offset;
+    for i in 0..3 {
+        if offset[i] > 0.0 {
+            po[i] = next_float_up(po[i]);
+        } else if offset[i] < 0.0 {
+            po[i] = next_float_down(po[i]);
+        }
+    }
+    po
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::*;
+    #[test]
+    fn next_float() {
+        assert!(next_float_up(1.0) > 1.0);
+        assert!(next_float_down(1.0) < 1.0);
+        assert!(next_float_up(0.0) > 0.0);
+        assert!(next_float_down(0.0) < 0.0);
+        assert!(next_float_up(-1.0) > -1.0);
+        assert_eq!(next_float_down(next_float_up(2.5)), 2.5);
+    }
+    #[test]
+    // the casts are no-ops in f64 builds
+    #[allow(clippy::unnecessary_cast)]
+    fn bounds_contain_exact_result() {
+        seed_random(5);
+        for _ in 0..1000 {
+            let (x, y, z) = (random() * 1e3, random() - 0.5, random() * 1e-3);
+            let r = (EFloat::from(x) * EFloat::from(y) - EFloat::from(z))
+                / (EFloat::from(x) + EFloat::from(1.0));
+            let exact = (x as f64 * y as f64 - z as f64) / (x as f64 + 1.0);
+            assert!(r.lower_bound() as f64 <= exact && exact <= r.upper_bound() as f64);
+        }
+        let (t0, t1) = quadratic(1.0.into(), (-3.0).into(), 2.0.into()).unwrap();
+        assert!(t0.lower_bound() <= 1.0 && 1.0 <= t0.upper_bound());
+        assert!(t1.lower_bound() <= 2.0 && 2.0 <= t1.upper_bound());
+        assert!(quadratic(1.0.into(), 0.0.into(), 1.0.into()).is_none());
+        let (t0, t1) = quadratic(2.0.into(), 0.0.into(), 0.0.into()).unwrap();
+        assert_eq!((t0.v, t1.v), (0.0, 0.0));
+        assert!(t0.lower_bound() <= 0.0 && 0.0 <= t0.upper_bound());
+    }
+}
@@ -0,0 +1,11 @@
+// Floating point type used throughout the renderer: f32 by default, f64 with
+// the "f64" cargo feature.
+#[cfg(not(feature = "f64"))]
+pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+
+#[cfg(not(feature = "f64"))]
+pub use std::f32::consts;
+#[cfg(feature = "f64")]
+pub use std::f64::consts;
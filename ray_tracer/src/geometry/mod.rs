@@ -1,7 +1,19 @@
+mod float;
 mod vector;
 mod ray;
 mod utils;
+mod distribution;
+mod efloat;
+mod bounds;
+mod transform;
+mod polynomial;
 
+pub use self::float::*;
 pub use self::vector::*;
 pub use self::ray::Ray;
-pub use self::utils::*;
\ No newline at end of file
+pub use self::utils::*;
+pub use self::distribution::*;
+pub use self::efloat::*;
+pub use self::bounds::Aabb;
+pub use self::transform::Transform;
+pub use self::polynomial::*;
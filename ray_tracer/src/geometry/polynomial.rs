@@ -0,0 +1,161 @@
+// Real roots of low degree polynomials, in increasing order. Computed in
+// double precision whatever the precision of the build, and polished with
+// Newton iterations on the original polynomial.
+
+fn eval(c: &[f64], x: f64) -> (f64, f64) {
+    // Horner, returning the value and the derivative
+    let (mut p, mut dp) = (0.0, 0.0);
+    for a in c.iter() {
+        dp = dp * x + p;
+        p = p * x + a;
+    }
+    (p, dp)
+}
+
+fn polish(c: &[f64], mut x: f64) -> f64 {
+    for _ in 0..4 {
+        let (p, dp) = eval(c, x);
+        if dp == 0.0 {
+            break;
+        }
+        let next = x - p / dp;
+        // keeps the original root when newton moves away
+        if eval(c, next).0.abs() > p.abs() {
+            break;
+        }
+        x = next;
+    }
+    x
+}
+
+// a*x^2 + b*x + c
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return if b == 0.0 { vec![] } else { vec![-c / b] };
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return vec![];
+    }
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0.0 {
+        return vec![0.0, 0.0];
+    }
+    let (x0, x1) = (q / a, c / q);
+    if x0 < x1 {
+        vec![x0, x1]
+    } else {
+        vec![x1, x0]
+    }
+}
+
+// x^3 + a*x^2 + b*x + c
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let p = b - a * a / 3.0;
+    let q = 2.0 * a * a * a / 27.0 - a * b / 3.0 + c;
+    let shift = -a / 3.0;
+    let discriminant = q * q / 4.0 + p * p * p / 27.0;
+    let mut roots = if discriminant > 0.0 {
+        let s = discriminant.sqrt();
+        vec![(-q / 2.0 + s).cbrt() + (-q / 2.0 - s).cbrt() + shift]
+    } else if p == 0.0 {
+        vec![shift]
+    } else {
+        // three real roots, trigonometric form
+        let r = 2.0 * (-p / 3.0).sqrt();
+        let phi = (3.0 * q / (p * r)).clamp(-1.0, 1.0).acos() / 3.0;
+        (0..3)
+            .map(|k| r * (phi - 2.0 * std::f64::consts::PI * k as f64 / 3.0).cos() + shift)
+            .collect()
+    };
+    for x in roots.iter_mut() {
+        *x = polish(&[1.0, a, b, c], *x);
+    }
+    // non-finite coefficients give no roots rather than NaNs
+    roots.retain(|x| x.is_finite());
+    roots.sort_by(|x, y| x.total_cmp(y));
+    roots
+}
+
+// c[0]*x^4 + c[1]*x^3 + c[2]*x^2 + c[3]*x + c[4], with Ferrari's method.
+pub fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    if c[0] == 0.0 {
+        return solve_cubic_general(c[1], c[2], c[3], c[4]);
+    }
+    let (a, b, cc, d) = (c[1] / c[0], c[2] / c[0], c[3] / c[0], c[4] / c[0]);
+    // depressed quartic y^4 + p*y^2 + q*y + r, x = y - a/4
+    let p = b - 3.0 * a * a / 8.0;
+    let q = cc - a * b / 2.0 + a * a * a / 8.0;
+    let r = d - a * cc / 4.0 + a * a * b / 16.0 - 3.0 * a * a * a * a / 256.0;
+    let mut ys = vec![];
+    let scale = 1.0 + p.abs() + r.abs().sqrt();
+    if q.abs() <= 1e-12 * scale * scale.sqrt() {
+        // biquadratic
+        for z in solve_quadratic(1.0, p, r) {
+            if z >= 0.0 {
+                ys.push(z.sqrt());
+                ys.push(-z.sqrt());
+            }
+        }
+    } else {
+        // the largest root of the resolvent cubic is positive when q != 0
+        let m = solve_cubic(p, p * p / 4.0 - r, -q * q / 8.0)
+            .last()
+            .copied()
+            .unwrap_or(0.0);
+        if m > 0.0 {
+            let s = (2.0 * m).sqrt();
+            ys.extend(solve_quadratic(1.0, -s, p / 2.0 + m + q / (2.0 * s)));
+            ys.extend(solve_quadratic(1.0, s, p / 2.0 + m - q / (2.0 * s)));
+        }
+    }
+    let mut roots: Vec<f64> = ys.iter().map(|y| polish(&c, y - a / 4.0)).collect();
+    roots.retain(|x| x.is_finite());
+    roots.sort_by(|x, y| x.total_cmp(y));
+    roots
+}
+
+fn solve_cubic_general(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    if a == 0.0 {
+        solve_quadratic(b, c, d)
+    } else {
+        solve_cubic(b / a, c / a, d / a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::*;
+    fn close(roots: &[f64], expected: &[f64]) -> bool {
+        roots.len() == expected.len()
+            && roots
+                .iter()
+                .zip(expected)
+                .all(|(r, e)| (r - e).abs() <= 1e-9 * (1.0 + e.abs()))
+    }
+    #[test]
+    fn cubic_roots() {
+        // (x - 1)(x - 2)(x + 3)
+        assert!(close(&solve_cubic(0.0, -7.0, 6.0), &[-3.0, 1.0, 2.0]));
+        // (x - 2)(x^2 + 1)
+        assert!(close(&solve_cubic(-2.0, 1.0, -2.0), &[2.0]));
+    }
+    #[test]
+    fn quartic_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        let r = solve_quartic([1.0, -10.0, 35.0, -50.0, 24.0]);
+        assert!(close(&r, &[1.0, 2.0, 3.0, 4.0]));
+        // (x^2 - 4)(x^2 + 1), biquadratic
+        assert!(close(
+            &solve_quartic([1.0, 0.0, -3.0, 0.0, -4.0]),
+            &[-2.0, 2.0]
+        ));
+        assert!(solve_quartic([1.0, 0.0, 1.0, 0.0, 1.0]).is_empty());
+        // a double root, as for a ray tangent to a torus: (x - 0.5)^2 (x + 1)(x - 3)
+        let r = solve_quartic([1.0, -3.0, -0.75, 2.5, -0.75]);
+        assert!(r.len() == 4 && (r[0] + 1.0).abs() < 1e-9 && (r[3] - 3.0).abs() < 1e-9);
+        assert!((r[1] - 0.5).abs() < 1e-6 && (r[2] - 0.5).abs() < 1e-6);
+        assert!(solve_quartic([1.0, f64::NAN, 0.0, 0.0, 0.0]).is_empty());
+        assert!(solve_cubic(f64::INFINITY, 0.0, 0.0).is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::geometry::Vec3;
+use crate::geometry::{Float, Vec3};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
@@ -14,7 +14,7 @@ impl Ray {
     }
 }
 impl Ray {
-    pub fn point_at_parameter(&self, t : f32) -> Vec3 {
+    pub fn point_at_parameter(&self, t : Float) -> Vec3 {
         self.o + t * self.d
     }
 }
\ No newline at end of file
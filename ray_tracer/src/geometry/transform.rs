@@ -0,0 +1,193 @@
+use crate::geometry::{gamma, normalize, Aabb, Float, Ray, Vec3};
+use std::ops::Mul;
+
+type Matrix = [[Float; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+fn mul(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0; 4]; 4];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+fn transpose(a: &Matrix) -> Matrix {
+    let mut m = [[0.0; 4]; 4];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = a[j][i];
+        }
+    }
+    m
+}
+
+// Affine transform, kept together with its inverse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    m: Matrix,
+    m_inv: Matrix,
+}
+impl Transform {
+    pub fn identity() -> Self {
+        Self {
+            m: IDENTITY,
+            m_inv: IDENTITY,
+        }
+    }
+    pub fn translate(t: Vec3) -> Self {
+        let mut m = IDENTITY;
+        let mut m_inv = IDENTITY;
+        for i in 0..3 {
+            m[i][3] = t[i];
+            m_inv[i][3] = -t[i];
+        }
+        Self { m, m_inv }
+    }
+    pub fn scale(s: Vec3) -> Self {
+        let mut m = IDENTITY;
+        let mut m_inv = IDENTITY;
+        for i in 0..3 {
+            m[i][i] = s[i];
+            m_inv[i][i] = 1.0 / s[i];
+        }
+        Self { m, m_inv }
+    }
+    // Rotation of `degrees` around `axis`, counterclockwise looking down it.
+    pub fn rotate(degrees: Float, axis: Vec3) -> Self {
+        let a = normalize(axis);
+        let (s, c) = degrees.to_radians().sin_cos();
+        let mut m = IDENTITY;
+        m[0][0] = a.x * a.x + (1.0 - a.x * a.x) * c;
+        m[0][1] = a.x * a.y * (1.0 - c) - a.z * s;
+        m[0][2] = a.x * a.z * (1.0 - c) + a.y * s;
+        m[1][0] = a.x * a.y * (1.0 - c) + a.z * s;
+        m[1][1] = a.y * a.y + (1.0 - a.y * a.y) * c;
+        m[1][2] = a.y * a.z * (1.0 - c) - a.x * s;
+        m[2][0] = a.x * a.z * (1.0 - c) - a.y * s;
+        m[2][1] = a.y * a.z * (1.0 - c) + a.x * s;
+        m[2][2] = a.z * a.z + (1.0 - a.z * a.z) * c;
+        Self {
+            m,
+            m_inv: transpose(&m),
+        }
+    }
+    pub fn inverse(&self) -> Self {
+        Self {
+            m: self.m_inv,
+            m_inv: self.m,
+        }
+    }
+    pub fn is_identity(&self) -> bool {
+        self.m == IDENTITY
+    }
+    pub fn point(&self, p: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+    // Normals transform with the inverse transpose. Not normalized.
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        let m = &self.m_inv;
+        Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+    // Transforms a point known up to `p_error` and returns the bound on the
+    // error of the result, see pbrt 3.9.3.
+    pub fn point_with_error(&self, p: &Vec3, p_error: &Vec3) -> (Vec3, Vec3) {
+        let m = &self.m;
+        let row = |i: usize| {
+            gamma(3)
+                * ((m[i][0] * p.x).abs()
+                    + (m[i][1] * p.y).abs()
+                    + (m[i][2] * p.z).abs()
+                    + m[i][3].abs())
+                + (gamma(3) + 1.0)
+                    * (m[i][0].abs() * p_error.x
+                        + m[i][1].abs() * p_error.y
+                        + m[i][2].abs() * p_error.z)
+        };
+        (self.point(p), Vec3::new(row(0), row(1), row(2)))
+    }
+    pub fn vector_with_error(&self, v: &Vec3) -> (Vec3, Vec3) {
+        let m = &self.m;
+        let row = |i: usize| {
+            gamma(3) * ((m[i][0] * v.x).abs() + (m[i][1] * v.y).abs() + (m[i][2] * v.z).abs())
+        };
+        (self.vector(v), Vec3::new(row(0), row(1), row(2)))
+    }
+    // The transformed ray and the error bounds of its origin and direction.
+    pub fn ray_with_error(&self, r: &Ray) -> (Ray, Vec3, Vec3) {
+        let (o, o_error) = self.point_with_error(&r.o, &Vec3::new(0.0, 0.0, 0.0));
+        let (d, d_error) = self.vector_with_error(&r.d);
+        (Ray::new(o, d), o_error, d_error)
+    }
+    pub fn bounds(&self, b: &Aabb) -> Aabb {
+        let p = self.point(&b.corner(0));
+        (1..8).fold(Aabb::new(p, p), |acc, i| {
+            let p = self.point(&b.corner(i));
+            acc.union(&Aabb::new(p, p))
+        })
+    }
+}
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+// a * b applies b first.
+impl Mul for Transform {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        Self {
+            m: mul(&self.m, &other.m),
+            m_inv: mul(&other.m_inv, &self.m_inv),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::*;
+    #[test]
+    fn compose_and_invert() {
+        let t = Transform::translate(Vec3::new(1.0, 2.0, 3.0))
+            * Transform::rotate(90.0, Vec3::new(0.0, 0.0, 1.0))
+            * Transform::scale(Vec3::new(2.0, 2.0, 2.0));
+        let p = t.point(&Vec3::new(1.0, 0.0, 0.0));
+        assert!((p - Vec3::new(1.0, 4.0, 3.0)).length() < 1e-5);
+        let q = t.inverse().point(&p);
+        assert!((q - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-5);
+        // normals stay perpendicular to transformed tangents
+        let s = Transform::scale(Vec3::new(1.0, 4.0, 1.0));
+        let (tangent, n) = (Vec3::new(1.0, -1.0, 0.0), Vec3::new(1.0, 1.0, 0.0));
+        assert!(dot(&s.vector(&tangent), &s.normal(&n)).abs() < 1e-6);
+        let b = t.bounds(&Aabb::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ));
+        assert!(b.contains(&Vec3::new(0.0, 3.0, 4.0)));
+    }
+}
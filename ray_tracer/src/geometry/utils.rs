@@ -1,33 +1,52 @@
-use crate::geometry::Vec3;
-use rand::Rng;
+use crate::geometry::{consts, Float, Vec3};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::cell::RefCell;
+
+thread_local! {
+    static RNG: RefCell<XorShiftRng> = RefCell::new(XorShiftRng::new_unseeded());
+}
+
+// Uniform sample in [0,1) from the calling thread's generator. Renders are
+// reproducible because the generator is reseeded for every pixel and pass.
+pub fn random() -> Float {
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+pub fn seed_random(seed: u64) {
+    // splitmix64 to spread nearby seeds; xorshift needs a nonzero state
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    let state = [z as u32, (z >> 32) as u32, 0x2545_f491, 0x4f6c_dd1d];
+    RNG.with(|rng| *rng.borrow_mut() = XorShiftRng::from_seed(state));
+}
 
 pub fn random_in_unit_sphere() -> Vec3 {
     let mut p: Vec3;
     loop {
-        p =
-            2.0 * Vec3::new(
-                rand::thread_rng().gen(),
-                rand::thread_rng().gen(),
-                rand::thread_rng().gen(),
-            ) - Vec3::new(1f32, 1f32, 1f32);
-        if p.length2() >= 1f32 {
-            break
+        p = 2.0 * Vec3::new(random(), random(), random()) - Vec3::new(1.0, 1.0, 1.0);
+        if p.length2() < 1.0 {
+            break;
         }
     }
     p
 }
 
+// Uniformly distributed direction.
+pub fn random_unit_vector() -> Vec3 {
+    let z = 1.0 - 2.0 * random();
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * consts::PI * random();
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
 pub fn random_in_unit_disk() -> Vec3 {
-    let mut p : Vec3;
+    let mut p: Vec3;
     loop {
-        p =
-            2.0 * Vec3::new(
-                rand::thread_rng().gen(),
-                rand::thread_rng().gen(),
-                0.0,
-            ) - Vec3::new(1f32, 1f32, 0.0);
-        if p.length2() >= 1f32 {
-            break
+        p = 2.0 * Vec3::new(random(), random(), 0.0) - Vec3::new(1.0, 1.0, 0.0);
+        if p.length2() < 1.0 {
+            break;
         }
     }
     p
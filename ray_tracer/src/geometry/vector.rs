@@ -1,26 +1,26 @@
-use std::f32;
+use crate::geometry::Float;
 use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, Sub, Neg};
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub struct Vec3 {
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
 }
 impl Vec3 {
-    pub fn new(x: f32, y: f32, z: f32) -> Self {
-        Self { x: x, y: y, z: z }
+    pub fn new(x: Float, y: Float, z: Float) -> Self {
+        Self { x, y, z }
     }
-    pub fn length2(&self) -> f32 {
+    pub fn length2(&self) -> Float {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
-    pub fn length(&self) -> f32 {
+    pub fn length(&self) -> Float {
         self.length2().sqrt()
     }
 }
 impl Index<usize> for Vec3 {
-    type Output = f32;
-    fn index<'a>(&'a self, i: usize) -> &'a f32 {
+    type Output = Float;
+    fn index(&self, i: usize) -> &Float {
         match i {
             0 => &self.x,
             1 => &self.y,
@@ -30,7 +30,7 @@ impl Index<usize> for Vec3 {
     }
 }
 impl IndexMut<usize> for Vec3 {
-    fn index_mut<'a>(&'a mut self, i: usize) -> &'a mut f32 {
+    fn index_mut(&mut self, i: usize) -> &mut Float {
         match i {
             0 => &mut self.x,
             1 => &mut self.y,
@@ -59,9 +59,9 @@ impl Sub for Vec3 {
         }
     }
 }
-impl Mul<f32> for Vec3 {
+impl Mul<Float> for Vec3 {
     type Output = Self;
-    fn mul(self, f: f32) -> Self {
+    fn mul(self, f: Float) -> Self {
         Self {
             x: self.x * f,
             y: self.y * f,
@@ -69,7 +69,7 @@ impl Mul<f32> for Vec3 {
         }
     }
 }
-impl Mul<Vec3> for f32 {
+impl Mul<Vec3> for Float {
     type Output = Vec3;
     fn mul(self, v: Vec3) -> Vec3 {
         Vec3 {
@@ -89,10 +89,10 @@ impl Mul<Vec3> for Vec3 {
         }
     }
 }
-impl Div<f32> for Vec3 {
+impl Div<Float> for Vec3 {
     type Output = Self;
-    fn div(self, f: f32) -> Self {
-        if f == 0f32 {
+    fn div(self, f: Float) -> Self {
+        if f == 0.0 {
             panic!("Cannot divide vector component by zero!");
         }
         Self {
@@ -109,9 +109,9 @@ impl AddAssign for Vec3 {
         self.z += other.z;
     }
 }
-impl DivAssign<f32> for Vec3 {
-    fn div_assign(&mut self, f: f32) {
-        if f == 0f32 {
+impl DivAssign<Float> for Vec3 {
+    fn div_assign(&mut self, f: Float) {
+        if f == 0.0 {
             panic!("Can't divide by zero!");
         }
         self.x /= f;
@@ -131,12 +131,12 @@ impl Neg for Vec3 {
 }
 pub fn normalize(a: Vec3) -> Vec3 {
     let l = a.length();
-    if l == 0f32 {
+    if l == 0.0 {
         panic!("Can't normalize zero vector!");
     }
     a / l
 }
-pub fn dot(a: &Vec3, b: &Vec3) -> f32 {
+pub fn dot(a: &Vec3, b: &Vec3) -> Float {
     a.x * b.x + a.y * b.y + a.z * b.z
 }
 pub fn cross(a: &Vec3, b: &Vec3) -> Vec3 {
@@ -147,9 +147,9 @@ pub fn cross(a: &Vec3, b: &Vec3) -> Vec3 {
     }
 }
 pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
-    *v - 2f32 * dot(v, n) * *n
+    *v - 2.0 * dot(v, n) * *n
 }
-pub fn refract(v: &Vec3, n: &Vec3, ni_over_nt: f32, refracted: &mut Vec3) -> bool {
+pub fn refract(v: &Vec3, n: &Vec3, ni_over_nt: Float, refracted: &mut Vec3) -> bool {
     let uv = normalize(*v);
     let dt = dot(&uv, n);
     let discriminant = 1.0 - ni_over_nt * ni_over_nt * (1.0 - dt * dt);
@@ -184,26 +184,26 @@ mod tests {
     }
     #[test]
     fn vec3_idx() {
-        let mut v = Vec3::new(1f32, 2f32, 3f32);
-        assert_eq!(v[0], 1f32);
-        assert_eq!(v[1], 2f32);
-        assert_eq!(v[2], 3f32);
-        v[0] *= -1f32;
-        v[1] *= -1f32;
-        v[2] *= -1f32;
-        assert_eq!(v[0], -1f32);
-        assert_eq!(v[1], -2f32);
-        assert_eq!(v[2], -3f32);
+        let mut v = Vec3::new(1.0, 2.0, 3.0);
+        assert_eq!(v[0], 1.0);
+        assert_eq!(v[1], 2.0);
+        assert_eq!(v[2], 3.0);
+        v[0] *= -1.0;
+        v[1] *= -1.0;
+        v[2] *= -1.0;
+        assert_eq!(v[0], -1.0);
+        assert_eq!(v[1], -2.0);
+        assert_eq!(v[2], -3.0);
     }
     #[test]
     #[should_panic]
     fn vec3_inv_idx() {
-        let v = Vec3::new(1f32, 1f32, 1f32);
+        let v = Vec3::new(1.0, 1.0, 1.0);
         println!("{:?}", v[3]);
     }
     #[test]
     #[should_panic]
     fn vec3_div_0f32() {
-        let _v = Vec3::new(1f32, 1f32, 1f32) / 0f32;
+        let _v = Vec3::new(1.0, 1.0, 1.0) / 0.0;
     }
 }
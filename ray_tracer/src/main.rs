@@ -1,137 +1,345 @@
-mod core;
-mod geometry;
-use rand::Rng;
-use std::f32;
-use std::rc::Rc;
+use ray_tracer::core::stats;
+use ray_tracer::{core, geometry};
+use std::fs;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-fn color(r: &mut geometry::Ray, world: &core::Hitable, depth: i32) -> geometry::Vec3 {
-    let mut rec = core::HitRecord::new();
-    if world.hit(r, 0.001, f32::INFINITY, &mut rec) == true {
-        let mut scattered = geometry::Ray::new(
-            geometry::Vec3::new(0f32, 0f32, 0f32),
-            geometry::Vec3::new(0f32, 0f32, 0f32),
-        );
-        let mut attenuation = geometry::Vec3::new(0f32, 0f32, 0f32);
-        match rec.mat {
-            None => return geometry::Vec3::new(0f32, 0f32, 0f32),
-            Some(ref mat) => {
-                if depth < 50 && mat.scatter(r, &rec, &mut attenuation, &mut scattered) == true {
-                    return attenuation * color(&mut scattered, world, depth + 1);
-                } else {
-                    return geometry::Vec3::new(0f32, 0f32, 0f32);
-                }
+struct Options {
+    scene: Option<String>,
+    spectral: bool,
+    spp: Option<u32>,
+    pass_spp: u32,
+    threads: Option<usize>,
+    seed: u64,
+    output: Option<String>,
+    checkpoint: Option<String>,
+    checkpoint_interval: u64,
+    resume: bool,
+    stats: bool,
+    heatmap: Option<String>,
+    // reference image to compare the render with, and where to write the
+    // difference
+    compare: Option<String>,
+    diff: Option<String>,
+    integrator: Option<String>,
+    // first and last frame of an animation
+    frames: Option<(u64, u64)>,
+    // address to coordinate workers on, or of the coordinator to work for
+    listen: Option<String>,
+    worker: Option<String>,
+    tile_size: usize,
+    worker_timeout: u64,
+    // workers to wait for before handing out tiles
+    workers: usize,
+    // draw the render in the terminal after every pass
+    preview: bool,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options {
+        scene: None,
+        spectral: false,
+        spp: None,
+        pass_spp: 16,
+        threads: None,
+        seed: 0,
+        output: None,
+        checkpoint: None,
+        checkpoint_interval: 60,
+        resume: false,
+        stats: false,
+        heatmap: None,
+        compare: None,
+        diff: None,
+        integrator: None,
+        frames: None,
+        listen: None,
+        worker: None,
+        tile_size: 32,
+        worker_timeout: 600,
+        workers: 1,
+        preview: false,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("missing value for {}", name));
+        let number = |s: String| {
+            s.parse::<u64>()
+                .map_err(|_| format!("invalid number '{}'", s))
+        };
+        let samples = |s: String| {
+            s.parse::<u32>()
+                .map_err(|_| format!("invalid sample count '{}'", s))
+        };
+        match arg.as_str() {
+            "--spectral" => options.spectral = true,
+            "--resume" => options.resume = true,
+            "--stats" => options.stats = true,
+            "--preview" => options.preview = true,
+            "--integrator" => options.integrator = Some(value(&arg)?),
+            "--heatmap" => options.heatmap = Some(value(&arg)?),
+            "--compare" => options.compare = Some(value(&arg)?),
+            "--diff" => options.diff = Some(value(&arg)?),
+            "--spp" => options.spp = Some(samples(value(&arg)?)?),
+            "--pass-spp" => options.pass_spp = samples(value(&arg)?)?.max(1),
+            "--threads" => options.threads = Some(number(value(&arg)?)? as usize),
+            "--seed" => options.seed = number(value(&arg)?)?,
+            "--output" => options.output = Some(value(&arg)?),
+            "--checkpoint" => options.checkpoint = Some(value(&arg)?),
+            "--checkpoint-interval" => options.checkpoint_interval = number(value(&arg)?)?,
+            "--listen" => options.listen = Some(value(&arg)?),
+            "--worker" => options.worker = Some(value(&arg)?),
+            "--tile-size" => options.tile_size = number(value(&arg)?)?.max(1) as usize,
+            "--worker-timeout" => options.worker_timeout = number(value(&arg)?)?,
+            "--workers" => options.workers = number(value(&arg)?)?.max(1) as usize,
+            "--frames" => {
+                let range = value(&arg)?;
+                options.frames = Some(match range.split_once('-') {
+                    Some((first, last)) => (number(first.to_string())?, number(last.to_string())?),
+                    None => (number(range.clone())?, number(range)?),
+                });
+            }
+            a if a.starts_with("--") => return Err(format!("unknown option '{}'", a)),
+            _ => options.scene = Some(arg),
+        }
+    }
+    if options.resume && options.checkpoint.is_none() {
+        return Err("--resume requires --checkpoint".to_string());
+    }
+    if options.diff.is_some() && options.compare.is_none() {
+        return Err("--diff requires --compare".to_string());
+    }
+    if options.frames.is_some() {
+        if !options.output.as_ref().is_some_and(|o| o.contains('#')) {
+            return Err("--frames requires an --output with # for the frame number".to_string());
+        }
+        if options.checkpoint.is_some() || options.heatmap.is_some() || options.compare.is_some() {
+            return Err(
+                "--frames can not be used with --checkpoint, --heatmap or --compare".to_string(),
+            );
+        }
+    }
+    if options.preview && (options.frames.is_some() || options.listen.is_some()) {
+        return Err("--preview can not be used with --frames or --listen".to_string());
+    }
+    if options.listen.is_some() {
+        if options.scene.is_none() {
+            return Err("--listen requires a scene file".to_string());
+        }
+        if options.frames.is_some()
+            || options.checkpoint.is_some()
+            || options.heatmap.is_some()
+            || options.stats
+        {
+            return Err(
+                "--listen can not be used with --frames, --checkpoint, --heatmap or --stats"
+                    .to_string(),
+            );
+        }
+    }
+    Ok(options)
+}
+
+fn run(options: Options) -> Result<(), String> {
+    if let Some(ref addr) = options.worker {
+        return core::work(addr, options.threads);
+    }
+    match options.frames {
+        Some((first, last)) => {
+            let pattern = options.output.clone().unwrap_or_default();
+            for frame in first..=last {
+                let output = frame_path(&pattern, frame);
+                render(&options, frame, Some(&output))?;
+                eprintln!("frame {} written to {}", frame, output);
             }
+            Ok(())
         }
+        None => render(&options, 0, options.output.as_deref()),
     }
-    let unit_direction = geometry::normalize(r.d);
-    let t = 0.5 * (unit_direction.y + 1.0);
-    (1.0 - t) * geometry::Vec3::new(1f32, 1f32, 1f32) + t * geometry::Vec3::new(0.5, 0.7, 1.0)
 }
 
-fn rand() -> f32 {
-    rand::thread_rng().gen()
+// Replaces the first run of # in pattern with the zero padded frame number.
+fn frame_path(pattern: &str, frame: u64) -> String {
+    let start = pattern.find('#').unwrap_or(pattern.len());
+    let width = pattern[start..].chars().take_while(|&c| c == '#').count();
+    format!(
+        "{}{:0width$}{}",
+        &pattern[..start],
+        frame,
+        &pattern[start + width..],
+        width = width
+    )
 }
 
-fn random_scene(world: &mut core::HitList) {
-    world.list.push(Box::new(core::SphereObject {
-        center: geometry::Vec3::new(0.0, -1000.0, 0.0),
-        radius: 1000.0,
-        mat: Some(Rc::new(core::materials::Lambertian::new(
-            geometry::Vec3::new(0.5, 0.5, 0.5),
-        ))),
-    }));
-    world.list.push(Box::new(core::SphereObject {
-        center: geometry::Vec3::new(0.0, 1.0, 0.0),
-        radius: 1.0,
-        mat: Some(Rc::new(core::materials::Dielectric::new(1.5))),
-    }));
-    world.list.push(Box::new(core::SphereObject {
-        center: geometry::Vec3::new(-4.0, 1.0, 0.0),
-        radius: 1.0,
-        mat: Some(Rc::new(core::materials::Lambertian::new(
-            geometry::Vec3::new(0.5, 0.5, 0.5),
-        ))),
-    }));
-    world.list.push(Box::new(core::SphereObject {
-        center: geometry::Vec3::new(4.0, 1.0, 0.0),
-        radius: 1.0,
-        mat: Some(Rc::new(core::materials::Metal::new(
-            geometry::Vec3::new(0.7, 0.6, 0.5),
-            0.0,
-        ))),
-    }));
-    for a in -11..12 {
-        for b in -11..12 {
-            let choose_mat: f32 = rand::thread_rng().gen();
-            let center = geometry::Vec3::new(a as f32 + 0.9 * rand(), 0.2, b as f32 + 0.9 * rand());
-            if (center - geometry::Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                if choose_mat < 0.8 {
-                    world.list.push(Box::new(core::SphereObject {
-                        center,
-                        radius: 0.2,
-                        mat: Some(Rc::new(core::materials::Lambertian::new(
-                            geometry::Vec3::new(rand() * rand(), rand() * rand(), rand() * rand()),
-                        ))),
-                    }));
-                } else if choose_mat < 0.95 {
-                    world.list.push(Box::new(core::SphereObject {
-                        center,
-                        radius: 0.2,
-                        mat: Some(Rc::new(core::materials::Metal::new(
-                            geometry::Vec3::new(
-                                0.5 * (1.0 + rand()),
-                                0.5 * (1.0 + rand()),
-                                0.5 * (1.0 + rand()),
-                            ),
-                            0.5 * rand(),
-                        ))),
-                    }));
-                } else {
-                    world.list.push(Box::new(core::SphereObject {
-                        center,
-                        radius: 0.2,
-                        mat: Some(Rc::new(core::materials::Dielectric::new(1.5))),
-                    }));
-                }
+fn render(options: &Options, frame: u64, output: Option<&str>) -> Result<(), String> {
+    let seed = core::frame_seed(options.seed, frame);
+    let mut scene = match options.scene {
+        Some(ref path) => core::Scene::load_frame(path, frame as geometry::Float)?,
+        None => core::Scene::default(),
+    };
+    if let Some(ref spec) = options.integrator {
+        scene.integrator = core::parse_integrator_spec(spec)?;
+    }
+    geometry::seed_random(seed);
+    scene.integrator.preprocess(&scene);
+    let spp = options.spp.unwrap_or(scene.spp as u32);
+    // the scene file and integrator, which the film of a checkpoint depends on
+    let source = match options.scene {
+        Some(ref path) => fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?,
+        None => String::new(),
+    };
+    let scene_hash = core::scene_hash(&format!(
+        "{}\n{}",
+        source,
+        options.integrator.as_deref().unwrap_or("")
+    ));
+    let mut renderer = match options.checkpoint {
+        Some(ref path) if options.resume => {
+            let r = core::Renderer::load_checkpoint(path)?;
+            if (r.film.width, r.film.height) != (scene.width, scene.height) {
+                return Err(format!(
+                    "{}: checkpoint does not match the image size",
+                    path
+                ));
+            }
+            if r.scene_hash != scene_hash {
+                return Err(format!(
+                    "{}: checkpoint was rendered from another scene or integrator",
+                    path
+                ));
             }
+            if r.spectral != options.spectral {
+                return Err(format!(
+                    "{}: checkpoint was rendered {} --spectral",
+                    path,
+                    if r.spectral { "with" } else { "without" }
+                ));
+            }
+            r
+        }
+        _ => {
+            let mut r = core::Renderer::new(scene.width, scene.height, seed);
+            r.scene_hash = scene_hash;
+            r.spectral = options.spectral;
+            r
+        }
+    };
+    if let Some(threads) = options.threads {
+        renderer.threads = threads;
+    }
+    let radiance = |r: &mut geometry::Ray| core::radiance(&scene, options.spectral, r);
+    if let Some(ref addr) = options.listen {
+        // the scene file is checked to be given with --listen
+        let job = core::RenderJob {
+            scene: source.clone(),
+            frame,
+            integrator: options.integrator.clone(),
+            spectral: options.spectral,
+            seed,
+            spp,
+        };
+        renderer.film = distribute(options, addr, &job, scene.width, scene.height)?;
+        renderer.passes = 1;
+        renderer.spp = spp;
+    }
+    let mut preview = if options.preview {
+        Some(core::Preview::new(renderer.spp)?)
+    } else {
+        None
+    };
+    if let Some(ref mut preview) = preview {
+        preview.draw(&renderer.film.image(), renderer.spp, spp)?;
+        renderer.stop = Some(preview.quit_flag());
+    }
+    let mut last_checkpoint = Instant::now();
+    while renderer.spp < spp {
+        renderer.render_pass(
+            &*scene.camera,
+            options.pass_spp.min(spp - renderer.spp),
+            &radiance,
+        );
+        // stopped from the preview, keeping the passes that were finished
+        let quit = preview.as_ref().is_some_and(|p| p.quit_requested());
+        match preview {
+            Some(ref mut preview) => preview.draw(&renderer.film.image(), renderer.spp, spp)?,
+            None => eprintln!("pass {}: {}/{} spp", renderer.passes, renderer.spp, spp),
+        }
+        if let Some(path) = output {
+            renderer.film.image().write_ppm(path)?;
+        }
+        if let Some(ref path) = options.checkpoint {
+            let interval = Duration::from_secs(options.checkpoint_interval);
+            if renderer.spp >= spp || quit || last_checkpoint.elapsed() >= interval {
+                renderer.save_checkpoint(path)?;
+                last_checkpoint = Instant::now();
+            }
+        }
+        if quit {
+            break;
+        }
+    }
+    // restores the terminal
+    drop(preview);
+    if options.stats {
+        eprint!("{}", stats::Report(&renderer.stats));
+    }
+    if let Some(ref path) = options.heatmap {
+        renderer.heatmap().write_ppm(path)?;
+    }
+    if let Some(ref path) = options.compare {
+        // as it would be stored, clamped and quantized
+        let image = core::Image::parse_pnm(renderer.film.image().to_ppm().as_bytes())?;
+        let reference = core::Image::read_pnm(path)?;
+        let metrics = core::compare(&image, &reference).map_err(|e| format!("{}: {}", path, e))?;
+        eprintln!("{}", metrics);
+        if let Some(ref diff) = options.diff {
+            core::difference(&image, &reference).write_ppm(diff)?;
+        }
+    }
+    match output {
+        Some(path) => renderer.film.image().write_ppm(path),
+        None => {
+            print!("{}", renderer.film.image().to_ppm());
+            Ok(())
         }
     }
 }
 
-fn main() {
-    let nx = 800;
-    let ny = 400;
-    let ns = 100;
-    let mut world = core::HitList::new();
-    random_scene(&mut world);
-    let lookfrom = geometry::Vec3::new(12.0, 1.2, 4.0);
-    let lookat = geometry::Vec3::new(2.0, 1.0, 0.0);
-    let dist_to_focus = (lookfrom - lookat).length();
-    let aperture: f32 = 0.03;
-    let cam = core::Camera::new(
-        lookfrom,
-        lookat,
-        geometry::Vec3::new(0.0, 1.0, 0.0),
-        30.0,
-        nx as f32 / ny as f32,
-        aperture,
-        dist_to_focus,
-    );
-    println!("P3\n{} {}\n255", nx, ny);
-    for j in (0..ny).rev() {
-        for i in 0..nx {
-            let mut col = geometry::Vec3::new(0f32, 0f32, 0f32);
-            for _ in 0..ns {
-                let u: f32 = i as f32 / nx as f32;
-                let v: f32 = j as f32 / ny as f32;
-                let mut r = cam.ray(u, v);
-                col += color(&mut r, &world, 0);
-            }
-            col /= ns as f32;
-            let ir = (255.99 * col.x) as i32;
-            let ig = (255.99 * col.y) as i32;
-            let ib = (255.99 * col.z) as i32;
-            println!("{} {} {}", ir, ig, ib);
+// Renders the job on the workers that connect to addr.
+fn distribute(
+    options: &Options,
+    addr: &str,
+    job: &core::RenderJob,
+    width: usize,
+    height: usize,
+) -> Result<core::Film, String> {
+    let listener = TcpListener::bind(addr).map_err(|e| format!("{}: {}", addr, e))?;
+    let coordinator = core::Coordinator::new(listener)
+        .with_tile_size(options.tile_size)
+        .with_timeout(Duration::from_secs(options.worker_timeout))
+        .with_workers(options.workers);
+    eprintln!("waiting for workers on {}", coordinator.local_addr()?);
+    let tiles = core::Tile::split(width, height, options.tile_size).len();
+    let rendered = AtomicUsize::new(0);
+    coordinator.render(job, width, height, |progress| match progress {
+        core::Progress::Connected(worker) => eprintln!("worker {} connected", worker),
+        core::Progress::Rendered(worker, _) => {
+            let n = rendered.fetch_add(1, Ordering::Relaxed) + 1;
+            eprintln!("tile {}/{} rendered by {}", n, tiles, worker);
+        }
+        core::Progress::Failed(worker, e) => {
+            eprintln!(
+                "worker {} failed: {}, handing its tile out again",
+                worker, e
+            )
         }
+    })
+}
+
+fn main() {
+    if let Err(e) = parse_options().and_then(run) {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
 }
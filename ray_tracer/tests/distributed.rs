@@ -0,0 +1,73 @@
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::{env, fs};
+
+// A coordinator and worker processes on localhost render the same image as a
+// single process rendering all samples in one pass.
+#[test]
+fn workers_on_localhost() {
+    let exe = env!("CARGO_BIN_EXE_ray_tracer");
+    let dir = env::temp_dir().join(format!("ray_tracer_distributed_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let scene = dir.join("scene.txt");
+    fs::write(
+        &scene,
+        "image width=40 height=24\n\
+         camera look_from=0,1,5 look_at=0,0.5,0 fov=40\n\
+         material ground albedo=0.5,0.5,0.5\n\
+         material glass type=dielectric ior=1.5\n\
+         sphere center=0,-1000,0 radius=1000 material=ground\n\
+         sphere center=0,0.5,0 radius=0.5 material=glass\n\
+         light point position=-1.5,1.5,1 intensity=4,3,2\n",
+    )
+    .unwrap();
+    let (distributed, local) = (dir.join("distributed.ppm"), dir.join("local.ppm"));
+    let mut coordinator = Command::new(exe)
+        .arg(&scene)
+        .args(["--listen", "127.0.0.1:0", "--tile-size", "8", "--spp", "8"])
+        .args(["--workers", "3"])
+        .arg("--output")
+        .arg(&distributed)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(coordinator.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let addr = line.trim().rsplit(' ').next().unwrap().to_string();
+    let workers: Vec<_> = (0..3)
+        .map(|_| {
+            Command::new(exe)
+                .args(["--worker", &addr, "--threads", "1"])
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    // drained so that the coordinator never blocks on a full pipe
+    let log: Vec<String> = stderr.lines().map(|l| l.unwrap()).collect();
+    assert!(coordinator.wait().unwrap().success(), "{:?}", log);
+    for mut worker in workers {
+        assert!(worker.wait().unwrap().success());
+    }
+    assert!(log.iter().any(|l| l.starts_with("tile 15/15")), "{:?}", log);
+    // the tiles are shared between the workers
+    let mut rendered_by: Vec<_> = log
+        .iter()
+        .filter(|l| l.starts_with("tile "))
+        .filter_map(|l| l.rsplit(' ').next())
+        .collect();
+    rendered_by.sort_unstable();
+    rendered_by.dedup();
+    assert!(rendered_by.len() >= 2, "{:?}", log);
+    let status = Command::new(exe)
+        .arg(&scene)
+        .args(["--spp", "8", "--pass-spp", "8"])
+        .arg("--output")
+        .arg(&local)
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(fs::read(&distributed).unwrap(), fs::read(&local).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}